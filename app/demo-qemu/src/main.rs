@@ -0,0 +1,24 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+#![no_std]
+#![no_main]
+
+#[cfg(not(feature = "panic-semihosting"))]
+compile_error!("Must have feature panic-semihosting enabled");
+
+// There's no debug probe to collect ITM output under emulation, so we always
+// panic (and log) via semihosting, which QEMU services directly.
+#[cfg(feature = "panic-semihosting")]
+extern crate panic_semihosting;
+
+use cortex_m_rt::entry;
+
+#[entry]
+fn main() -> ! {
+    // The MPS2 FPGA images run their system clock at 25MHz.
+    const CYCLES_PER_MS: u32 = 25_000;
+
+    unsafe { kern::startup::start_kernel(CYCLES_PER_MS) }
+}
@@ -50,6 +50,45 @@ struct I2cController {
     ports: BTreeMap<String, I2cPort>,
     #[serde(default)]
     target: bool,
+    #[serde(default)]
+    emulate: Vec<I2cEmulatedDevice>,
+}
+
+//
+// A device emulated by a controller that is operating as a target.  As with
+// [`I2cDevice`], what should be an enum is flattened:  an emulated device is
+// either an EEPROM-like device (with [`eeprom`] denoting its size in bytes)
+// or a register-mapped device (with [`registers`] denoting its SMBus
+// commands).
+//
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct I2cEmulatedDevice {
+    /// I2C address
+    address: u8,
+
+    /// description of device
+    description: String,
+
+    /// size of EEPROM-like device, if any
+    eeprom: Option<usize>,
+
+    /// registers of register-mapped device, if any
+    registers: Option<Vec<I2cEmulatedRegister>>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct I2cEmulatedRegister {
+    /// SMBus command code
+    code: u8,
+
+    /// size of register (or maximum size of block) in bytes
+    size: usize,
+
+    /// register is an SMBus block
+    #[serde(default)]
+    block: bool,
 }
 
 //
@@ -102,6 +141,12 @@ struct I2cDevice {
     /// sensor information, if any
     sensors: Option<I2cSensors>,
 
+    /// thermal information, if any
+    thermal: Option<I2cThermal>,
+
+    /// power monitoring information, if any
+    power: Option<I2cPower>,
+
     /// device is removable
     #[serde(default)]
     removable: bool,
@@ -132,6 +177,7 @@ struct I2cMux {
     driver: String,
     address: u8,
     enable: Option<I2cPinSet>,
+    reset: Option<I2cPinSet>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -159,6 +205,130 @@ struct I2cSensors {
 
     #[serde(default)]
     speed: usize,
+
+    #[serde(default)]
+    energy: usize,
+
+    /// alarm thresholds, if any, by sensor kind
+    thresholds: Option<I2cSensorThresholds>,
+}
+
+//
+// A device with thermal information is a temperature input to the thermal
+// loop: it is either a zone's temperature, or (if `cpu` is set) a CPU that
+// the zone cools.
+//
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct I2cThermal {
+    zone: String,
+    #[serde(default)]
+    cpu: bool,
+}
+
+//
+// A device with power information has its rails monitored by the power task,
+// which considers each of them to be on in (and above) the given power
+// state.  Hot swap controllers must also specify their sense resistor, e.g.:
+//
+//   power = { state = "A2", rsense = 1 }
+//
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct I2cPower {
+    state: String,
+
+    /// sense resistor, in milliohms
+    rsense: Option<u32>,
+}
+
+//
+// Thresholds apply to every sensor of the given kind on a device, e.g.:
+//
+//   sensors = { temperature = 1, thresholds = { temperature = {
+//       warning_high = 70, critical_high = 80, hysteresis = 2 } } }
+//
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct I2cSensorThresholds {
+    temperature: Option<I2cThresholds>,
+    power: Option<I2cThresholds>,
+    current: Option<I2cThresholds>,
+    voltage: Option<I2cThresholds>,
+    speed: Option<I2cThresholds>,
+    energy: Option<I2cThresholds>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct I2cThresholds {
+    warning_low: Option<f32>,
+    warning_high: Option<f32>,
+    critical_low: Option<f32>,
+    critical_high: Option<f32>,
+
+    /// distance a value must move back past a threshold to clear an alarm
+    #[serde(default)]
+    hysteresis: f32,
+}
+
+impl I2cSensorThresholds {
+    fn get(&self, kind: Sensor) -> Option<&I2cThresholds> {
+        match kind {
+            Sensor::Temperature => self.temperature.as_ref(),
+            Sensor::Power => self.power.as_ref(),
+            Sensor::Current => self.current.as_ref(),
+            Sensor::Voltage => self.voltage.as_ref(),
+            Sensor::Speed => self.speed.as_ref(),
+            Sensor::Energy => self.energy.as_ref(),
+        }
+    }
+}
+
+impl I2cThresholds {
+    fn validate(&self, device: &str) {
+        if !(self.hysteresis >= 0.0) {
+            panic!("device {} has invalid hysteresis", device);
+        }
+
+        if let (Some(warning), Some(critical)) =
+            (self.warning_high, self.critical_high)
+        {
+            if warning > critical {
+                panic!("device {} warns above its critical limit", device);
+            }
+        }
+
+        if let (Some(warning), Some(critical)) =
+            (self.warning_low, self.critical_low)
+        {
+            if warning < critical {
+                panic!("device {} warns below its critical limit", device);
+            }
+        }
+    }
+
+    fn generate(&self) -> String {
+        let limit = |l: Option<f32>| match l {
+            Some(l) => format!("Some({:?})", l),
+            None => "None".to_string(),
+        };
+
+        format!(
+            r##"Thresholds {{
+                warning_low: {},
+                warning_high: {},
+                critical_low: {},
+                critical_high: {},
+                hysteresis: {:?},
+            }}"##,
+            limit(self.warning_low),
+            limit(self.warning_high),
+            limit(self.critical_low),
+            limit(self.critical_high),
+            self.hysteresis,
+        )
+    }
 }
 
 #[derive(Copy, Clone, PartialEq)]
@@ -166,14 +336,20 @@ pub enum Disposition {
     /// controller is an initiator
     Initiator,
 
-    /// controller is a target
+    /// controller is a target (not emulating devices)
     Target,
 
+    /// controller is a target, emulating devices
+    Emulator,
+
     /// devices are used (i.e., controller is not used), but not as sensors
     Devices,
 
     /// devices are used, with some used as sensors
     Sensors,
+
+    /// devices are used as sensors, with some monitored as power rails
+    Power,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
@@ -183,6 +359,7 @@ enum Sensor {
     Current,
     Voltage,
     Speed,
+    Energy,
 }
 
 impl std::fmt::Display for Sensor {
@@ -196,11 +373,33 @@ impl std::fmt::Display for Sensor {
                 Sensor::Current => "CURRENT",
                 Sensor::Voltage => "VOLTAGE",
                 Sensor::Speed => "SPEED",
+                Sensor::Energy => "ENERGY",
             }
         )
     }
 }
 
+//
+// The driver crate for the controllers that we're generating configuration
+// for.  This is determined by our features (as set by the server that is
+// consuming the configuration); absent the `lpc55` feature, we generate for
+// the STM32H7.
+//
+#[derive(Copy, Clone, PartialEq)]
+enum Driver {
+    Stm32h7,
+    Lpc55,
+}
+
+impl Driver {
+    fn krate(&self) -> &'static str {
+        match self {
+            Driver::Stm32h7 => "drv_stm32h7_i2c",
+            Driver::Lpc55 => "drv_lpc55_i2c",
+        }
+    }
+}
+
 struct ConfigGenerator {
     /// output that we're building
     output: String,
@@ -208,6 +407,9 @@ struct ConfigGenerator {
     /// disposition of this configuration: target v. initiator v. devices
     disposition: Disposition,
 
+    /// driver crate for our controllers
+    driver: Driver,
+
     /// all controllers
     controllers: Vec<I2cController>,
 
@@ -237,6 +439,7 @@ impl ConfigGenerator {
         let mut buses = HashMap::new();
         let mut ports = IndexMap::new();
         let mut singletons = HashMap::new();
+        let mut emulating = None;
 
         for c in i2c.controllers {
             //
@@ -261,11 +464,41 @@ impl ConfigGenerator {
                 ports.insert((c.controller, p.clone()), index);
             }
 
-            if c.target != (disposition == Disposition::Target) {
-                continue;
+            if !c.target && !c.emulate.is_empty() {
+                panic!(
+                    "I2C{} emulates devices but is not a target",
+                    c.controller
+                );
+            }
+
+            let ours = match disposition {
+                Disposition::Target => c.target && c.emulate.is_empty(),
+                Disposition::Emulator => c.target && !c.emulate.is_empty(),
+                _ => !c.target,
+            };
+
+            if c.target && !c.emulate.is_empty() {
+                emulating = Some(c.controller);
             }
 
-            controllers.push(c);
+            if ours {
+                controllers.push(c);
+            }
+        }
+
+        //
+        // A controller that emulates devices belongs to the target server;
+        // if it's the only target, make clear that it can't also be used by
+        // a task expecting a plain target (e.g., the SPD proxy).
+        //
+        if let (Disposition::Target, true, Some(c)) =
+            (disposition, controllers.is_empty(), emulating)
+        {
+            panic!(
+                "I2C{} emulates devices, so can't also be used as a target \
+                by this task",
+                c
+            );
         }
 
         if let Some(devices) = &i2c.devices {
@@ -300,6 +533,11 @@ impl ConfigGenerator {
         Self {
             output: String::new(),
             disposition: disposition,
+            driver: if cfg!(feature = "lpc55") {
+                Driver::Lpc55
+            } else {
+                Driver::Stm32h7
+            },
             controllers: controllers,
             buses: buses,
             ports: ports,
@@ -323,29 +561,42 @@ impl ConfigGenerator {
     }
 
     pub fn generate_controllers(&mut self) -> Result<()> {
-        let mut s = &mut self.output;
-
         match self.disposition {
-            Disposition::Devices | Disposition::Sensors => {
+            Disposition::Devices
+            | Disposition::Sensors
+            | Disposition::Power => {
                 panic!("illegal disposition for controller generation");
             }
 
-            Disposition::Initiator | Disposition::Target => {}
+            Disposition::Target | Disposition::Emulator
+                if self.driver == Driver::Lpc55 =>
+            {
+                panic!("LPC55 I2C controllers cannot be configured as targets");
+            }
+
+            Disposition::Initiator
+            | Disposition::Target
+            | Disposition::Emulator => {}
         }
 
+        let driver = self.driver;
+        let mut s = &mut self.output;
+
         writeln!(
             &mut s,
             r##"
-    use drv_stm32h7_i2c::I2cController;
+    use {}::I2cController;
 
     pub fn controllers() -> [I2cController<'static>; {}] {{"##,
+            driver.krate(),
             self.controllers.len()
         )?;
 
         if self.controllers.len() > 0 {
-            writeln!(
-                &mut s,
-                r##"
+            match driver {
+                Driver::Stm32h7 => writeln!(
+                    &mut s,
+                    r##"
         use drv_stm32xx_sys_api::Peripheral;
         use drv_i2c_api::Controller;
 
@@ -357,7 +608,15 @@ impl ConfigGenerator {
 
         #[cfg(feature = "h7b3")]
         use stm32h7::stm32h7b3 as device;"##
-            )?;
+                )?,
+                Driver::Lpc55 => writeln!(
+                    &mut s,
+                    r##"
+        use drv_lpc55_syscon_api::Peripheral;
+        use drv_i2c_api::Controller;
+        use lpc55_pac as device;"##
+                )?,
+            }
         }
 
         write!(
@@ -367,17 +626,30 @@ impl ConfigGenerator {
         )?;
 
         for c in &self.controllers {
-            write!(
-                &mut s,
-                r##"
+            match driver {
+                Driver::Stm32h7 => write!(
+                    &mut s,
+                    r##"
             I2cController {{
                 controller: Controller::I2C{controller},
                 peripheral: Peripheral::I2c{controller},
                 notification: (1 << ({controller} - 1)),
                 registers: unsafe {{ &*device::I2C{controller}::ptr() }},
             }},"##,
-                controller = c.controller
-            )?;
+                    controller = c.controller
+                )?,
+                Driver::Lpc55 => write!(
+                    &mut s,
+                    r##"
+            I2cController {{
+                controller: Controller::I2C{controller},
+                peripheral: Peripheral::Fc{controller},
+                flexcomm: unsafe {{ &*device::FLEXCOMM{controller}::ptr() }},
+                registers: unsafe {{ &*device::I2C{controller}::ptr() }},
+            }},"##,
+                    controller = c.controller
+                )?,
+            }
         }
 
         writeln!(
@@ -390,16 +662,90 @@ impl ConfigGenerator {
         Ok(())
     }
 
+    ///
+    /// Generates the fields of an `I2cPin` for the specified pins, each
+    /// indented by `indent` spaces.
+    ///
+    fn pin_fields(
+        &self,
+        c: &I2cController,
+        port: usize,
+        gpio_port: &str,
+        pin: &I2cPinSet,
+        indent: usize,
+    ) -> Result<String> {
+        let (pins, function) = match self.driver {
+            Driver::Stm32h7 => {
+                let mut pins = format!(
+                    "gpio_api::Port::{}.pin({})",
+                    gpio_port, pin.pins[0]
+                );
+
+                for p in &pin.pins[1..] {
+                    write!(&mut pins, ".and_pin({})", p)?;
+                }
+
+                (pins, format!("Alternate::AF{}", pin.af))
+            }
+
+            Driver::Lpc55 => {
+                if gpio_port != "0" && gpio_port != "1" {
+                    bail!(
+                        "I2C{} has pins on GPIO port \"{}\"; \
+                        LPC55 GPIO ports are 0 and 1",
+                        c.controller,
+                        gpio_port
+                    );
+                }
+
+                let pins = pin
+                    .pins
+                    .iter()
+                    .map(|p| format!("Pin::PIO{}_{}", gpio_port, p))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+
+                (format!("&[{}]", pins), format!("AltFn::Alt{}", pin.af))
+            }
+        };
+
+        Ok(format!(
+            r##"{indent}controller: Controller::I2C{controller},
+{indent}port: PortIndex({port}),
+{indent}gpio_pins: {pins},
+{indent}function: {function},"##,
+            indent = " ".repeat(indent),
+            controller = c.controller,
+            port = port,
+            pins = pins,
+            function = function,
+        ))
+    }
+
+    /// Returns the imports needed by the pins generated by [`pin_fields`].
+    fn pin_imports(&self) -> &'static str {
+        match self.driver {
+            Driver::Stm32h7 => {
+                "use drv_stm32xx_sys_api::{self as gpio_api, Alternate};"
+            }
+            Driver::Lpc55 => "use drv_lpc55_gpio_api::{AltFn, Pin};",
+        }
+    }
+
     pub fn generate_pins(&mut self) -> Result<()> {
-        let mut s = &mut self.output;
+        let mut s = String::new();
         let mut len = 0;
 
         match self.disposition {
-            Disposition::Devices | Disposition::Sensors => {
+            Disposition::Devices
+            | Disposition::Sensors
+            | Disposition::Power => {
                 panic!("illegal disposition for pin generation");
             }
 
-            Disposition::Initiator | Disposition::Target => {}
+            Disposition::Initiator
+            | Disposition::Target
+            | Disposition::Emulator => {}
         }
 
         for c in &self.controllers {
@@ -411,9 +757,10 @@ impl ConfigGenerator {
         writeln!(
             &mut s,
             r##"
-    use drv_stm32h7_i2c::I2cPin;
+    use {}::I2cPin;
 
     pub fn pins() -> [I2cPin; {}] {{"##,
+            self.driver.krate(),
             len
         )?;
 
@@ -422,7 +769,8 @@ impl ConfigGenerator {
                 &mut s,
                 r##"
         use drv_i2c_api::{{Controller, PortIndex}};
-        use drv_stm32xx_sys_api::{{self as gpio_api, Alternate}};"##
+        {}"##,
+                self.pin_imports()
             )?;
         }
 
@@ -435,30 +783,18 @@ impl ConfigGenerator {
         for c in &self.controllers {
             for (index, (p, port)) in c.ports.iter().enumerate() {
                 for pin in &port.pins {
-                    let mut pinstr = String::new();
-                    write!(&mut pinstr, "pin({})", pin.pins[0])?;
-
-                    for i in 1..pin.pins.len() {
-                        write!(&mut pinstr, ".and_pin({})", pin.pins[i])?;
-                    }
+                    let gpio_port = match pin.gpio_port {
+                        Some(ref port) => port,
+                        None => p,
+                    };
 
                     write!(
                         &mut s,
                         r##"
             I2cPin {{
-                controller: Controller::I2C{controller},
-                port: PortIndex({i2c_port}),
-                gpio_pins: gpio_api::Port::{gpio_port}.{pinstr},
-                function: Alternate::AF{af},
+{}
             }},"##,
-                        controller = c.controller,
-                        i2c_port = index,
-                        gpio_port = match pin.gpio_port {
-                            Some(ref port) => port,
-                            None => p,
-                        },
-                        pinstr = pinstr,
-                        af = pin.af
+                        self.pin_fields(c, index, gpio_port, pin, 16)?
                     )?;
                 }
             }
@@ -471,15 +807,17 @@ impl ConfigGenerator {
     }}"##
         )?;
 
+        self.output.push_str(&s);
+
         Ok(())
     }
 
     pub fn generate_muxes(&mut self) -> Result<()> {
-        if self.disposition == Disposition::Target {
-            panic!("cannot generate muxes when configured as target");
+        if self.disposition != Disposition::Initiator {
+            panic!("can only generate muxes when configured as initiator");
         }
 
-        let mut s = &mut self.output;
+        let mut s = String::new();
         let mut len = 0;
 
         for c in &self.controllers {
@@ -491,9 +829,10 @@ impl ConfigGenerator {
         write!(
             &mut s,
             r##"
-    use drv_stm32h7_i2c::I2cMux;
+    use {}::I2cMux;
 
     pub fn muxes() -> [I2cMux<'static>; {}] {{"##,
+            self.driver.krate(),
             len
         )?;
 
@@ -504,7 +843,8 @@ impl ConfigGenerator {
         use drv_i2c_api::{{Controller, PortIndex, Mux}};
 
         #[allow(unused_imports)]
-        use drv_stm32xx_sys_api::{{self as gpio_api, Alternate}};"##
+        {}"##,
+                self.pin_imports()
             )?;
         }
 
@@ -517,41 +857,64 @@ impl ConfigGenerator {
         for c in &self.controllers {
             for (index, (p, port)) in c.ports.iter().enumerate() {
                 for (mindex, mux) in port.muxes.iter().enumerate() {
-                    let enablestr = if let Some(enable) = &mux.enable {
-                        let mut enablestr = String::new();
-                        write!(
-                            &mut enablestr,
+                    let pinstr = |pin: &Option<I2cPinSet>, what| {
+                        let pin = match pin {
+                            Some(pin) => pin,
+                            None => return Ok("None".to_string()),
+                        };
+
+                        let gpio_port = match pin.gpio_port {
+                            Some(ref port) => port,
+                            None => bail!(
+                                "missing pin port on mux {} \
+                                on I2C{}, port {}, mux {}",
+                                what,
+                                c.controller,
+                                p,
+                                mindex + 1
+                            ),
+                        };
+
+                        Ok(format!(
                             r##"Some(I2cPin {{
-                    controller: Controller::I2C{controller},
-                    port: PortIndex({port}),
-                    gpio_pins: gpio_api::Port::{gpio_port}.pin({gpio_pin}),
-                    function: Alternate::AF{af},
+{}
                 }})"##,
-                            controller = c.controller,
-                            port = index,
-                            gpio_port = match enable.gpio_port {
-                                Some(ref port) => port,
-                                None => bail!(
-                                    "missing pin port on mux enable \
-                                    on I2C{}, port {}, mux {}",
-                                    c.controller,
-                                    p,
-                                    mindex + 1
-                                ),
-                            },
-                            gpio_pin = enable.pins[0],
-                            af = enable.af
-                        )?;
-                        enablestr
-                    } else {
-                        "None".to_string()
+                            self.pin_fields(c, index, gpio_port, pin, 20)?
+                        ))
                     };
 
-                    let driver_struct = format!(
-                        "{}{}",
-                        (&mux.driver[..1].to_string()).to_uppercase(),
-                        &mux.driver[1..]
-                    );
+                    let enablestr = pinstr(&mux.enable, "enable")?;
+                    let resetstr = pinstr(&mux.reset, "reset")?;
+
+                    let driver = match (mux.driver.as_str(), self.driver) {
+                        ("ltc4306", _) => "ltc4306::Ltc4306",
+                        ("max7358", Driver::Stm32h7) => "max7358::Max7358",
+                        ("pca9545", _) => "pca954x::Pca9545",
+                        ("pca9546", _) => "pca954x::Pca9546",
+                        ("pca9548", _) => "pca954x::Pca9548",
+
+                        //
+                        // The MAX7358 requires zero-byte reads, which the
+                        // LPC55 controller cannot generate.
+                        //
+                        ("max7358", Driver::Lpc55) => bail!(
+                            "mux driver \"{}\" on I2C{}, port {}, mux {} \
+                            is not supported on LPC55",
+                            mux.driver,
+                            c.controller,
+                            p,
+                            mindex + 1
+                        ),
+
+                        _ => bail!(
+                            "unknown mux driver \"{}\" on I2C{}, port {}, \
+                            mux {}",
+                            mux.driver,
+                            c.controller,
+                            p,
+                            mindex + 1
+                        ),
+                    };
 
                     write!(
                         &mut s,
@@ -560,16 +923,17 @@ impl ConfigGenerator {
                 controller: Controller::I2C{controller},
                 port: PortIndex({i2c_port}),
                 id: Mux::M{mindex},
-                driver: &drv_stm32h7_i2c::{driver}::{driver_struct},
+                driver: &drv_i2c_mux::{driver},
                 enable: {enable},
+                reset: {reset},
                 address: 0x{address:x},
             }},"##,
                         controller = c.controller,
                         i2c_port = index,
                         mindex = mindex + 1,
-                        driver = mux.driver,
-                        driver_struct = driver_struct,
+                        driver = driver,
                         enable = enablestr,
+                        reset = resetstr,
                         address = mux.address,
                     )?;
                 }
@@ -583,10 +947,251 @@ impl ConfigGenerator {
     }}"##
         )?;
 
+        self.output.push_str(&s);
+
         Ok(())
     }
 
-    fn generate_device(&self, d: &I2cDevice) -> String {
+    /// Determines the mux and segment for a device, if any.
+    fn device_segment(&self, d: &I2cDevice) -> Option<(u8, u8)> {
+        match (d.mux, d.segment) {
+            (Some(mux), Some(segment)) => Some((mux, segment)),
+            (None, None) => None,
+            (_, _) => {
+                panic!(
+                    "device {} at address 0x{:x} must have both \
+                    a mux and a segment, or neither",
+                    d.device, d.address
+                );
+            }
+        }
+    }
+
+    pub fn generate_device_configs(&mut self) -> Result<()> {
+        if self.disposition != Disposition::Initiator {
+            panic!(
+                "can only generate device configs when configured as initiator"
+            );
+        }
+
+        let mut devices = vec![];
+
+        for d in &self.devices {
+            let (controller, port) = self.device_port(d);
+
+            if self.controllers.iter().any(|c| c.controller == controller) {
+                devices.push((d, controller, port, self.device_segment(d)));
+            }
+        }
+
+        let mut s = &mut self.output;
+
+        write!(
+            &mut s,
+            r##"
+    use drv_i2c_server_core::I2cDeviceConfig;
+
+    pub const NUM_DEVICES: usize = {};
+
+    pub fn device_configs() -> [I2cDeviceConfig; NUM_DEVICES] {{
+        #[allow(unused_imports)]
+        use drv_i2c_api::{{Controller, PortIndex, Mux, Segment}};
+
+        ["##,
+            devices.len()
+        )?;
+
+        for (d, controller, port, segment) in devices {
+            let segment = match segment {
+                Some((mux, segment)) => {
+                    format!("Some((Mux::M{}, Segment::S{}))", mux, segment)
+                }
+                None => "None".to_string(),
+            };
+
+            write!(
+                &mut s,
+                r##"
+            // {description}
+            I2cDeviceConfig {{
+                controller: Controller::I2C{controller},
+                port: PortIndex({port}),
+                segment: {segment},
+                address: 0x{address:x},
+                removable: {removable},
+            }},"##,
+                description = d.description,
+                controller = controller,
+                port = port,
+                segment = segment,
+                address = d.address,
+                removable = d.removable,
+            )?;
+        }
+
+        writeln!(
+            &mut s,
+            r##"
+        ]
+    }}"##
+        )?;
+
+        Ok(())
+    }
+
+    pub fn generate_targets(&mut self) -> Result<()> {
+        if self.disposition != Disposition::Emulator {
+            panic!("can only generate targets when emulating devices");
+        }
+
+        let mut devices = vec![];
+        let mut registers = vec![];
+        let mut base = 0;
+
+        for c in &self.controllers {
+            for d in &c.emulate {
+                if devices.iter().any(|(addr, _)| *addr == d.address) {
+                    panic!(
+                        "I2C{} emulates address 0x{:x} twice",
+                        c.controller, d.address
+                    );
+                }
+
+                let kind = match (d.eeprom, &d.registers) {
+                    (Some(size), None) => {
+                        if size == 0 || size > 256 {
+                            panic!(
+                                "emulated EEPROM at 0x{:x} has size {}; \
+                                must be between 1 and 256",
+                                d.address, size
+                            );
+                        }
+
+                        let kind = format!(
+                            "I2cTargetKind::Eeprom {{ base: {}, size: {} }}",
+                            base, size
+                        );
+
+                        base += size;
+                        kind
+                    }
+                    (None, Some(regs)) => {
+                        for (i, r) in regs.iter().enumerate() {
+                            if regs[..i].iter().any(|p| p.code == r.code) {
+                                panic!(
+                                    "emulated device at 0x{:x} has \
+                                    register 0x{:x} twice",
+                                    d.address, r.code
+                                );
+                            }
+
+                            if r.size == 0 || r.size > 255 {
+                                panic!(
+                                    "emulated register 0x{:x} at 0x{:x} has \
+                                    size {}; must be between 1 and 255",
+                                    r.code, d.address, r.size
+                                );
+                            }
+
+                            registers.push((d.address, r, base));
+                            base += r.size;
+                        }
+
+                        "I2cTargetKind::Registers".to_string()
+                    }
+                    (_, _) => {
+                        panic!(
+                            "emulated device at 0x{:x} must have exactly \
+                            one of eeprom or registers",
+                            d.address
+                        );
+                    }
+                };
+
+                devices.push((d.address, (d, kind)));
+            }
+        }
+
+        let mut s = &mut self.output;
+
+        write!(
+            &mut s,
+            r##"
+    pub mod targets {{
+        use i2c_emulator::{{
+            I2cTargetDevice, I2cTargetKind, I2cTargetRegister
+        }};
+
+        pub const NUM_DEVICES: usize = {};
+        pub const NUM_REGISTERS: usize = {};
+        pub const MEMORY_SIZE: usize = {};
+
+        #[allow(dead_code)]
+        pub fn devices() -> [I2cTargetDevice; NUM_DEVICES] {{
+            ["##,
+            devices.len(),
+            registers.len(),
+            base,
+        )?;
+
+        for (address, (d, kind)) in &devices {
+            write!(
+                &mut s,
+                r##"
+                // {description}
+                I2cTargetDevice {{
+                    address: 0x{address:x},
+                    kind: {kind},
+                }},"##,
+                description = d.description,
+                address = address,
+                kind = kind,
+            )?;
+        }
+
+        write!(
+            &mut s,
+            r##"
+            ]
+        }}
+
+        #[allow(dead_code)]
+        pub fn registers() -> [I2cTargetRegister; NUM_REGISTERS] {{
+            ["##
+        )?;
+
+        for (address, r, base) in &registers {
+            write!(
+                &mut s,
+                r##"
+                I2cTargetRegister {{
+                    address: 0x{address:x},
+                    code: 0x{code:x},
+                    base: {base},
+                    size: {size},
+                    block: {block},
+                }},"##,
+                address = address,
+                code = r.code,
+                base = base,
+                size = r.size,
+                block = r.block,
+            )?;
+        }
+
+        writeln!(
+            &mut s,
+            r##"
+            ]
+        }}
+    }}"##
+        )?;
+
+        Ok(())
+    }
+
+    /// Determines the controller and port index for a device.
+    fn device_port(&self, d: &I2cDevice) -> (u8, usize) {
         let controller = match &d.bus {
             Some(bus) => self.buses.get(bus).unwrap().0,
             None => d.controller.unwrap(),
@@ -625,6 +1230,19 @@ impl ConfigGenerator {
             },
         };
 
+        (controller, *port)
+    }
+
+    fn generate_device(&self, d: &I2cDevice) -> String {
+        let (controller, port) = self.device_port(d);
+
+        let segment = match self.device_segment(d) {
+            Some((mux, segment)) => {
+                format!("Some((Mux::M{}, Segment::S{}))", mux, segment)
+            }
+            None => "None".to_string(),
+        };
+
         format!(
             r##"
             // {description}
@@ -637,7 +1255,7 @@ impl ConfigGenerator {
             description = d.description,
             controller = controller,
             port = port,
-            segment = "None",
+            segment = segment,
             address = d.address,
         )
     }
@@ -668,7 +1286,8 @@ impl ConfigGenerator {
             &mut self.output,
             r##"
     pub mod devices {{
-        use drv_i2c_api::{{I2cDevice, Controller, PortIndex}};
+        #[allow(unused_imports)]
+        use drv_i2c_api::{{I2cDevice, Controller, PortIndex, Mux, Segment}};
         use userlib::TaskId;
 "##
         )?;
@@ -768,22 +1387,50 @@ impl ConfigGenerator {
             &mut self.output,
             r##"
     pub mod pmbus {{
-        use drv_i2c_api::{{I2cDevice, Controller, PortIndex}};
+        #[allow(unused_imports)]
+        use drv_i2c_api::{{I2cDevice, Controller, PortIndex, Mux, Segment}};
         use userlib::TaskId;
 "##
         )?;
 
+        //
+        // A device with more than one rail selects among them via PMBus
+        // PAGE; for such a device, we hand back an I2cDevice that has its
+        // page set to the rail, so that the I2C server will select the page
+        // as part of every transaction with it.
+        //
         for (rail, (device, index)) in &byrail {
             write!(
                 &mut self.output,
                 r##"
         #[allow(dead_code)]
-        pub fn {}(task: TaskId) -> (I2cDevice, u8) {{"##,
+        pub fn {}(task: TaskId) -> (I2cDevice, u8) {{
+            #[allow(unused_mut)]
+            let mut device = "##,
                 rail.to_lowercase(),
             )?;
 
             let out = self.generate_device(device);
-            writeln!(&mut self.output, "({}, {})\n        }}", out, index)?;
+            writeln!(&mut self.output, "{};", out)?;
+
+            let paged = match &device.pmbus {
+                Some(I2cPmbus { rails: Some(rails) }) => rails.len() > 1,
+                _ => false,
+            };
+
+            if paged {
+                writeln!(
+                    &mut self.output,
+                    "            device.page = Some({});",
+                    index
+                )?;
+            }
+
+            writeln!(
+                &mut self.output,
+                "            (device, {})\n        }}",
+                index
+            )?;
         }
 
         writeln!(&mut self.output, "    }}")?;
@@ -837,6 +1484,70 @@ impl ConfigGenerator {
         Ok(())
     }
 
+    fn generate_metadata(
+        &self,
+        d: &I2cDevice,
+        kind: Sensor,
+        name: Option<&str>,
+    ) -> String {
+        let (controller, port) = self.device_port(d);
+        let some = |v: Option<String>| match v {
+            Some(v) => format!("Some({})", v),
+            None => "None".to_string(),
+        };
+
+        format!(
+            r##"
+            SensorInfo {{
+                kind: SensorKind::{kind:?},
+                device: {device:?},
+                name: {name},
+                refdes: {refdes},
+                description: {description:?},
+                controller: {controller},
+                port: {port},
+                mux: {mux},
+                segment: {segment},
+                address: 0x{address:x},
+            }}"##,
+            kind = kind,
+            device = d.device,
+            name = some(name.map(|n| format!("{:?}", n))),
+            refdes = some(d.refdes.as_ref().map(|r| format!("{:?}", r))),
+            description = d.description,
+            controller = controller,
+            port = port,
+            mux = some(d.mux.map(|m| m.to_string())),
+            segment = some(d.segment.map(|s| s.to_string())),
+            address = d.address,
+        )
+    }
+
+    /// Returns every sensor (as its device, kind, and index among sensors of
+    /// that kind on the device), in order of sensor ID.
+    fn sensor_list(&self) -> Vec<(&I2cDevice, Sensor, usize)> {
+        let mut list = vec![];
+
+        for d in &self.devices {
+            if let Some(s) = &d.sensors {
+                for (kind, count) in [
+                    (Sensor::Temperature, s.temperature),
+                    (Sensor::Power, s.power),
+                    (Sensor::Current, s.current),
+                    (Sensor::Voltage, s.voltage),
+                    (Sensor::Speed, s.speed),
+                    (Sensor::Energy, s.energy),
+                ] {
+                    for i in 0..count {
+                        list.push((d, kind, i));
+                    }
+                }
+            }
+        }
+
+        list
+    }
+
     pub fn generate_sensors(&mut self) -> Result<()> {
         let mut bydevice = MultiMap::new();
         let mut byname = MultiMap::new();
@@ -845,10 +1556,21 @@ impl ConfigGenerator {
         let mut bykind = MultiMap::new();
 
         let mut sensors = vec![];
+        let mut metadata = vec![];
 
         let mut add_sensor = |kind, d: &I2cDevice, idx: usize| {
             let id = sensors.len();
-            sensors.push(kind);
+            let thresholds = d
+                .sensors
+                .as_ref()
+                .and_then(|s| s.thresholds.as_ref())
+                .and_then(|t| t.get(kind));
+
+            if let Some(thresholds) = thresholds {
+                thresholds.validate(&d.device);
+            }
+
+            sensors.push(thresholds.map(|t| t.generate()));
 
             let name: Option<String> = if let Some(pmbus) = &d.pmbus {
                 if let Some(rails) = &pmbus.rails {
@@ -864,6 +1586,8 @@ impl ConfigGenerator {
                 d.name.clone()
             };
 
+            metadata.push(self.generate_metadata(d, kind, name.as_deref()));
+
             if let Some(bus) = &d.bus {
                 bybus.insert((d.device.clone(), bus.clone(), kind), id);
 
@@ -883,42 +1607,48 @@ impl ConfigGenerator {
             bykind.insert(kind, id);
         };
 
-        for d in &self.devices {
-            if let Some(s) = &d.sensors {
-                for i in 0..s.temperature {
-                    add_sensor(Sensor::Temperature, &d, i);
-                }
+        for (d, kind, i) in self.sensor_list() {
+            add_sensor(kind, d, i);
+        }
 
-                for i in 0..s.power {
-                    add_sensor(Sensor::Power, &d, i);
-                }
+        write!(
+            &mut self.output,
+            r##"
+    pub mod sensors {{
+        use task_sensor_api::{{SensorId, SensorInfo, SensorKind, Thresholds}};
 
-                for i in 0..s.current {
-                    add_sensor(Sensor::Current, &d, i);
-                }
+        #[allow(dead_code)]
+        pub const NUM_SENSORS: usize = {};
 
-                for i in 0..s.voltage {
-                    add_sensor(Sensor::Voltage, &d, i);
-                }
+        #[allow(dead_code)]
+        pub const THRESHOLDS: [Thresholds; NUM_SENSORS] = ["##,
+            sensors.len()
+        )?;
 
-                for i in 0..s.speed {
-                    add_sensor(Sensor::Speed, &d, i);
+        for thresholds in &sensors {
+            match thresholds {
+                Some(t) => write!(&mut self.output, "\n            {},", t)?,
+                None => {
+                    write!(&mut self.output, "\n            Thresholds::NONE,")?
                 }
             }
         }
 
+        writeln!(&mut self.output, "\n        ];")?;
+
         write!(
             &mut self.output,
             r##"
-    pub mod sensors {{
-        use task_sensor_api::SensorId;
-
         #[allow(dead_code)]
-        pub const NUM_SENSORS: usize = {};
-"##,
-            sensors.len()
+        pub const METADATA: [SensorInfo; NUM_SENSORS] = ["##
         )?;
 
+        for m in &metadata {
+            write!(&mut self.output, "{},", m)?;
+        }
+
+        writeln!(&mut self.output, "\n        ];")?;
+
         for ((device, kind), ids) in bydevice.iter_all() {
             self.emit_sensor(device, &format!("{}", kind), ids)?;
         }
@@ -947,6 +1677,332 @@ impl ConfigGenerator {
         Ok(())
     }
 
+    pub fn generate_thermal(&mut self) -> Result<()> {
+        let list = self.sensor_list();
+        let first = |d: &I2cDevice, kind| {
+            list.iter()
+                .position(|(s, k, _)| std::ptr::eq(*s, d) && *k == kind)
+        };
+
+        let mut inputs = vec![];
+        let mut controllers = vec![];
+
+        for d in &self.devices {
+            if let Some(t) = &d.thermal {
+                let part = match d.device.as_str() {
+                    "tmp117" => "Tmp117",
+                    "sbtsi" => "Sbtsi",
+                    _ => panic!("device {} can't be a thermal input", d.device),
+                };
+
+                let id = match first(d, Sensor::Temperature) {
+                    Some(id) => id,
+                    None => panic!(
+                        "thermal input {} has no temperature sensor",
+                        d.device
+                    ),
+                };
+
+                inputs.push(format!(
+                    r##"
+            Input {{
+                part: Part::{},
+                device: {},
+                sensor: SensorId({}),
+                zone: {:?},
+                cpu: {},
+            }},"##,
+                    part,
+                    self.generate_device(d),
+                    id,
+                    t.zone,
+                    t.cpu,
+                ));
+            }
+
+            if d.device == "max31790" {
+                let fans = list
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, (s, k, _))| {
+                        std::ptr::eq(*s, d) && *k == Sensor::Speed
+                    })
+                    .map(|(id, _)| format!("SensorId({})", id))
+                    .collect::<Vec<_>>();
+
+                controllers.push((
+                    fans.len(),
+                    format!(
+                        r##"
+            FanController {{
+                device: {},
+                fans: &[{}],
+            }},"##,
+                        self.generate_device(d),
+                        fans.join(", "),
+                    ),
+                ));
+            }
+        }
+
+        write!(
+            &mut self.output,
+            r##"
+    pub mod thermal {{
+        #[allow(unused_imports)]
+        use drv_i2c_api::{{I2cDevice, Controller, PortIndex, Mux, Segment}};
+        use task_sensor_api::SensorId;
+        use userlib::TaskId;
+
+        #[allow(dead_code)]
+        #[derive(Copy, Clone, Debug, PartialEq)]
+        pub enum Part {{
+            Tmp117,
+            Sbtsi,
+        }}
+
+        /// A temperature input to the thermal loop
+        #[allow(dead_code)]
+        pub struct Input {{
+            pub part: Part,
+            pub device: I2cDevice,
+            pub sensor: SensorId,
+            pub zone: &'static str,
+            pub cpu: bool,
+        }}
+
+        /// A fan controller, with a speed sensor for each of its fans
+        #[allow(dead_code)]
+        pub struct FanController {{
+            pub device: I2cDevice,
+            pub fans: &'static [SensorId],
+        }}
+
+        #[allow(dead_code)]
+        pub const NUM_INPUTS: usize = {};
+
+        #[allow(dead_code)]
+        pub const NUM_FAN_CONTROLLERS: usize = {};
+
+        #[allow(dead_code)]
+        pub const NUM_FANS: usize = {};
+
+        #[allow(dead_code, unused_variables)]
+        pub fn inputs(task: TaskId) -> [Input; NUM_INPUTS] {{
+            ["##,
+            inputs.len(),
+            controllers.len(),
+            controllers.iter().map(|(n, _)| n).sum::<usize>(),
+        )?;
+
+        for input in &inputs {
+            write!(&mut self.output, "{}", input)?;
+        }
+
+        write!(
+            &mut self.output,
+            r##"
+            ]
+        }}
+
+        #[allow(dead_code, unused_variables)]
+        pub fn fan_controllers(
+            task: TaskId,
+        ) -> [FanController; NUM_FAN_CONTROLLERS] {{
+            ["##
+        )?;
+
+        for (_, controller) in &controllers {
+            write!(&mut self.output, "{}", controller)?;
+        }
+
+        writeln!(
+            &mut self.output,
+            r##"
+            ]
+        }}
+    }}"##
+        )?;
+
+        Ok(())
+    }
+
+    pub fn generate_power(&mut self) -> Result<()> {
+        //
+        // PMBus parts monitored via the generic PMBus driver; each has a
+        // constant of the same name (uppercased) in its `pmbus_device`
+        // module.  Any other part (save the ADM1272, which has its own
+        // driver) can't be a power rail.
+        //
+        const PMBUS_PARTS: &[&str] =
+            &["bmr491", "isl68224", "raa229618", "tps546b24a"];
+
+        const STATES: &[&str] = &["A2", "A2PlusMono", "A2PlusFans", "A1", "A0"];
+
+        let list = self.sensor_list();
+        let sensor = |d: &I2cDevice, kind, index| {
+            list.iter()
+                .position(|(s, k, i)| {
+                    std::ptr::eq(*s, d) && *k == kind && *i == index
+                })
+                .map(|id| format!("SensorId({})", id))
+        };
+
+        let mut rails = vec![];
+
+        for d in &self.devices {
+            let power = match &d.power {
+                Some(power) => power,
+                None => continue,
+            };
+
+            if !STATES.contains(&power.state.as_str()) {
+                panic!(
+                    "device {} has unknown power state {}",
+                    d.device, power.state
+                );
+            }
+
+            let part = if d.device == "adm1272" {
+                match power.rsense {
+                    Some(rsense) => {
+                        format!(
+                            "Part::Adm1272(Ohms({:?}))",
+                            rsense as f32 / 1000.0
+                        )
+                    }
+                    None => panic!("adm1272 must specify rsense"),
+                }
+            } else if PMBUS_PARTS.contains(&d.device.as_str()) {
+                if power.rsense.is_some() {
+                    panic!("device {} can't specify rsense", d.device);
+                }
+
+                format!(
+                    "Part::Pmbus(&pmbus_device::{})",
+                    d.device.to_uppercase()
+                )
+            } else {
+                panic!("device {} can't be a power rail", d.device);
+            };
+
+            let names = match &d.pmbus {
+                Some(I2cPmbus { rails: Some(names) }) => names,
+                _ => panic!("power rail {} has no PMBus rails", d.device),
+            };
+
+            for (index, name) in names.iter().enumerate() {
+                if name.is_empty() {
+                    continue;
+                }
+
+                let required = |kind| match sensor(d, kind, index) {
+                    Some(id) => id,
+                    None => panic!("rail {} has no {} sensor", name, kind),
+                };
+
+                let optional = |kind| match sensor(d, kind, index) {
+                    Some(id) => format!("Some({})", id),
+                    None => "None".to_string(),
+                };
+
+                rails.push(format!(
+                    r##"
+            Rail {{
+                name: {name:?},
+                part: {part},
+                device: {device},
+                rail: {index},
+                state: PowerState::{state},
+                voltage: {voltage},
+                current: {current},
+                temperature: {temperature},
+                power: {power},
+                energy: {energy},
+            }},"##,
+                    name = name,
+                    part = part,
+                    device = self.generate_device(d),
+                    index = index,
+                    state = power.state,
+                    voltage = required(Sensor::Voltage),
+                    current = required(Sensor::Current),
+                    temperature = optional(Sensor::Temperature),
+                    power = optional(Sensor::Power),
+                    energy = optional(Sensor::Energy),
+                ));
+            }
+        }
+
+        //
+        // Faulted rails are reported as a mask in a u32 (see the
+        // `faulted_rails` operation in idl/power.idol).
+        //
+        if rails.len() > 32 {
+            panic!(
+                "{} power rails are configured, but at most 32 are supported",
+                rails.len()
+            );
+        }
+
+        write!(
+            &mut self.output,
+            r##"
+    pub mod power {{
+        use drv_gimlet_seq_api::PowerState;
+        #[allow(unused_imports)]
+        use drv_i2c_api::{{I2cDevice, Controller, PortIndex, Mux, Segment}};
+        use drv_i2c_devices::pmbus_device::{{self, Quirks}};
+        use task_sensor_api::SensorId;
+        use userlib::units::Ohms;
+        use userlib::TaskId;
+
+        /// The driver for a rail's device
+        #[allow(dead_code)]
+        pub enum Part {{
+            Pmbus(&'static Quirks),
+            Adm1272(Ohms),
+        }}
+
+        /// A power rail, and the sensors for its readings
+        #[allow(dead_code)]
+        pub struct Rail {{
+            pub name: &'static str,
+            pub part: Part,
+            pub device: I2cDevice,
+            pub rail: u8,
+            pub state: PowerState,
+            pub voltage: SensorId,
+            pub current: SensorId,
+            pub temperature: Option<SensorId>,
+            pub power: Option<SensorId>,
+            pub energy: Option<SensorId>,
+        }}
+
+        #[allow(dead_code)]
+        pub const NUM_RAILS: usize = {};
+
+        #[allow(dead_code, unused_variables)]
+        pub fn rails(task: TaskId) -> [Rail; NUM_RAILS] {{
+            ["##,
+            rails.len(),
+        )?;
+
+        for rail in &rails {
+            write!(&mut self.output, "{}", rail)?;
+        }
+
+        writeln!(
+            &mut self.output,
+            r##"
+            ]
+        }}
+    }}"##
+        )?;
+
+        Ok(())
+    }
+
     pub fn generate_ports(&mut self) -> Result<()> {
         writeln!(
             &mut self.output,
@@ -1002,11 +2058,34 @@ pub fn codegen(disposition: Disposition) -> Result<()> {
             g.generate_ports()?;
         }
 
+        Disposition::Emulator => {
+            let n = g.ncontrollers();
+
+            if n != 1 {
+                //
+                // The emulating server operates exactly one controller; if
+                // no controller emulates devices, the task should be
+                // deconfigured.
+                //
+                panic!(
+                    "found {} I2C controller(s) emulating devices; \
+                    expected exactly one",
+                    n
+                );
+            }
+
+            g.generate_controllers()?;
+            g.generate_pins()?;
+            g.generate_ports()?;
+            g.generate_targets()?;
+        }
+
         Disposition::Initiator => {
             g.generate_controllers()?;
             g.generate_pins()?;
             g.generate_ports()?;
             g.generate_muxes()?;
+            g.generate_device_configs()?;
         }
 
         Disposition::Devices => {
@@ -1018,6 +2097,15 @@ pub fn codegen(disposition: Disposition) -> Result<()> {
             g.generate_devices()?;
             g.generate_pmbus()?;
             g.generate_sensors()?;
+            g.generate_thermal()?;
+        }
+
+        Disposition::Power => {
+            g.generate_devices()?;
+            g.generate_pmbus()?;
+            g.generate_sensors()?;
+            g.generate_thermal()?;
+            g.generate_power()?;
         }
     }
 
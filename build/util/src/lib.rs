@@ -56,6 +56,19 @@ pub fn task_config<T: DeserializeOwned>() -> Result<T> {
     toml_from_env("HUBRIS_TASK_CONFIG")
 }
 
+/// Pulls the task configuration, if the task has one (it is absent, and this
+/// returns `None`, if the task has no `config` section in the app
+/// configuration).  See `config` for more details.
+pub fn task_maybe_config<T: DeserializeOwned>() -> Result<Option<T>> {
+    println!("cargo:rerun-if-env-changed=HUBRIS_TASK_CONFIG");
+
+    match env::var("HUBRIS_TASK_CONFIG") {
+        Ok(_) => Ok(Some(task_config()?)),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
 fn toml_from_env<T: DeserializeOwned>(var: &str) -> Result<T> {
     let config = env::var(var)?;
     println!("--- toml for ${} ---", var);
@@ -2,7 +2,7 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::collections::{hash_map::DefaultHasher, BTreeMap, HashMap, VecDeque};
 use std::fs::{self, File};
 use std::hash::{Hash, Hasher};
 use std::io::{Read, Write};
@@ -17,11 +17,11 @@ use path_slash::PathBufExt;
 use serde::Serialize;
 
 use crate::{
-    elf, task_slot, Config, LoadSegment, Output, Peripheral, Signing,
+    elf, sign, task_slot, Config, LoadSegment, Output, Peripheral, Signing,
     Supervisor, Task,
 };
 
-use lpc55_sign::{crc_image, signed_image};
+use lpc55_sign::crc_image;
 
 /// In practice, applications with active interrupt activity tend to use about
 /// 650 bytes of stack. Because kernel stack overflows are annoying, we've
@@ -41,33 +41,11 @@ pub fn package(
     let toml = Config::from_file(&cfg)?;
 
     let mut out = PathBuf::from("target");
-    let buildstamp_file = out.join("buildstamp");
-
     out.push(&toml.name);
     out.push("dist");
 
     std::fs::create_dir_all(&out)?;
 
-    let rebuild = match std::fs::read(&buildstamp_file) {
-        Ok(contents) => {
-            if let Ok(contents) = std::str::from_utf8(&contents) {
-                if let Ok(cmp) = u64::from_str_radix(contents, 16) {
-                    toml.buildhash != cmp
-                } else {
-                    println!("buildstamp file contents unknown; re-building.");
-                    true
-                }
-            } else {
-                println!("buildstamp file contents corrupt; re-building.");
-                true
-            }
-        }
-        Err(_) => {
-            println!("no buildstamp file found; re-building.");
-            true
-        }
-    };
-
     let mut src_dir = cfg.to_path_buf();
     src_dir.pop();
 
@@ -110,31 +88,6 @@ pub fn package(
     let mut all_output_sections = BTreeMap::default();
     let mut entry_points = HashMap::<_, _>::default();
 
-    // if we need to rebuild, we should clean everything before we start building
-    if rebuild {
-        println!("app.toml has changed; rebuilding all tasks");
-
-        cargo_clean(&toml.kernel.name, &toml.target)?;
-
-        for name in toml.tasks.keys() {
-            // this feels redundant, don't we already have the name? consider
-            // our supervisor:
-            //
-            // [tasks.jefe]
-            // path = "../task-jefe"
-            // name = "task-jefe"
-            //
-            // the "name" in the key is jefe, but the package name is in
-            // tasks.jefe.name, and that's what we need to give to cargo
-            let task_toml = &toml.tasks[name];
-
-            cargo_clean(&task_toml.name, &toml.target)?;
-        }
-    }
-
-    // now that we're clean, update our buildstamp file; any failure to build
-    // from here on need not trigger a clean
-    std::fs::write(&buildstamp_file, format!("{:x}", toml.buildhash))?;
     let mut shared_syms: Option<&[String]> = None;
 
     // Panic messages in crates have a long prefix; we'll shorten it using
@@ -177,10 +130,6 @@ pub fn package(
     // If there is a bootloader, build it first as there may be dependencies
     // for applications
     if let Some(bootloader) = toml.bootloader.as_ref() {
-        if rebuild {
-            cargo_clean(&bootloader.name, &toml.target)?;
-        }
-
         let mut bootloader_memory = IndexMap::new();
         let flash = memories.get("bootloader_flash").unwrap();
         let ram = memories.get("bootloader_ram").unwrap();
@@ -509,13 +458,13 @@ Did you mean to run `cargo xtask dist`?"
 
         let bootloader_fname =
             if let Some(signing) = toml.signing.get("bootloader") {
-                format!("bootloader_{}.bin", signing.method)
+                signing.image_file("bootloader")
             } else {
                 "bootloader.bin".into()
             };
 
         let hubris_fname = if let Some(signing) = toml.signing.get("combined") {
-            format!("combined_{}.bin", signing.method)
+            signing.image_file("combined")
         } else {
             "combined.bin".into()
         };
@@ -662,8 +611,8 @@ Did you mean to run `cargo xtask dist`?"
         archive
             .copy(out.join(&bootloader.name), img_dir.join(&bootloader.name))?;
     }
-    for s in toml.signing.keys() {
-        let name = format!("{}_{}.bin", s, toml.signing.get(s).unwrap().method);
+    for (s, signing) in &toml.signing {
+        let name = signing.output_file(s);
         archive.copy(out.join(&name), img_dir.join(&name))?;
     }
 
@@ -676,12 +625,14 @@ Did you mean to run `cargo xtask dist`?"
     // To allow for the image to be flashed based only on the archive (e.g.,
     // by Humility), we pull in our flash configuration, flatten it to pull in
     // any external configuration files, serialize it, and add it to the
-    // archive.
+    // archive.  (Emulated boards have nothing to flash, and so have no
+    // flash configuration.)
     //
-    let mut config = crate::flash::config(&toml.board.as_str())?;
-    config.flatten()?;
+    if let Some(mut config) = crate::flash::config(&toml.board.as_str())? {
+        config.flatten()?;
 
-    archive.text(img_dir.join("flash.ron"), ron::to_string(&config)?)?;
+        archive.text(img_dir.join("flash.ron"), ron::to_string(&config)?)?;
+    }
 
     archive.finish()?;
 
@@ -790,22 +741,32 @@ fn do_sign_file(
     header_start: u32,
     memories: &IndexMap<String, Range<u32>>,
 ) -> Result<()> {
+    let priv_key = || {
+        sign.priv_key.as_ref().ok_or_else(|| {
+            anyhow!("{} signing method requires priv-key", sign.method)
+        })
+    };
+
     if sign.method == "crc" {
         crc_image::update_crc(
             &out.join(format!("{}.bin", fname)),
             &out.join(format!("{}_crc.bin", fname)),
         )
     } else if sign.method == "rsa" {
-        let priv_key = sign.priv_key.as_ref().unwrap();
-        let root_cert = sign.root_cert.as_ref().unwrap();
-        signed_image::sign_image(
-            false, // TODO add an option to enable DICE
-            &out.join(format!("{}.bin", fname)),
-            &src_dir.join(&priv_key),
-            &src_dir.join(&root_cert),
-            &out.join(format!("{}_rsa.bin", fname)),
-            &out.join("CMPA.bin"),
-        )
+        let root_cert = sign.root_cert.as_ref().ok_or_else(|| {
+            anyhow!("{} signing method requires root-cert", sign.method)
+        })?;
+        // TODO add an option to enable DICE
+        let key = sign::SigningKey::load(&src_dir.join(priv_key()?))?;
+        let root_cert = std::fs::read(src_dir.join(root_cert))?;
+        let image = std::fs::read(out.join(format!("{}.bin", fname)))?;
+
+        let (signed, cmpa) = sign::lpc55_sign(&image, &key, &root_cert)
+            .context(format!("failed to sign {}", fname))?;
+
+        std::fs::write(out.join(format!("{}_rsa.bin", fname)), signed)?;
+        std::fs::write(out.join("CMPA.bin"), cmpa)?;
+        Ok(())
     } else if sign.method == "ecc" {
         // Right now we just generate the header
         generate_header(
@@ -814,6 +775,15 @@ fn do_sign_file(
             header_start,
             memories,
         )
+    } else if sign.method == "detached" {
+        let key = sign::SigningKey::load(&src_dir.join(priv_key()?))?;
+        let image = std::fs::read(out.join(format!("{}.bin", fname)))?;
+
+        let sig = sign::detached_sign(&image, &key)
+            .context(format!("failed to sign {}", fname))?;
+
+        std::fs::write(out.join(format!("{}.sig", fname)), sig)?;
+        Ok(())
     } else {
         eprintln!("Invalid sign method {}", sign.method);
         std::process::exit(1);
@@ -1041,6 +1011,47 @@ fn build(
     app_config: &Option<ordered_toml::Value>,
     extra_env: &[(&str, &str)],
 ) -> Result<()> {
+    // Hash everything that goes into this build other than the sources
+    // themselves: our arguments (which become cargo flags and environment
+    // variables) and the linker scripts that we've generated for it.
+    let mut hasher = DefaultHasher::new();
+    (target, board_name, path, name, features, task_names).hash(&mut hasher);
+    (remap_paths, secure_separation, shared_syms, extra_env).hash(&mut hasher);
+    for config in &[config, app_config] {
+        config
+            .as_ref()
+            .map(|c| toml::to_string(c).unwrap())
+            .hash(&mut hasher);
+    }
+    for script in &["target/memory.x", "target/link.x", "target/table.ld"] {
+        std::fs::read(script).ok().hash(&mut hasher);
+    }
+    let inputs = hasher.finish();
+
+    let mut cargo_out = Path::new("target").to_path_buf();
+    cargo_out.push(target);
+    cargo_out.push("release");
+    cargo_out.push(name);
+
+    let stamp = BuildStamp {
+        inputs,
+        sources: hash_sources(&cargo_out, path),
+    };
+    let stamp_file = dest.with_extension("stamp");
+
+    if dest.exists() && BuildStamp::read(&stamp_file) == Some(stamp) {
+        println!("{} is up to date", name);
+        return Ok(());
+    }
+
+    // Cargo doesn't know about our linker scripts, and the output in its
+    // target directory may have been built for a different image; if it was
+    // built from different inputs, clean it to force a relink.
+    let inputs_file = cargo_out.with_extension("inputs");
+    if BuildStamp::read_inputs(&inputs_file) != Some(inputs) {
+        cargo_clean(name, target)?;
+    }
+
     println!("building path {}", path.display());
 
     // NOTE: current_dir's docs suggest that you should use canonicalize for
@@ -1068,7 +1079,7 @@ fn build(
 
     // This works because we control the environment in which we're about
     // to invoke cargo, and never modify CARGO_TARGET in that environment.
-    let mut cargo_out = Path::new("target").to_path_buf();
+    let cargo_dir = Path::new("target");
 
     let remap_path_prefix: String = remap_paths
         .iter()
@@ -1086,7 +1097,7 @@ fn build(
              -C overflow-checks=y \
              {}
              ",
-            cargo_out.display(),
+            cargo_dir.display(),
             remap_path_prefix,
         ),
     );
@@ -1163,16 +1174,94 @@ fn build(
         bail!("command failed, see output for details");
     }
 
-    cargo_out.push(target);
-    cargo_out.push("release");
-    cargo_out.push(name);
-
     println!("{} -> {}", cargo_out.display(), dest.display());
-    std::fs::copy(&cargo_out, dest)?;
+    std::fs::copy(&cargo_out, &dest)?;
+
+    // Now that cargo has written a fresh dep-info file, record what we built.
+    std::fs::write(&inputs_file, format!("{:x}", inputs))?;
+    BuildStamp {
+        inputs,
+        sources: hash_sources(&cargo_out, path),
+    }
+    .write(&stamp_file)?;
 
     Ok(())
 }
 
+/// Records the inputs to a single build, allowing `build` to skip cargo (and
+/// the relink) entirely when nothing has changed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct BuildStamp {
+    /// Hash of the build configuration and generated linker scripts
+    inputs: u64,
+    /// Hash of the source files cargo reported as dependencies of the build,
+    /// or `None` if they could not all be found
+    sources: Option<u64>,
+}
+
+impl BuildStamp {
+    fn read(path: &Path) -> Option<Self> {
+        let contents = std::fs::read_to_string(path).ok()?;
+        let mut words = contents.split_whitespace();
+        let inputs = u64::from_str_radix(words.next()?, 16).ok()?;
+        let sources = u64::from_str_radix(words.next()?, 16).ok()?;
+
+        Some(BuildStamp {
+            inputs,
+            sources: Some(sources),
+        })
+    }
+
+    fn read_inputs(path: &Path) -> Option<u64> {
+        let contents = std::fs::read_to_string(path).ok()?;
+        u64::from_str_radix(contents.trim(), 16).ok()
+    }
+
+    fn write(&self, path: &Path) -> Result<()> {
+        // A stamp whose sources we couldn't hash would never match, so don't
+        // bother writing it.
+        match self.sources {
+            Some(sources) => std::fs::write(
+                path,
+                format!("{:x} {:x}", self.inputs, sources),
+            )?,
+            None => {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Hashes the size and modification time of every source file listed in
+/// the dep-info file cargo writes next to `cargo_out`, along with the
+/// manifest of the package at `path` and the workspace lockfile.
+fn hash_sources(cargo_out: &Path, path: &Path) -> Option<u64> {
+    let depinfo =
+        std::fs::read_to_string(cargo_out.with_extension("d")).ok()?;
+
+    // The dep-info file is in Makefile syntax: a target, a colon, and then
+    // whitespace-separated dependencies, with spaces in paths escaped and
+    // lines continued with a trailing backslash.
+    let (_, deps) = depinfo.split_once(": ")?;
+    let deps = deps.replace("\\\n", " ").replace("\\ ", "\0");
+
+    let mut files = deps
+        .split_whitespace()
+        .map(|d| PathBuf::from(d.replace('\0', " ")))
+        .collect::<Vec<_>>();
+    files.push(path.join("Cargo.toml"));
+    files.push(PathBuf::from("Cargo.lock"));
+
+    let mut hasher = DefaultHasher::new();
+    for file in files {
+        let meta = std::fs::metadata(&file).ok()?;
+        (file, meta.len(), meta.modified().ok()?).hash(&mut hasher);
+    }
+
+    Some(hasher.finish())
+}
+
 #[derive(Debug, Clone, Default)]
 struct Allocations {
     /// Map from memory-name to address-range
@@ -134,7 +134,7 @@ impl FlashConfig {
     }
 }
 
-pub fn config(board: &str) -> anyhow::Result<FlashConfig> {
+pub fn config(board: &str) -> anyhow::Result<Option<FlashConfig>> {
     match board {
         "lpcxpresso55s69" | "gemini-bu-rot-1" | "gimlet-rot-1" => {
             let chip = if board == "lpcxpresso55s69" {
@@ -159,7 +159,7 @@ pub fn config(board: &str) -> anyhow::Result<FlashConfig> {
                 .arg("hex")
                 .payload();
 
-            Ok(flash)
+            Ok(Some(flash))
         }
         "stm32f3-discovery" | "stm32f4-discovery" | "nucleo-h743zi2"
         | "nucleo-h753zi" | "stm32h7b3i-dk" | "gemini-bu-1" | "gimletlet-1"
@@ -195,8 +195,9 @@ pub fn config(board: &str) -> anyhow::Result<FlashConfig> {
                 .arg("-c")
                 .arg("exit");
 
-            Ok(flash)
+            Ok(Some(flash))
         }
+        board if crate::qemu::machine(board).is_some() => Ok(None),
         _ => {
             anyhow::bail!("unrecognized board {}", board);
         }
@@ -216,6 +217,7 @@ fn chip_name(board: &str) -> anyhow::Result<&'static str> {
         "stm32g031" => "STM32G031Y8Yx",
          "stm32g070" => "STM32G070KBTx",
          "stm32g0b1" => anyhow::bail!("This board is not yet supported by probe-rs, please use OpenOCD directly"),
+        board if crate::qemu::machine(board).is_some() => anyhow::bail!("{} is emulated; use `cargo xtask qemu` instead", board),
         _ => anyhow::bail!("unrecognized board {}", board),
 
     };
@@ -2,8 +2,7 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use std::collections::{hash_map::DefaultHasher, BTreeMap};
-use std::hash::{Hash, Hasher};
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 
 use anyhow::{bail, Result};
@@ -19,9 +18,13 @@ mod elf;
 mod flash;
 mod gdb;
 mod humility;
+mod qemu;
+mod sign;
 mod sizes;
+mod stack;
 mod task_slot;
 mod test;
+mod verify;
 
 #[derive(Debug, Parser)]
 #[clap(max_term_width = 80, about = "extra tasks to help you work on Hubris")]
@@ -77,6 +80,30 @@ enum Xtask {
         cfg: PathBuf,
     },
 
+    /// Runs `xtask dist` and reports the worst-case stack depth of each task,
+    /// as determined by static analysis of its call graph
+    StackCheck {
+        /// Request verbosity from tools we shell out to, and print the
+        /// deepest call path for each task.
+        #[clap(short)]
+        verbose: bool,
+        /// Path to the image configuration file, in TOML.
+        cfg: PathBuf,
+    },
+
+    /// Runs `xtask dist` and then runs the image under QEMU, streaming its
+    /// (semihosted) log output.  Only emulated boards are supported.
+    Qemu {
+        /// Request verbosity from tools we shell out to.
+        #[clap(short)]
+        verbose: bool,
+        /// Wait for gdb to attach (on localhost:1234) before starting.
+        #[clap(long)]
+        gdb: bool,
+        /// Path to the image configuration file, in TOML.
+        cfg: PathBuf,
+    },
+
     /// Runs `xtask dist` and then runs a properly configured gdb for you.
     Gdb {
         /// Path to the image configuration file, in TOML.
@@ -100,13 +127,28 @@ enum Xtask {
         /// Path to the image configuration file, in TOML.
         cfg: PathBuf,
 
-        /// Do not flash a new image; just run `humility test`
+        /// Do not flash a new image; just run `humility test` (or, for
+        /// emulated boards, run the existing image under QEMU)
         #[clap(short)]
         noflash: bool,
 
         /// Request verbosity from tools we shell out to.
         #[clap(short)]
         verbose: bool,
+
+        /// Write a JUnit XML report of the results to this file (emulated
+        /// boards only)
+        #[clap(long)]
+        junit: Option<PathBuf>,
+
+        /// Write a TAP report of the results to this file (emulated boards
+        /// only)
+        #[clap(long)]
+        tap: Option<PathBuf>,
+
+        /// Seconds to wait for an emulated test run to complete
+        #[clap(long, default_value = "300")]
+        timeout: u64,
     },
 
     /// Runs `cargo clippy` on a specified task
@@ -131,6 +173,13 @@ enum Xtask {
         /// Path to task executable
         task_bin: PathBuf,
     },
+
+    /// Checks the signatures of a previously built image against the keys
+    /// in its `signing` configuration
+    VerifySignature {
+        /// Path to the image configuration file, in TOML.
+        cfg: PathBuf,
+    },
 }
 
 /// A `RawConfig` represents an `app.toml` file that has been deserialized,
@@ -178,7 +227,6 @@ struct Config {
     extratext: IndexMap<String, Peripheral>,
     supervisor: Option<Supervisor>,
     config: Option<ordered_toml::Value>,
-    buildhash: u64,
 }
 
 impl Config {
@@ -186,26 +234,19 @@ impl Config {
         let cfg_contents = std::fs::read(&cfg)?;
         let toml: RawConfig = toml::from_slice(&cfg_contents)?;
 
-        let mut hasher = DefaultHasher::new();
-        hasher.write(&cfg_contents);
-
         // If the app.toml specifies a `chip` key, then load the peripheral
-        // register map from a separate file and accumulate that file in the
-        // buildhash.
+        // register map from a separate file.
         let peripherals = if let Some(chip) = &toml.chip {
             if !toml.peripherals.is_empty() {
                 bail!("Cannot specify both chip and peripherals");
             }
             let chip_file = cfg.parent().unwrap().join(chip);
             let chip_contents = std::fs::read(chip_file)?;
-            hasher.write(&chip_contents);
             toml::from_slice(&chip_contents)?
         } else {
             toml.peripherals
         };
 
-        let buildhash = hasher.finish();
-
         Ok(Config {
             name: toml.name,
             target: toml.target,
@@ -222,11 +263,19 @@ impl Config {
             extratext: toml.extratext,
             supervisor: toml.supervisor,
             config: toml.config,
-            buildhash,
         })
     }
 }
 
+/// Signing configuration for one output (`combined` or `bootloader`).  The
+/// `method` is one of:
+///
+/// - `crc`: LPC55 CRC image (no signature)
+/// - `rsa`: LPC55 signed image, using an RSA-2048/4096 `priv-key` and a
+///   matching `root-cert`
+/// - `ecc`: image header only
+/// - `detached`: a separate signature file, using an RSA-2048/4096, P-256 or
+///   P-384 `priv-key`; the image itself is unmodified
 #[derive(Clone, Debug, Deserialize)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
 struct Signing {
@@ -235,6 +284,28 @@ struct Signing {
     root_cert: Option<PathBuf>,
 }
 
+impl Signing {
+    /// Returns the name of the image that should be flashed for the output
+    /// named `name`.
+    fn image_file(&self, name: &str) -> String {
+        if self.method == "detached" {
+            format!("{}.bin", name)
+        } else {
+            format!("{}_{}.bin", name, self.method)
+        }
+    }
+
+    /// Returns the name of the file produced by signing the output named
+    /// `name`.
+    fn output_file(&self, name: &str) -> String {
+        if self.method == "detached" {
+            format!("{}.sig", name)
+        } else {
+            self.image_file(name)
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
 struct Bootloader {
@@ -470,6 +541,14 @@ fn main() -> Result<()> {
             dist::package(verbose, false, &cfg, None)?;
             sizes::run(&cfg, false)?;
         }
+        Xtask::StackCheck { verbose, cfg } => {
+            dist::package(verbose, false, &cfg, None)?;
+            stack::run(&cfg, verbose)?;
+        }
+        Xtask::Qemu { verbose, gdb, cfg } => {
+            dist::package(verbose, false, &cfg, None)?;
+            qemu::run(verbose, &cfg, gdb)?;
+        }
         Xtask::Gdb { cfg, gdb_cfg } => {
             dist::package(false, false, &cfg, None)?;
             gdb::run(&cfg, &gdb_cfg)?;
@@ -481,13 +560,36 @@ fn main() -> Result<()> {
             cfg,
             noflash,
             verbose,
+            junit,
+            tap,
+            timeout,
         } => {
-            if !noflash {
-                dist::package(verbose, false, &cfg, None)?;
-                flash::run(verbose, &cfg)?;
-            }
+            let reports = test::Reports { junit, tap };
+            let board = Config::from_file(&cfg)?.board;
+
+            if qemu::machine(&board).is_some() {
+                if !noflash {
+                    dist::package(verbose, false, &cfg, None)?;
+                }
 
-            test::run(verbose, &cfg)?;
+                test::run_qemu(
+                    verbose,
+                    &cfg,
+                    std::time::Duration::from_secs(timeout),
+                    &reports,
+                )?;
+            } else {
+                if !reports.is_empty() {
+                    bail!("test reports are only supported on emulated boards");
+                }
+
+                if !noflash {
+                    dist::package(verbose, false, &cfg, None)?;
+                    flash::run(verbose, &cfg)?;
+                }
+
+                test::run(verbose, &cfg)?;
+            }
         }
         Xtask::Clippy {
             package,
@@ -500,6 +602,9 @@ fn main() -> Result<()> {
         Xtask::TaskSlots { task_bin } => {
             task_slot::dump_task_slot_table(&task_bin)?;
         }
+        Xtask::VerifySignature { cfg } => {
+            verify::run(&cfg)?;
+        }
     }
 
     Ok(())
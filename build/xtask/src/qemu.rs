@@ -0,0 +1,91 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::Context;
+
+use crate::Config;
+
+/// The QEMU binary we run; all of our emulated boards are Arm.
+const QEMU: &str = "qemu-system-arm";
+
+/// The QEMU machine and CPU that emulate a given board.
+#[derive(Debug)]
+pub struct Machine {
+    pub machine: &'static str,
+    pub cpu: &'static str,
+}
+
+/// Returns the QEMU machine for `board`, or `None` if the board can't be
+/// emulated.
+pub fn machine(board: &str) -> Option<Machine> {
+    match board {
+        "mps2-an386" => Some(Machine {
+            machine: "mps2-an386",
+            cpu: "cortex-m4",
+        }),
+        _ => None,
+    }
+}
+
+/// Builds a QEMU command line to run the image for `toml`, which must
+/// already have been built.  Semihosting is enabled (for unprivileged code
+/// too, since that's where tasks run) and directed to QEMU's own stdio, so
+/// `sys_log!` output appears on stdout.
+pub fn command(toml: &Config) -> anyhow::Result<Command> {
+    let machine = machine(&toml.board).ok_or_else(|| {
+        anyhow::anyhow!("board {} cannot be emulated", toml.board)
+    })?;
+
+    let mut image = PathBuf::from("target");
+    image.push(&toml.name);
+    image.push("dist");
+    image.push("final.elf");
+
+    let mut qemu = Command::new(QEMU);
+    qemu.arg("-machine")
+        .arg(machine.machine)
+        .arg("-cpu")
+        .arg(machine.cpu)
+        .arg("-nographic")
+        .arg("-monitor")
+        .arg("none")
+        .arg("-serial")
+        .arg("none")
+        .arg("-semihosting-config")
+        .arg("enable=on,target=native,userspace=on")
+        .arg("-kernel")
+        .arg(image);
+
+    Ok(qemu)
+}
+
+pub fn run(verbose: bool, cfg: &Path, gdb: bool) -> anyhow::Result<()> {
+    ctrlc::set_handler(|| {}).expect("Error setting Ctrl-C handler");
+
+    let toml = Config::from_file(&cfg)?;
+    let mut qemu = command(&toml)?;
+
+    if gdb {
+        // Wait for a debugger to attach on the default port (1234).
+        qemu.arg("-s").arg("-S");
+        println!("waiting for gdb on localhost:1234");
+    }
+
+    if verbose {
+        println!("running {:?}", qemu);
+    }
+
+    let status = qemu
+        .status()
+        .with_context(|| format!("failed to run {} ({:?})", QEMU, qemu))?;
+
+    if !status.success() {
+        anyhow::bail!("{} failed; see output for details", QEMU);
+    }
+
+    Ok(())
+}
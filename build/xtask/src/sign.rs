@@ -0,0 +1,673 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Native image signing.
+//!
+//! We support two signed formats:
+//!
+//! - LPC55 signed images, as consumed by the LPC55 boot ROM.  These are
+//!   RSA-2048 or RSA-4096 only (the ROM knows nothing else), and consist of
+//!   the image with a certificate block and PKCS#1 v1.5 signature appended,
+//!   along with a CMPA page containing the hash of the root key table.
+//!
+//! - Detached signatures, for targets (e.g. STM32) with no ROM support for
+//!   signed images.  These are a small header, the signer's public key and a
+//!   signature over the unmodified image, and may use any supported key.
+
+use std::convert::TryInto;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context, Result};
+use byteorder::{ByteOrder, LittleEndian};
+use p256::pkcs8::DecodePrivateKey as DecodeEcPrivateKey;
+use rsa::pkcs1::{DecodeRsaPrivateKey, DecodeRsaPublicKey, EncodeRsaPublicKey};
+use rsa::pkcs8::DecodePrivateKey;
+use rsa::{
+    Hash, PaddingScheme, PublicKey, PublicKeyParts, RsaPrivateKey, RsaPublicKey,
+};
+use sha2::{Digest, Sha256};
+
+/// Offset of the image length word in an LPC55 image.
+const LPC55_IMAGE_LEN_OFFSET: usize = 0x20;
+
+/// Offset of the image type word in an LPC55 image.
+const LPC55_IMAGE_TYPE_OFFSET: usize = 0x24;
+
+/// Offset of the word containing the offset of the certificate block.
+const LPC55_CERT_OFFSET_OFFSET: usize = 0x28;
+
+/// Image type for a signed XIP image.
+const LPC55_IMAGE_TYPE_SIGNED: u32 = 0x4;
+
+/// Magic number ("cert") at the start of an LPC55 certificate block.
+const LPC55_CERT_MAGIC: u32 = 0x7472_6563;
+
+/// Size of the LPC55 certificate block header.
+const LPC55_CERT_HEADER_LEN: usize = 0x20;
+
+/// Number of entries in the root key hash table.
+const LPC55_RKH_ENTRIES: usize = 4;
+
+/// Offset of the SECURE_BOOT_CFG word within the CMPA.
+const LPC55_CMPA_SECURE_BOOT_CFG: usize = 0x1c;
+
+/// Offset of the root key table hash within the CMPA.
+const LPC55_CMPA_RKTH: usize = 0x50;
+
+/// Size of the CMPA page.
+const LPC55_CMPA_LEN: usize = 512;
+
+/// SECURE_BOOT_CFG bits enabling secure boot.
+const LPC55_SECURE_BOOT_EN: u32 = 0b11 << 30;
+
+/// SECURE_BOOT_CFG bit restricting the ROM to RSA-4096 keys.
+const LPC55_RSA4K_ONLY: u32 = 0b01;
+
+/// Magic number ("HSIG") at the start of a detached signature.
+pub const DETACHED_MAGIC: u32 = 0x4749_5348;
+
+/// Version of the detached signature format that we emit.
+const DETACHED_VERSION: u32 = 1;
+
+/// Size of the detached signature header.
+const DETACHED_HEADER_LEN: usize = 24;
+
+/// Signature algorithms, as identified in a detached signature header.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Algorithm {
+    Rsa2048Sha256 = 1,
+    Rsa4096Sha256 = 2,
+    EcdsaP256Sha256 = 3,
+    EcdsaP384Sha384 = 4,
+}
+
+impl std::convert::TryFrom<u32> for Algorithm {
+    type Error = anyhow::Error;
+
+    fn try_from(x: u32) -> Result<Self> {
+        match x {
+            1 => Ok(Algorithm::Rsa2048Sha256),
+            2 => Ok(Algorithm::Rsa4096Sha256),
+            3 => Ok(Algorithm::EcdsaP256Sha256),
+            4 => Ok(Algorithm::EcdsaP384Sha384),
+            _ => bail!("unknown signature algorithm {}", x),
+        }
+    }
+}
+
+/// A private key, loaded from a PEM or DER file.
+pub enum SigningKey {
+    Rsa(RsaPrivateKey),
+    P256(p256::ecdsa::SigningKey),
+    P384(p384::ecdsa::SigningKey),
+}
+
+/// A public key, used to check signatures.
+pub enum VerifyingKey {
+    Rsa(RsaPublicKey),
+    P256(p256::ecdsa::VerifyingKey),
+    P384(p384::ecdsa::VerifyingKey),
+}
+
+impl SigningKey {
+    /// Loads a private key, which may be PKCS#1 (RSA), SEC1 (EC) or PKCS#8
+    /// (either), in PEM or DER form.
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+
+        let key = if contents.starts_with(b"-----BEGIN") {
+            let pem = std::str::from_utf8(&contents)?;
+            Self::from_pem(pem)
+        } else {
+            Self::from_der(&contents)
+        };
+
+        key.with_context(|| format!("unrecognized key in {}", path.display()))
+    }
+
+    fn from_pem(pem: &str) -> Result<Self> {
+        if let Ok(key) = RsaPrivateKey::from_pkcs1_pem(pem) {
+            return Ok(SigningKey::Rsa(key));
+        }
+        if let Ok(key) = RsaPrivateKey::from_pkcs8_pem(pem) {
+            return Ok(SigningKey::Rsa(key));
+        }
+        if let Ok(key) = p256::SecretKey::from_pkcs8_pem(pem) {
+            return Ok(SigningKey::P256(key.into()));
+        }
+        if let Ok(key) = p384::SecretKey::from_pkcs8_pem(pem) {
+            return Ok(SigningKey::P384(key.into()));
+        }
+        if let Ok(key) = p256::SecretKey::from_sec1_pem(pem) {
+            return Ok(SigningKey::P256(key.into()));
+        }
+        if let Ok(key) = p384::SecretKey::from_sec1_pem(pem) {
+            return Ok(SigningKey::P384(key.into()));
+        }
+        bail!("not an RSA, P-256 or P-384 private key");
+    }
+
+    fn from_der(der: &[u8]) -> Result<Self> {
+        if let Ok(key) = RsaPrivateKey::from_pkcs1_der(der) {
+            return Ok(SigningKey::Rsa(key));
+        }
+        if let Ok(key) = RsaPrivateKey::from_pkcs8_der(der) {
+            return Ok(SigningKey::Rsa(key));
+        }
+        if let Ok(key) = p256::SecretKey::from_pkcs8_der(der) {
+            return Ok(SigningKey::P256(key.into()));
+        }
+        if let Ok(key) = p384::SecretKey::from_pkcs8_der(der) {
+            return Ok(SigningKey::P384(key.into()));
+        }
+        if let Ok(key) = p256::SecretKey::from_sec1_der(der) {
+            return Ok(SigningKey::P256(key.into()));
+        }
+        if let Ok(key) = p384::SecretKey::from_sec1_der(der) {
+            return Ok(SigningKey::P384(key.into()));
+        }
+        bail!("not an RSA, P-256 or P-384 private key");
+    }
+
+    pub fn algorithm(&self) -> Result<Algorithm> {
+        match self {
+            SigningKey::Rsa(key) => rsa_algorithm(&key.to_public_key()),
+            SigningKey::P256(_) => Ok(Algorithm::EcdsaP256Sha256),
+            SigningKey::P384(_) => Ok(Algorithm::EcdsaP384Sha384),
+        }
+    }
+
+    pub fn verifying_key(&self) -> VerifyingKey {
+        match self {
+            SigningKey::Rsa(key) => VerifyingKey::Rsa(key.to_public_key()),
+            SigningKey::P256(key) => VerifyingKey::P256(key.verifying_key()),
+            SigningKey::P384(key) => VerifyingKey::P384(key.verifying_key()),
+        }
+    }
+
+    /// Signs `data`, returning the signature in its natural encoding: raw
+    /// PKCS#1 v1.5 for RSA, ASN.1 DER for ECDSA.
+    pub fn sign(&self, data: &[u8]) -> Result<Vec<u8>> {
+        use p256::ecdsa::signature::Signer;
+
+        match self {
+            SigningKey::Rsa(key) => {
+                let digest = Sha256::digest(data);
+                Ok(key.sign(rsa_padding(), &digest)?)
+            }
+            SigningKey::P256(key) => {
+                let sig: p256::ecdsa::Signature = key.try_sign(data)?;
+                Ok(sig.to_der().as_bytes().to_vec())
+            }
+            SigningKey::P384(key) => {
+                let sig: p384::ecdsa::Signature = key.try_sign(data)?;
+                Ok(sig.to_der().as_bytes().to_vec())
+            }
+        }
+    }
+}
+
+impl VerifyingKey {
+    fn from_encoded(algorithm: Algorithm, bytes: &[u8]) -> Result<Self> {
+        Ok(match algorithm {
+            Algorithm::Rsa2048Sha256 | Algorithm::Rsa4096Sha256 => {
+                VerifyingKey::Rsa(RsaPublicKey::from_pkcs1_der(bytes)?)
+            }
+            Algorithm::EcdsaP256Sha256 => VerifyingKey::P256(
+                p256::ecdsa::VerifyingKey::from_sec1_bytes(bytes)?,
+            ),
+            Algorithm::EcdsaP384Sha384 => VerifyingKey::P384(
+                p384::ecdsa::VerifyingKey::from_sec1_bytes(bytes)?,
+            ),
+        })
+    }
+
+    /// Encodes the key: PKCS#1 DER for RSA, an uncompressed SEC1 point for
+    /// ECDSA.
+    fn encode(&self) -> Result<Vec<u8>> {
+        Ok(match self {
+            VerifyingKey::Rsa(key) => key.to_pkcs1_der()?.as_ref().to_vec(),
+            VerifyingKey::P256(key) => {
+                key.to_encoded_point(false).as_bytes().to_vec()
+            }
+            VerifyingKey::P384(key) => {
+                key.to_encoded_point(false).as_bytes().to_vec()
+            }
+        })
+    }
+
+    pub fn verify(&self, data: &[u8], sig: &[u8]) -> Result<()> {
+        use p256::ecdsa::signature::Verifier;
+
+        match self {
+            VerifyingKey::Rsa(key) => {
+                let digest = Sha256::digest(data);
+                key.verify(rsa_padding(), &digest, sig)?;
+            }
+            VerifyingKey::P256(key) => {
+                let sig = p256::ecdsa::Signature::from_der(sig)?;
+                key.verify(data, &sig)?;
+            }
+            VerifyingKey::P384(key) => {
+                let sig = p384::ecdsa::Signature::from_der(sig)?;
+                key.verify(data, &sig)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn rsa_padding() -> PaddingScheme {
+    PaddingScheme::new_pkcs1v15_sign(Some(Hash::SHA2_256))
+}
+
+fn rsa_algorithm(key: &RsaPublicKey) -> Result<Algorithm> {
+    match key.size() * 8 {
+        2048 => Ok(Algorithm::Rsa2048Sha256),
+        4096 => Ok(Algorithm::Rsa4096Sha256),
+        bits => bail!("unsupported RSA key size: {} bits", bits),
+    }
+}
+
+/// Extracts the RSA public key from a DER-encoded X.509 certificate.
+fn cert_rsa_key(cert: &[u8]) -> Result<RsaPublicKey> {
+    let (_, cert) = x509_parser::parse_x509_certificate(cert)
+        .map_err(|e| anyhow!("failed to parse certificate: {}", e))?;
+    let spki = &cert.tbs_certificate.subject_pki;
+
+    Ok(RsaPublicKey::from_pkcs1_der(spki.subject_public_key.data)?)
+}
+
+/// Computes the root key hash of an RSA public key, as the LPC55 ROM does:
+/// SHA-256 over the big-endian modulus followed by the big-endian exponent.
+fn lpc55_rkh(key: &RsaPublicKey) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(key.n().to_bytes_be());
+    hasher.update(key.e().to_bytes_be());
+    hasher.finalize().into()
+}
+
+/// Produces an LPC55 signed image from `image` (a flat binary), signed with
+/// `key` and carrying the DER-encoded root certificate `root_cert`.  Returns
+/// the signed image and a matching CMPA page.
+pub fn lpc55_sign(
+    image: &[u8],
+    key: &SigningKey,
+    root_cert: &[u8],
+) -> Result<(Vec<u8>, Vec<u8>)> {
+    let key = match key {
+        SigningKey::Rsa(key) => key,
+        _ => bail!("LPC55 signed images require an RSA key"),
+    };
+    let algorithm = rsa_algorithm(&key.to_public_key())?;
+
+    if cert_rsa_key(root_cert)? != key.to_public_key() {
+        bail!("private key does not match root certificate");
+    }
+
+    if image.len() < LPC55_CERT_OFFSET_OFFSET + 4 {
+        bail!("image is too small to contain an LPC55 header");
+    }
+
+    // The certificate block must be word aligned.
+    let mut signed = image.to_vec();
+    signed.resize((image.len() + 3) & !3, 0);
+    let cert_offset = signed.len();
+
+    let mut cert = root_cert.to_vec();
+    cert.resize((root_cert.len() + 3) & !3, 0);
+
+    let rkh = lpc55_rkh(&key.to_public_key());
+    let cert_block_len =
+        LPC55_CERT_HEADER_LEN + 4 + cert.len() + LPC55_RKH_ENTRIES * rkh.len();
+    let total_len = cert_offset + cert_block_len;
+
+    LittleEndian::write_u32(
+        &mut signed[LPC55_IMAGE_LEN_OFFSET..],
+        total_len as u32,
+    );
+    LittleEndian::write_u32(
+        &mut signed[LPC55_IMAGE_TYPE_OFFSET..],
+        LPC55_IMAGE_TYPE_SIGNED,
+    );
+    LittleEndian::write_u32(
+        &mut signed[LPC55_CERT_OFFSET_OFFSET..],
+        cert_offset as u32,
+    );
+
+    let mut header = [0u8; LPC55_CERT_HEADER_LEN];
+    LittleEndian::write_u32(&mut header[0x00..], LPC55_CERT_MAGIC);
+    LittleEndian::write_u16(&mut header[0x04..], 1); // major version
+    LittleEndian::write_u16(&mut header[0x06..], 0); // minor version
+    LittleEndian::write_u32(&mut header[0x08..], LPC55_CERT_HEADER_LEN as u32);
+    LittleEndian::write_u32(&mut header[0x0c..], 0); // flags
+    LittleEndian::write_u32(&mut header[0x10..], 0); // build number
+    LittleEndian::write_u32(&mut header[0x14..], total_len as u32);
+    LittleEndian::write_u32(&mut header[0x18..], 1); // certificate count
+    LittleEndian::write_u32(&mut header[0x1c..], (cert.len() + 4) as u32);
+    signed.extend_from_slice(&header);
+
+    let mut len = [0u8; 4];
+    LittleEndian::write_u32(&mut len, cert.len() as u32);
+    signed.extend_from_slice(&len);
+    signed.extend_from_slice(&cert);
+
+    // We only have the one root key; the remaining slots are left zeroed.
+    let mut rkh_table = [0u8; LPC55_RKH_ENTRIES * 32];
+    rkh_table[..32].copy_from_slice(&rkh);
+    signed.extend_from_slice(&rkh_table);
+
+    let sig = key.sign(rsa_padding(), &Sha256::digest(&signed))?;
+    signed.extend_from_slice(&sig);
+
+    let mut cmpa = vec![0u8; LPC55_CMPA_LEN];
+    let mut secure_boot_cfg = LPC55_SECURE_BOOT_EN;
+    if algorithm == Algorithm::Rsa4096Sha256 {
+        secure_boot_cfg |= LPC55_RSA4K_ONLY;
+    }
+    LittleEndian::write_u32(
+        &mut cmpa[LPC55_CMPA_SECURE_BOOT_CFG..],
+        secure_boot_cfg,
+    );
+    cmpa[LPC55_CMPA_RKTH..LPC55_CMPA_RKTH + 32]
+        .copy_from_slice(&Sha256::digest(rkh_table));
+
+    Ok((signed, cmpa))
+}
+
+/// Checks an LPC55 signed image and its CMPA page, returning the signing
+/// algorithm on success.
+pub fn lpc55_verify(signed: &[u8], cmpa: &[u8]) -> Result<Algorithm> {
+    let word = |offset: usize| -> Result<usize> {
+        signed
+            .get(offset..offset + 4)
+            .map(|w| LittleEndian::read_u32(w) as usize)
+            .ok_or_else(|| anyhow!("image truncated at {:#x}", offset))
+    };
+
+    if word(LPC55_IMAGE_TYPE_OFFSET)? as u32 & 0xff != LPC55_IMAGE_TYPE_SIGNED {
+        bail!("image is not marked as signed");
+    }
+
+    let total_len = word(LPC55_IMAGE_LEN_OFFSET)?;
+    let cert_offset = word(LPC55_CERT_OFFSET_OFFSET)?;
+
+    if word(cert_offset)? as u32 != LPC55_CERT_MAGIC {
+        bail!("bad certificate block magic at {:#x}", cert_offset);
+    }
+    if word(cert_offset + 0x14)? != total_len {
+        bail!("certificate block disagrees with image length");
+    }
+    if word(cert_offset + 0x18)? != 1 {
+        bail!("only single-certificate chains are supported");
+    }
+
+    let cert_start = cert_offset + LPC55_CERT_HEADER_LEN;
+    let cert_len = word(cert_start)?;
+    let cert = signed
+        .get(cert_start + 4..cert_start + 4 + cert_len)
+        .ok_or_else(|| anyhow!("certificate truncated"))?;
+    let key = cert_rsa_key(cert)?;
+    let algorithm = rsa_algorithm(&key)?;
+
+    let rkh_start = cert_start + 4 + cert_len;
+    let rkh_table = signed
+        .get(rkh_start..rkh_start + LPC55_RKH_ENTRIES * 32)
+        .ok_or_else(|| anyhow!("root key table truncated"))?;
+    let rkh = lpc55_rkh(&key);
+    if !rkh_table.chunks(32).any(|h| h == rkh) {
+        bail!("certificate key is not in the root key table");
+    }
+
+    if cmpa.len() < LPC55_CMPA_RKTH + 32 {
+        bail!("CMPA is too short");
+    }
+    if cmpa[LPC55_CMPA_RKTH..LPC55_CMPA_RKTH + 32]
+        != Sha256::digest(rkh_table)[..]
+    {
+        bail!("CMPA root key table hash does not match image");
+    }
+
+    if signed.len() != total_len + key.size() {
+        bail!(
+            "image is {} bytes; expected {}",
+            signed.len(),
+            total_len + key.size()
+        );
+    }
+
+    let digest = Sha256::digest(&signed[..total_len]);
+    key.verify(rsa_padding(), &digest, &signed[total_len..])
+        .map_err(|e| anyhow!("bad signature: {}", e))?;
+
+    Ok(algorithm)
+}
+
+/// Produces a detached signature over `image`.
+///
+/// The format is a header of little-endian words (magic, version, algorithm,
+/// image length, key length, signature length), followed by the encoded
+/// public key and the signature.
+pub fn detached_sign(image: &[u8], key: &SigningKey) -> Result<Vec<u8>> {
+    let pubkey = key.verifying_key().encode()?;
+    let sig = key.sign(image)?;
+
+    let mut out = vec![0u8; DETACHED_HEADER_LEN];
+    LittleEndian::write_u32(&mut out[0x00..], DETACHED_MAGIC);
+    LittleEndian::write_u32(&mut out[0x04..], DETACHED_VERSION);
+    LittleEndian::write_u32(&mut out[0x08..], key.algorithm()? as u32);
+    LittleEndian::write_u32(&mut out[0x0c..], image.len() as u32);
+    LittleEndian::write_u32(&mut out[0x10..], pubkey.len() as u32);
+    LittleEndian::write_u32(&mut out[0x14..], sig.len() as u32);
+    out.extend_from_slice(&pubkey);
+    out.extend_from_slice(&sig);
+
+    Ok(out)
+}
+
+/// Checks a detached signature against `image`.  If `expected` is given, the
+/// signature must also have been made by that key (rather than merely by the
+/// key embedded in the signature).
+pub fn detached_verify(
+    image: &[u8],
+    sig: &[u8],
+    expected: Option<&VerifyingKey>,
+) -> Result<Algorithm> {
+    if sig.len() < DETACHED_HEADER_LEN {
+        bail!("signature is too short");
+    }
+    let word = |offset: usize| LittleEndian::read_u32(&sig[offset..]);
+
+    if word(0x00) != DETACHED_MAGIC {
+        bail!("bad signature magic {:#x}", word(0x00));
+    }
+    if word(0x04) != DETACHED_VERSION {
+        bail!("unsupported signature version {}", word(0x04));
+    }
+    let algorithm: Algorithm = word(0x08).try_into()?;
+    if word(0x0c) as usize != image.len() {
+        bail!(
+            "signature covers {} bytes, but image is {} bytes",
+            word(0x0c),
+            image.len()
+        );
+    }
+
+    let key_len = word(0x10) as usize;
+    let sig_len = word(0x14) as usize;
+    if sig.len() != DETACHED_HEADER_LEN + key_len + sig_len {
+        bail!("signature length does not match header");
+    }
+    let (pubkey, sig) = sig[DETACHED_HEADER_LEN..].split_at(key_len);
+
+    if let Some(expected) = expected {
+        if expected.encode()? != pubkey {
+            bail!("image was signed by an unexpected key");
+        }
+    }
+
+    VerifyingKey::from_encoded(algorithm, pubkey)?
+        .verify(image, sig)
+        .map_err(|e| anyhow!("bad signature: {}", e))?;
+
+    Ok(algorithm)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn fake_cert(name: &str) -> PathBuf {
+        [env!("CARGO_MANIFEST_DIR"), "../../support/fake_certs", name]
+            .iter()
+            .collect()
+    }
+
+    fn image() -> Vec<u8> {
+        (0..4096u32).map(|i| (i * 7 + 3) as u8).collect()
+    }
+
+    fn detached_roundtrip(key: &str, algorithm: Algorithm) {
+        let key = SigningKey::load(&fake_cert(key)).unwrap();
+        assert_eq!(key.algorithm().unwrap(), algorithm);
+
+        let image = image();
+        let sig = detached_sign(&image, &key).unwrap();
+        let verifying = key.verifying_key();
+
+        assert_eq!(detached_verify(&image, &sig, None).unwrap(), algorithm);
+        assert_eq!(
+            detached_verify(&image, &sig, Some(&verifying)).unwrap(),
+            algorithm
+        );
+
+        let mut tampered = image.clone();
+        tampered[0x100] ^= 1;
+        assert!(detached_verify(&tampered, &sig, None).is_err());
+
+        let mut bad = sig.clone();
+        let last = bad.len() - 1;
+        bad[last] ^= 1;
+        assert!(detached_verify(&image, &bad, None).is_err());
+    }
+
+    #[test]
+    fn detached_rsa2048() {
+        detached_roundtrip("fake_private_key.pem", Algorithm::Rsa2048Sha256);
+    }
+
+    #[test]
+    fn detached_rsa4096() {
+        detached_roundtrip(
+            "fake_private_key_4096.pem",
+            Algorithm::Rsa4096Sha256,
+        );
+    }
+
+    #[test]
+    fn detached_p256() {
+        detached_roundtrip("p256-private-key.der", Algorithm::EcdsaP256Sha256);
+    }
+
+    #[test]
+    fn detached_p384() {
+        detached_roundtrip("p384-private-key.der", Algorithm::EcdsaP384Sha384);
+    }
+
+    #[test]
+    fn detached_wrong_key() {
+        let key = SigningKey::load(&fake_cert("p256-private-key.der")).unwrap();
+        let other = SigningKey::load(&fake_cert("p384-private-key.der"))
+            .unwrap()
+            .verifying_key();
+
+        let image = image();
+        let sig = detached_sign(&image, &key).unwrap();
+        assert!(detached_verify(&image, &sig, Some(&other)).is_err());
+    }
+
+    fn lpc55_roundtrip(key: &str, cert: &str, algorithm: Algorithm) {
+        let signing = SigningKey::load(&fake_cert(key)).unwrap();
+        let root_cert = std::fs::read(fake_cert(cert)).unwrap();
+        let (mut signed, cmpa) =
+            lpc55_sign(&image(), &signing, &root_cert).unwrap();
+
+        assert_eq!(lpc55_verify(&signed, &cmpa).unwrap(), algorithm);
+
+        signed[0x100] ^= 1;
+        assert!(lpc55_verify(&signed, &cmpa).is_err());
+    }
+
+    /// Checks that images produced by the `lpc55_sign` crate (which we used
+    /// before signing natively) pass our verifier.
+    fn lpc55_sign_crate(key: &str, cert: &str, algorithm: Algorithm) {
+        let dir = std::env::temp_dir().join(format!(
+            "xtask-sign-{}-{}",
+            std::process::id(),
+            key
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        std::fs::write(dir.join("image.bin"), image()).unwrap();
+        lpc55_sign::signed_image::sign_image(
+            false,
+            &dir.join("image.bin"),
+            &fake_cert(key),
+            &fake_cert(cert),
+            &dir.join("image_rsa.bin"),
+            &dir.join("CMPA.bin"),
+        )
+        .unwrap();
+
+        let signed = std::fs::read(dir.join("image_rsa.bin")).unwrap();
+        let cmpa = std::fs::read(dir.join("CMPA.bin")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(lpc55_verify(&signed, &cmpa).unwrap(), algorithm);
+    }
+
+    #[test]
+    fn lpc55_rsa2048() {
+        lpc55_roundtrip(
+            "fake_private_key.pem",
+            "fake_certificate.der.crt",
+            Algorithm::Rsa2048Sha256,
+        );
+        lpc55_sign_crate(
+            "fake_private_key.pem",
+            "fake_certificate.der.crt",
+            Algorithm::Rsa2048Sha256,
+        );
+    }
+
+    #[test]
+    fn lpc55_rsa4096() {
+        lpc55_roundtrip(
+            "fake_private_key_4096.pem",
+            "fake_certificate_4096.der.crt",
+            Algorithm::Rsa4096Sha256,
+        );
+        lpc55_sign_crate(
+            "fake_private_key_4096.pem",
+            "fake_certificate_4096.der.crt",
+            Algorithm::Rsa4096Sha256,
+        );
+    }
+
+    #[test]
+    fn lpc55_wrong_key() {
+        let signing = SigningKey::load(&fake_cert("fake_private_key.pem"));
+        let root_cert =
+            std::fs::read(fake_cert("fake_certificate_4096.der.crt")).unwrap();
+        assert!(lpc55_sign(&image(), &signing.unwrap(), &root_cert).is_err());
+
+        let ecdsa = SigningKey::load(&fake_cert("p256-private-key.der"));
+        let root_cert =
+            std::fs::read(fake_cert("fake_certificate.der.crt")).unwrap();
+        assert!(lpc55_sign(&image(), &ecdsa.unwrap(), &root_cert).is_err());
+    }
+}
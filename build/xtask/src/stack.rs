@@ -0,0 +1,634 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Static stack depth analysis.
+//!
+//! For each task, we disassemble every function in the ELF, determine the
+//! size of its stack frame, and build a call graph from its direct calls.
+//! The worst-case stack depth is the most expensive path through that graph
+//! from the entry point.
+//!
+//! Frame sizes come from the `.stack_sizes` section when the task was built
+//! with `-Z emit-stack-sizes`; otherwise (and for any function the section
+//! doesn't cover, like hand-written assembly) we add up the stack
+//! adjustments in the function's prologue.
+//!
+//! This is necessarily a lower bound: indirect branches (calls through trait
+//! objects or function pointers, but also jump tables and computed jumps)
+//! can't be followed, and recursion makes the depth unbounded.  Both are
+//! flagged in the report.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use capstone::arch::arm::{ArchExtraMode, ArchMode};
+use capstone::prelude::*;
+use goblin::elf::sym::STT_FUNC;
+use termcolor::{Color, ColorSpec, WriteColor};
+
+use crate::{elf, Config};
+
+/// Everything we know about a single function.
+#[derive(Debug, Default)]
+struct Function {
+    name: String,
+    /// Bytes of stack used by this function itself
+    frame: u32,
+    /// Whether the frame size is dynamic (e.g., `sub sp, sp, r0`), and thus
+    /// `frame` is only a lower bound
+    dynamic: bool,
+    /// Addresses of functions called directly (including tail calls)
+    calls: BTreeSet<u32>,
+    /// Whether this function makes any indirect calls or jumps (including
+    /// through jump tables), and thus may reach code we know nothing about
+    indirect: bool,
+}
+
+/// The result of walking the call graph from a single function.
+#[derive(Clone, Debug, Default)]
+struct Depth {
+    /// Worst-case stack depth
+    depth: u32,
+    /// Call path that results in `depth`
+    path: Vec<u32>,
+    /// Whether any function reachable from here recurses
+    recursive: bool,
+    /// Whether any function reachable from here has an indirect branch
+    indirect: bool,
+    /// Whether any function reachable from here has a dynamic frame
+    dynamic: bool,
+}
+
+pub fn run(cfg: &Path, verbose: bool) -> Result<()> {
+    let toml = Config::from_file(&cfg)?;
+
+    let mut dist_dir = PathBuf::from("target");
+    dist_dir.push(&toml.name);
+    dist_dir.push("dist");
+
+    let color_choice = if atty::is(atty::Stream::Stdout) {
+        termcolor::ColorChoice::Auto
+    } else {
+        termcolor::ColorChoice::Never
+    };
+    let mut out = termcolor::StandardStream::stdout(color_choice);
+
+    let mut overflows = vec![];
+
+    for (name, task) in &toml.tasks {
+        let stacksize = task.stacksize.or(toml.stacksize).ok_or_else(|| {
+            anyhow!("{}: no stack size specified and there is no default", name)
+        })?;
+        let buffer = std::fs::read(dist_dir.join(name))?;
+        let functions = analyze(&buffer)
+            .with_context(|| format!("failed to analyze {}", name))?;
+
+        let elf = goblin::elf::Elf::parse(&buffer)?;
+        let entry = elf.header.e_entry as u32 & !1;
+
+        let mut memo = BTreeMap::new();
+        let depth = walk(entry, &functions, &mut memo, &mut vec![]);
+
+        write!(
+            out,
+            "{:<16} {:>6} / {:>6} bytes",
+            name, depth.depth, stacksize
+        )?;
+
+        let mut color = ColorSpec::new();
+        if depth.depth > stacksize {
+            color.set_fg(Some(Color::Red)).set_bold(true);
+            out.set_color(&color)?;
+            write!(out, " OVERFLOW")?;
+            overflows.push(name.clone());
+        } else if depth.depth * 10 > stacksize * 9 {
+            color.set_fg(Some(Color::Yellow));
+            out.set_color(&color)?;
+            write!(out, " (within 10%)")?;
+        }
+        out.reset()?;
+
+        let mut flags = vec![];
+        if depth.recursive {
+            flags.push("recursion");
+        }
+        if depth.indirect {
+            flags.push("indirect branches");
+        }
+        if depth.dynamic {
+            flags.push("dynamic frames");
+        }
+        if !flags.is_empty() {
+            write!(out, " [lower bound: {}]", flags.join(", "))?;
+        }
+        writeln!(out)?;
+
+        if verbose {
+            for addr in &depth.path {
+                let f = &functions[addr];
+                writeln!(
+                    out,
+                    "    {:>6}  {:08x} {}{}",
+                    f.frame,
+                    addr,
+                    f.name,
+                    if f.dynamic { " (dynamic)" } else { "" }
+                )?;
+            }
+
+            for (addr, f) in &functions {
+                if f.indirect && memo.contains_key(addr) {
+                    writeln!(out, "    indirect branch in {}", f.name)?;
+                }
+            }
+        }
+    }
+
+    if !overflows.is_empty() {
+        bail!(
+            "worst-case stack depth exceeds stacksize for: {}",
+            overflows.join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+/// Disassembles every function in the given ELF image, returning a map from
+/// function address to what we learned about it.
+fn analyze(buffer: &[u8]) -> Result<BTreeMap<u32, Function>> {
+    let elf = goblin::elf::Elf::parse(buffer)?;
+
+    let cs = Capstone::new()
+        .arm()
+        .mode(ArchMode::Thumb)
+        .extra_mode([ArchExtraMode::MClass].iter().copied())
+        .build()
+        .map_err(|e| anyhow!("failed to initialize disassembler: {}", e))?;
+
+    let sizes = match elf::get_section_by_name(&elf, ".stack_sizes") {
+        Some(section) => {
+            let offset = section.sh_offset as usize;
+            let data = buffer
+                .get(offset..offset + section.sh_size as usize)
+                .ok_or_else(|| anyhow!(".stack_sizes is truncated"))?;
+            parse_stack_sizes(data)?
+        }
+        None => BTreeMap::new(),
+    };
+
+    let mut functions = BTreeMap::new();
+    let mut extents = BTreeMap::new();
+
+    for sym in elf.syms.iter() {
+        if sym.st_type() != STT_FUNC || sym.st_size == 0 {
+            continue;
+        }
+
+        let addr = sym.st_value as u32 & !1;
+        let name = elf.strtab.get_at(sym.st_name).unwrap_or("<unknown>");
+
+        extents.insert(addr, addr + sym.st_size as u32);
+        functions.insert(
+            addr,
+            Function {
+                name: name.to_string(),
+                ..Default::default()
+            },
+        );
+    }
+
+    // Finds the function containing the given address, if any.
+    let containing = |target: u32| -> Option<u32> {
+        extents
+            .range(..=target)
+            .next_back()
+            .filter(|(_, &end)| target < end)
+            .map(|(&start, _)| start)
+    };
+
+    for (&addr, &end) in &extents {
+        let section = match elf::get_section_by_vma(&elf, addr as u64) {
+            Some(section) => section,
+            None => continue,
+        };
+
+        let offset =
+            (addr as u64 - section.sh_addr + section.sh_offset) as usize;
+        let len = (end - addr) as usize;
+        let code = buffer
+            .get(offset..offset + len)
+            .ok_or_else(|| anyhow!("function at {:#x} is truncated", addr))?;
+
+        let f = functions.get_mut(&addr).unwrap();
+
+        // Walk instruction by instruction, rather than with `disasm_all`, so
+        // that we can step over literal pools and anything else that isn't
+        // code.
+        let mut pos = 0;
+        let mut in_prologue = true;
+
+        while pos < code.len() {
+            let insns = cs
+                .disasm_count(&code[pos..], (addr as usize + pos) as u64, 1)
+                .map_err(|e| anyhow!("disassembly failed: {}", e))?;
+
+            let insn = match insns.iter().next() {
+                Some(insn) => insn,
+                None => {
+                    pos += 2;
+                    continue;
+                }
+            };
+            pos += insn.bytes().len();
+
+            let mnemonic = insn.mnemonic().unwrap_or("");
+            let ops = insn.op_str().unwrap_or("");
+
+            // We only count stack adjustments made before the first branch;
+            // anything after that is (we hope) restoring the frame.
+            if in_prologue {
+                match prologue_adjustment(mnemonic, ops) {
+                    Some(Adjustment::Fixed(n)) => f.frame += n,
+                    Some(Adjustment::Dynamic) => f.dynamic = true,
+                    None => {}
+                }
+            }
+
+            match branch(mnemonic, ops) {
+                Some(Branch::Call(target)) => {
+                    in_prologue = false;
+                    if let Some(callee) = containing(target) {
+                        f.calls.insert(callee);
+                    }
+                }
+                Some(Branch::Jump(target)) => {
+                    in_prologue = false;
+                    // A branch out of this function is a tail call.
+                    if target < addr || target >= end {
+                        if let Some(callee) = containing(target) {
+                            f.calls.insert(callee);
+                        }
+                    }
+                }
+                Some(Branch::Indirect) => {
+                    in_prologue = false;
+                    f.indirect = true;
+                }
+                Some(Branch::Return) => in_prologue = false,
+                None => {}
+            }
+        }
+
+        // The compiler knows better than our prologue analysis.
+        if let Some(&size) = sizes.get(&addr) {
+            f.frame = size;
+        }
+    }
+
+    Ok(functions)
+}
+
+/// Parses the contents of a `.stack_sizes` section: a sequence of function
+/// addresses (32-bit, little-endian) each followed by the ULEB128-encoded
+/// size of that function's static stack frame.
+fn parse_stack_sizes(data: &[u8]) -> Result<BTreeMap<u32, u32>> {
+    let mut sizes = BTreeMap::new();
+    let mut pos = 0;
+
+    while pos < data.len() {
+        let addr = data
+            .get(pos..pos + 4)
+            .map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]]))
+            .ok_or_else(|| anyhow!(".stack_sizes truncated at {:#x}", pos))?;
+        pos += 4;
+
+        let mut size = 0u32;
+        let mut shift = 0;
+
+        loop {
+            let byte = *data.get(pos).ok_or_else(|| {
+                anyhow!(".stack_sizes truncated at {:#x}", pos)
+            })?;
+            pos += 1;
+
+            if shift >= 32 {
+                bail!(".stack_sizes entry for {:#x} is too large", addr);
+            }
+
+            size |= ((byte & 0x7f) as u32) << shift;
+            shift += 7;
+
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+
+        sizes.insert(addr & !1, size);
+    }
+
+    Ok(sizes)
+}
+
+#[derive(Debug, PartialEq)]
+enum Adjustment {
+    Fixed(u32),
+    Dynamic,
+}
+
+/// Parses an immediate operand, e.g. `#0x20` or `#32`.
+fn immediate(op: &str) -> Option<u32> {
+    let op = op.trim().strip_prefix('#')?;
+    match op.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => op.parse().ok(),
+    }
+}
+
+/// Counts the registers in a register list, e.g. `{r4, r5, r7, lr}`.
+fn register_count(ops: &str) -> Option<u32> {
+    let list = ops.split('{').nth(1)?.split('}').next()?;
+    Some(list.split(',').filter(|r| !r.trim().is_empty()).count() as u32)
+}
+
+/// Determines how much a prologue instruction grows the stack, if at all.
+fn prologue_adjustment(mnemonic: &str, ops: &str) -> Option<Adjustment> {
+    let mnemonic = mnemonic.trim_end_matches(".w");
+
+    match mnemonic {
+        "push" => Some(Adjustment::Fixed(register_count(ops)? * 4)),
+        "stmdb" | "stmfd" if ops.starts_with("sp!") => {
+            Some(Adjustment::Fixed(register_count(ops)? * 4))
+        }
+        "vpush" => {
+            let per_reg = if ops.contains('d') { 8 } else { 4 };
+            Some(Adjustment::Fixed(register_count(ops)? * per_reg))
+        }
+        "str" if ops.contains("[sp, #-") && ops.ends_with("]!") => {
+            let imm = ops.split("#-").nth(1)?.trim_end_matches("]!");
+            Some(Adjustment::Fixed(immediate(&format!("#{}", imm))?))
+        }
+        "sub" | "subw" if ops.starts_with("sp,") => {
+            let operand = ops.rsplit(',').next()?;
+            match immediate(operand) {
+                Some(n) => Some(Adjustment::Fixed(n)),
+                None => Some(Adjustment::Dynamic),
+            }
+        }
+        _ => None,
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Branch {
+    /// A direct call (`bl`, `blx #imm`)
+    Call(u32),
+    /// A direct, unconditional or conditional jump (`b`, `cbz`, ...)
+    Jump(u32),
+    /// A call or jump through a register, a load or move into `pc`, or a
+    /// table branch (`tbb`, `tbh`)
+    Indirect,
+    /// A return (`bx lr`, a `pop` or `ldm` that includes `pc`, or a load of
+    /// `pc` from the stack)
+    Return,
+}
+
+fn branch(mnemonic: &str, ops: &str) -> Option<Branch> {
+    let base = mnemonic.trim_end_matches(".w").trim_end_matches(".n");
+
+    if (base == "pop" || base.starts_with("ldm")) && ops.contains("pc") {
+        return Some(Branch::Return);
+    }
+
+    // Anything else that writes `pc` is a jump we can't follow -- save for
+    // popping `pc` off the stack, which is a return.
+    if ops.starts_with("pc,") {
+        return match base {
+            "ldr" if ops.contains("[sp]") => Some(Branch::Return),
+            "ldr" | "mov" | "add" => Some(Branch::Indirect),
+            _ => None,
+        };
+    }
+
+    match base {
+        "bl" | "blx" => match immediate(ops) {
+            Some(target) => Some(Branch::Call(target)),
+            None => Some(Branch::Indirect),
+        },
+        "bx" => {
+            if ops.trim() == "lr" {
+                Some(Branch::Return)
+            } else {
+                Some(Branch::Indirect)
+            }
+        }
+        "tbb" | "tbh" => Some(Branch::Indirect),
+        "cbz" | "cbnz" => {
+            Some(Branch::Jump(immediate(ops.rsplit(',').next()?)?))
+        }
+        _ if base == "b" || (base.starts_with('b') && base.len() == 3) => {
+            // Covers `b` and its conditional forms (`beq`, `bne`, ...); the
+            // remaining three-letter b-mnemonics (`bic`, `bfc`, `bfi`, `bkpt`)
+            // don't take an immediate address and so fall through.
+            immediate(ops).map(Branch::Jump)
+        }
+        _ => None,
+    }
+}
+
+/// Computes the worst-case stack depth from the function at `addr`.  `stack`
+/// holds the functions on the current path, for recursion detection; `memo`
+/// caches results for functions we've already visited.
+///
+/// Every result is cached, including those computed while a cycle is open.
+/// The depth of a function within a cycle then depends on where we entered
+/// the cycle -- but it is only a lower bound in any case, and every function
+/// in the cycle is (correctly) marked as recursive, as each can reach the
+/// function that was on the stack when the cycle was found.
+fn walk(
+    addr: u32,
+    functions: &BTreeMap<u32, Function>,
+    memo: &mut BTreeMap<u32, Depth>,
+    stack: &mut Vec<u32>,
+) -> Depth {
+    if let Some(depth) = memo.get(&addr) {
+        return depth.clone();
+    }
+
+    let f = match functions.get(&addr) {
+        Some(f) => f,
+        None => return Depth::default(),
+    };
+
+    if stack.contains(&addr) {
+        return Depth {
+            recursive: true,
+            ..Default::default()
+        };
+    }
+
+    stack.push(addr);
+
+    let mut worst = Depth::default();
+    let mut recursive = false;
+    let mut indirect = f.indirect;
+    let mut dynamic = f.dynamic;
+
+    for &callee in &f.calls {
+        let d = walk(callee, functions, memo, stack);
+        recursive |= d.recursive;
+        indirect |= d.indirect;
+        dynamic |= d.dynamic;
+        if d.depth > worst.depth || worst.path.is_empty() {
+            worst = d;
+        }
+    }
+
+    stack.pop();
+
+    let mut path = vec![addr];
+    path.extend(worst.path);
+
+    let depth = Depth {
+        depth: f.frame + worst.depth,
+        path,
+        recursive,
+        indirect,
+        dynamic,
+    };
+
+    memo.insert(addr, depth.clone());
+    depth
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn immediates() {
+        assert_eq!(immediate("#0x20"), Some(0x20));
+        assert_eq!(immediate(" #32"), Some(32));
+        assert_eq!(immediate("r3"), None);
+        assert_eq!(immediate("#0x800012c"), Some(0x0800_012c));
+    }
+
+    #[test]
+    fn prologues() {
+        assert_eq!(
+            prologue_adjustment("push", "{r4, r5, r7, lr}"),
+            Some(Adjustment::Fixed(16))
+        );
+        assert_eq!(
+            prologue_adjustment("push.w", "{r4, r5, r6, r7, r8, r9, lr}"),
+            Some(Adjustment::Fixed(28))
+        );
+        assert_eq!(
+            prologue_adjustment("stmdb", "sp!, {r4, r5, r6, lr}"),
+            Some(Adjustment::Fixed(16))
+        );
+        assert_eq!(
+            prologue_adjustment("sub", "sp, #0x10"),
+            Some(Adjustment::Fixed(0x10))
+        );
+        assert_eq!(
+            prologue_adjustment("sub.w", "sp, sp, #0x218"),
+            Some(Adjustment::Fixed(0x218))
+        );
+        assert_eq!(
+            prologue_adjustment("subw", "sp, sp, #0x404"),
+            Some(Adjustment::Fixed(0x404))
+        );
+        assert_eq!(
+            prologue_adjustment("sub.w", "sp, sp, r0"),
+            Some(Adjustment::Dynamic)
+        );
+        assert_eq!(
+            prologue_adjustment("vpush", "{d8, d9, d10}"),
+            Some(Adjustment::Fixed(24))
+        );
+        assert_eq!(
+            prologue_adjustment("vpush", "{s16, s17}"),
+            Some(Adjustment::Fixed(8))
+        );
+        assert_eq!(
+            prologue_adjustment("str", "lr, [sp, #-0x8]!"),
+            Some(Adjustment::Fixed(8))
+        );
+        assert_eq!(prologue_adjustment("sub", "r0, r1, #4"), None);
+        assert_eq!(prologue_adjustment("mov", "r7, sp"), None);
+    }
+
+    #[test]
+    fn branches() {
+        assert_eq!(branch("bl", "#0x8000120"), Some(Branch::Call(0x0800_0120)));
+        assert_eq!(
+            branch("blx", "#0x8000124"),
+            Some(Branch::Call(0x0800_0124))
+        );
+        assert_eq!(branch("blx", "r3"), Some(Branch::Indirect));
+        assert_eq!(
+            branch("b.w", "#0x8000200"),
+            Some(Branch::Jump(0x0800_0200))
+        );
+        assert_eq!(branch("b", "#0x8000204"), Some(Branch::Jump(0x0800_0204)));
+        assert_eq!(
+            branch("bne.w", "#0x8000208"),
+            Some(Branch::Jump(0x0800_0208))
+        );
+        assert_eq!(
+            branch("cbz", "r0, #0x800020c"),
+            Some(Branch::Jump(0x0800_020c))
+        );
+        assert_eq!(branch("bx", "lr"), Some(Branch::Return));
+        assert_eq!(branch("bx", "r2"), Some(Branch::Indirect));
+        assert_eq!(branch("pop", "{r4, r5, r7, pc}"), Some(Branch::Return));
+        assert_eq!(branch("pop.w", "{r4, r5, r7, lr}"), None);
+        assert_eq!(branch("ldr", "pc, [sp], #4"), Some(Branch::Return));
+        assert_eq!(branch("ldr", "pc, [r0, #4]"), Some(Branch::Indirect));
+        assert_eq!(branch("tbb", "[pc, r0]"), Some(Branch::Indirect));
+        assert_eq!(branch("bic", "r0, r0, #1"), None);
+    }
+
+    #[test]
+    fn stack_sizes() {
+        let data = [
+            0x01, 0x01, 0x00, 0x08, 0x10, // 0x08000101: 16
+            0x40, 0x02, 0x00, 0x08, 0x98, 0x04, // 0x08000240: 0x218
+            0x81, 0x03, 0x00, 0x08, 0x00, // 0x08000381: 0
+        ];
+        let sizes = parse_stack_sizes(&data).unwrap();
+
+        assert_eq!(sizes.len(), 3);
+        assert_eq!(sizes[&0x0800_0100], 16);
+        assert_eq!(sizes[&0x0800_0240], 0x218);
+        assert_eq!(sizes[&0x0800_0380], 0);
+
+        assert!(parse_stack_sizes(&data[..4]).is_err());
+        assert!(parse_stack_sizes(&[0, 0, 0, 8, 0x80]).is_err());
+    }
+
+    #[test]
+    fn recursion() {
+        let function = |frame, calls: &[u32]| Function {
+            frame,
+            calls: calls.iter().copied().collect(),
+            ..Default::default()
+        };
+
+        let mut functions = BTreeMap::new();
+        functions.insert(1, function(8, &[2, 3]));
+        functions.insert(2, function(32, &[]));
+        functions.insert(3, function(16, &[4]));
+        functions.insert(4, function(4, &[3]));
+
+        let depth = walk(1, &functions, &mut BTreeMap::new(), &mut vec![]);
+        assert_eq!(depth.depth, 40);
+        assert_eq!(depth.path, vec![1, 2]);
+        assert!(depth.recursive);
+        assert!(!depth.indirect);
+    }
+}
@@ -2,12 +2,29 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader};
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
 
-use anyhow::Context;
+use anyhow::{bail, Context};
 
-use crate::Config;
+use crate::{qemu, Config};
+
+/// Where (if anywhere) to write reports of an emulated test run.
+#[derive(Debug, Default)]
+pub struct Reports {
+    pub junit: Option<PathBuf>,
+    pub tap: Option<PathBuf>,
+}
+
+impl Reports {
+    pub fn is_empty(&self) -> bool {
+        self.junit.is_none() && self.tap.is_none()
+    }
+}
 
 pub fn run(verbose: bool, cfg: &Path) -> anyhow::Result<()> {
     let toml = Config::from_file(&cfg)?;
@@ -36,3 +53,382 @@ pub fn run(verbose: bool, cfg: &Path) -> anyhow::Result<()> {
 
     Ok(())
 }
+
+/// Runs the test image for `cfg` (which must already have been built) under
+/// QEMU, reading the test runner's output from semihosting.  The test runner
+/// must have been built with its `semihosting-exit` feature, so that QEMU
+/// exits when the suite is done; if it hasn't exited after `timeout`, it is
+/// killed and whichever case was running is blamed.
+pub fn run_qemu(
+    verbose: bool,
+    cfg: &Path,
+    timeout: Duration,
+    reports: &Reports,
+) -> anyhow::Result<()> {
+    let toml = Config::from_file(&cfg)?;
+    let mut qemu = qemu::command(&toml)?;
+
+    if verbose {
+        println!("running {:?}", qemu);
+    }
+
+    let mut child = qemu
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("failed to run {:?}", qemu))?;
+
+    // Read output on another thread so that we can give up on a hung image.
+    let stdout = child.stdout.take().unwrap();
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            match line {
+                Ok(line) => {
+                    if tx.send(line).is_err() {
+                        break;
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+    });
+
+    let deadline = Instant::now() + timeout;
+    let mut suite = Suite::default();
+    let mut timed_out = false;
+
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+
+        match rx.recv_timeout(remaining) {
+            Ok(line) => {
+                if verbose {
+                    println!("{}", line);
+                }
+
+                if let Some(case) = suite.parse(&line) {
+                    let case = &suite.cases[case];
+                    println!("{:<40} {}", case.name, case.outcome);
+                }
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                timed_out = true;
+                child.kill().context("failed to kill QEMU")?;
+                break;
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        }
+    }
+
+    let status = child.wait()?;
+    suite.finish(timed_out);
+
+    if let Some(path) = &reports.junit {
+        std::fs::write(path, suite.junit(&toml.name))
+            .with_context(|| format!("failed to write {}", path.display()))?;
+    }
+
+    if let Some(path) = &reports.tap {
+        std::fs::write(path, suite.tap())
+            .with_context(|| format!("failed to write {}", path.display()))?;
+    }
+
+    let failed = suite
+        .cases
+        .iter()
+        .filter(|c| c.outcome != Outcome::Pass)
+        .count();
+
+    println!(
+        "{} passed, {} failed, {} total",
+        suite.cases.len() - failed,
+        failed,
+        suite.cases.len()
+    );
+
+    if timed_out {
+        bail!("test image did not finish within {:?}", timeout);
+    }
+
+    if suite.done.is_none() {
+        bail!("test image exited ({}) before the suite finished", status);
+    }
+
+    if failed != 0 || suite.cases.len() != suite.expected {
+        bail!("test failed");
+    }
+
+    Ok(())
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Outcome {
+    /// The case was never started.  As the suite is expected to run every
+    /// case, this is reported as a failure.
+    NotRun,
+    /// The case was started, but never finished.
+    Hung,
+    Pass,
+    Fail,
+}
+
+impl std::fmt::Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let s = match self {
+            Outcome::NotRun => "not run",
+            Outcome::Hung => "HUNG",
+            Outcome::Pass => "ok",
+            Outcome::Fail => "FAIL",
+        };
+
+        f.write_str(s)
+    }
+}
+
+#[derive(Debug)]
+struct Case {
+    name: String,
+    outcome: Outcome,
+    /// Other output (e.g., fault details) logged while the case was running.
+    output: Vec<String>,
+    started: Option<Instant>,
+    elapsed: Option<Duration>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Phase {
+    Boot,
+    Meta,
+    Run,
+    Done,
+}
+
+impl Default for Phase {
+    fn default() -> Self {
+        Phase::Boot
+    }
+}
+
+/// The results of a run of the test suite, as parsed from the test runner's
+/// output; see `test/test-runner` for the format.
+#[derive(Debug, Default)]
+struct Suite {
+    phase: Phase,
+    expected: usize,
+    cases: Vec<Case>,
+    running: Option<usize>,
+    done: Option<bool>,
+}
+
+impl Suite {
+    /// Parses one line of output, returning the index of the case that it
+    /// finished, if any.
+    fn parse(&mut self, line: &str) -> Option<usize> {
+        let line = line.trim_end();
+        let (word, rest) = match line.split_once(' ') {
+            Some((word, rest)) => (word, rest.trim()),
+            None => (line, ""),
+        };
+
+        match (self.phase, word) {
+            (_, "meta") => {
+                // The runner restarts the suite from scratch (e.g., if the
+                // image was reset); forget anything we've seen so far.
+                *self = Suite {
+                    phase: Phase::Meta,
+                    ..Default::default()
+                };
+            }
+            (Phase::Meta, "expect") => {
+                self.expected = rest.parse().unwrap_or(0);
+            }
+            (Phase::Meta, "case") => {
+                self.cases.push(Case {
+                    name: rest.to_string(),
+                    outcome: Outcome::NotRun,
+                    output: vec![],
+                    started: None,
+                    elapsed: None,
+                });
+            }
+            (Phase::Meta, "run") => {
+                self.phase = Phase::Run;
+            }
+            (Phase::Run, "start") => {
+                let index = self.case(rest);
+                let case = &mut self.cases[index];
+                case.outcome = Outcome::Hung;
+                case.started = Some(Instant::now());
+                self.running = Some(index);
+            }
+            (Phase::Run, "finish") => {
+                let (status, name) = rest.split_once(' ').unwrap_or((rest, ""));
+                let index = self.case(name.trim());
+                let case = &mut self.cases[index];
+                case.outcome = if status == "ok" {
+                    Outcome::Pass
+                } else {
+                    Outcome::Fail
+                };
+                case.elapsed = case.started.map(|s| s.elapsed());
+                self.running = None;
+                return Some(index);
+            }
+            (Phase::Run, "done") => {
+                self.done = Some(rest == "pass" || rest == "ok");
+                self.phase = Phase::Done;
+            }
+            _ => {
+                if let Some(index) = self.running {
+                    self.cases[index].output.push(line.to_string());
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Returns the index of the case named `name`, adding it if the runner
+    /// didn't announce it up front.
+    fn case(&mut self, name: &str) -> usize {
+        match self.cases.iter().position(|c| c.name == name) {
+            Some(index) => index,
+            None => {
+                self.cases.push(Case {
+                    name: name.to_string(),
+                    outcome: Outcome::NotRun,
+                    output: vec![],
+                    started: None,
+                    elapsed: None,
+                });
+                self.cases.len() - 1
+            }
+        }
+    }
+
+    /// Accounts for a run that ended before the suite did.
+    fn finish(&mut self, timed_out: bool) {
+        if let Some(index) = self.running.take() {
+            let case = &mut self.cases[index];
+            case.elapsed = case.started.map(|s| s.elapsed());
+            case.output.push(if timed_out {
+                "timed out".to_string()
+            } else {
+                "QEMU exited while running".to_string()
+            });
+        }
+    }
+
+    fn junit(&self, name: &str) -> String {
+        let failures = self
+            .cases
+            .iter()
+            .filter(|c| c.outcome != Outcome::Pass)
+            .count();
+        let time: f64 = self
+            .cases
+            .iter()
+            .filter_map(|c| c.elapsed)
+            .map(|e| e.as_secs_f64())
+            .sum();
+
+        let mut out = String::new();
+        writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#).unwrap();
+        writeln!(out, "<testsuites>").unwrap();
+        writeln!(
+            out,
+            r#"  <testsuite name="{}" tests="{}" failures="{}" errors="0" time="{:.3}">"#,
+            xml_escape(name),
+            self.cases.len(),
+            failures,
+            time,
+        )
+        .unwrap();
+
+        for case in &self.cases {
+            write!(
+                out,
+                r#"    <testcase name="{}" classname="{}" time="{:.3}""#,
+                xml_escape(&case.name),
+                xml_escape(name),
+                case.elapsed.map(|e| e.as_secs_f64()).unwrap_or(0.0),
+            )
+            .unwrap();
+
+            match case.outcome {
+                Outcome::Pass => {
+                    writeln!(out, "/>").unwrap();
+                    continue;
+                }
+                Outcome::NotRun | Outcome::Fail | Outcome::Hung => {
+                    writeln!(
+                        out,
+                        r#">
+      <failure message="{}">{}</failure>"#,
+                        case.outcome,
+                        xml_escape(&case.output.join("\n")),
+                    )
+                    .unwrap();
+                }
+            }
+
+            writeln!(out, "    </testcase>").unwrap();
+        }
+
+        writeln!(out, "  </testsuite>").unwrap();
+        writeln!(out, "</testsuites>").unwrap();
+        out
+    }
+
+    fn tap(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "TAP version 13").unwrap();
+        writeln!(out, "1..{}", self.cases.len()).unwrap();
+
+        for (i, case) in self.cases.iter().enumerate() {
+            match case.outcome {
+                Outcome::Pass => {
+                    writeln!(out, "ok {} - {}", i + 1, case.name).unwrap();
+                }
+                Outcome::NotRun | Outcome::Fail | Outcome::Hung => {
+                    writeln!(
+                        out,
+                        "not ok {} - {} # {}",
+                        i + 1,
+                        case.name,
+                        case.outcome
+                    )
+                    .unwrap();
+
+                    for line in &case.output {
+                        writeln!(out, "# {}", line).unwrap();
+                    }
+                }
+            }
+        }
+
+        out
+    }
+}
+
+fn xml_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            // Control characters other than whitespace aren't legal in XML
+            // 1.0 at all, even escaped.
+            c if c.is_control() && !matches!(c, '\n' | '\r' | '\t') => {
+                out.push('\u{fffd}')
+            }
+            c => out.push(c),
+        }
+    }
+
+    out
+}
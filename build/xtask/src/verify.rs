@@ -0,0 +1,69 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{sign, Config};
+
+pub fn run(cfg: &Path) -> Result<()> {
+    let toml = Config::from_file(&cfg)?;
+
+    let mut out = PathBuf::from("target");
+    out.push(&toml.name);
+    out.push("dist");
+
+    let mut src_dir = cfg.to_path_buf();
+    src_dir.pop();
+
+    if toml.signing.is_empty() {
+        bail!("{} has no signing configuration", cfg.display());
+    }
+
+    let mut failed = false;
+
+    for (name, signing) in &toml.signing {
+        let result = match signing.method.as_str() {
+            "rsa" => {
+                let signed = std::fs::read(out.join(signing.image_file(name)))?;
+                let cmpa = std::fs::read(out.join("CMPA.bin"))?;
+                sign::lpc55_verify(&signed, &cmpa)
+            }
+            "detached" => {
+                let image = std::fs::read(out.join(format!("{}.bin", name)))?;
+                let sig = std::fs::read(out.join(signing.output_file(name)))?;
+                let key = match &signing.priv_key {
+                    Some(priv_key) => Some(
+                        sign::SigningKey::load(&src_dir.join(priv_key))?
+                            .verifying_key(),
+                    ),
+                    None => None,
+                };
+                sign::detached_verify(&image, &sig, key.as_ref())
+            }
+            method => {
+                println!(
+                    "{}: {} images are not signed; skipping",
+                    name, method
+                );
+                continue;
+            }
+        };
+
+        match result.with_context(|| signing.output_file(name)) {
+            Ok(algorithm) => println!("{}: ok ({:?})", name, algorithm),
+            Err(e) => {
+                println!("{}: FAILED: {:#}", name, e);
+                failed = true;
+            }
+        }
+    }
+
+    if failed {
+        bail!("signature verification failed");
+    }
+
+    Ok(())
+}
@@ -28,4 +28,8 @@ pub enum PowerState {
     A0 = 5,
 }
 
+/// Notification posted to the sequencer by the power task when it latches a
+/// fault on a rail.
+pub const POWER_FAULT_NOTIFICATION: u32 = 1 << 0;
+
 include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
@@ -13,12 +13,12 @@ use ringbuf::*;
 use userlib::*;
 
 use drv_gimlet_hf_api as hf_api;
-use drv_gimlet_seq_api::{PowerState, SeqError};
+use drv_gimlet_seq_api::{PowerState, SeqError, POWER_FAULT_NOTIFICATION};
 use drv_i2c_api::{I2cDevice, ResponseCode};
 use drv_ice40_spi_program as ice40;
 use drv_spi_api as spi_api;
 use drv_stm32xx_sys_api as sys_api;
-use idol_runtime::RequestError;
+use idol_runtime::{NotificationHandler, RequestError};
 use seq_spi::{Addr, Reg};
 
 task_slot!(SYS, sys);
@@ -52,6 +52,7 @@ enum Trace {
     ClockConfigWrite(usize),
     ClockConfigSuccess(usize),
     ClockConfigFailed(usize, ResponseCode),
+    PowerFault,
     None,
 }
 
@@ -319,7 +320,7 @@ fn main() -> ! {
 
     loop {
         ringbuf_entry!(Trace::Done);
-        idol_runtime::dispatch(&mut buffer, &mut server);
+        idol_runtime::dispatch_n(&mut buffer, &mut server);
     }
 }
 
@@ -478,6 +479,20 @@ impl idl::InOrderSequencerImpl for ServerImpl {
     }
 }
 
+impl NotificationHandler for ServerImpl {
+    fn current_notification_mask(&self) -> u32 {
+        POWER_FAULT_NOTIFICATION
+    }
+
+    fn handle_notification(&mut self, _bits: u32) {
+        //
+        // For now, we only record that the power task has seen a rail fault;
+        // the details can be retrieved from the power task itself.
+        //
+        ringbuf_entry!(Trace::PowerFault);
+    }
+}
+
 fn reprogram_fpga(
     spi: &spi_api::SpiDevice,
     sys: &sys_api::Sys,
@@ -547,28 +562,28 @@ cfg_if::cfg_if! {
         const PGS_PULL: sys_api::Pull = sys_api::Pull::None;
 
         fn vcore_soc_off() {
-            use drv_i2c_devices::raa229618::Raa229618;
+            use drv_i2c_devices::pmbus_device::{PmbusDevice, RAA229618};
             let i2c = I2C.get_task_id();
 
             let (device, rail) = i2c_config::pmbus::vdd_vcore(i2c);
-            let mut vdd_vcore = Raa229618::new(&device, rail);
+            let mut vdd_vcore = PmbusDevice::new(&device, rail, &RAA229618);
 
             let (device, rail) = i2c_config::pmbus::vddcr_soc(i2c);
-            let mut vddcr_soc = Raa229618::new(&device, rail);
+            let mut vddcr_soc = PmbusDevice::new(&device, rail, &RAA229618);
 
             vdd_vcore.turn_off().unwrap();
             vddcr_soc.turn_off().unwrap();
         }
 
         fn vcore_soc_on() {
-            use drv_i2c_devices::raa229618::Raa229618;
+            use drv_i2c_devices::pmbus_device::{PmbusDevice, RAA229618};
             let i2c = I2C.get_task_id();
 
             let (device, rail) = i2c_config::pmbus::vdd_vcore(i2c);
-            let mut vdd_vcore = Raa229618::new(&device, rail);
+            let mut vdd_vcore = PmbusDevice::new(&device, rail, &RAA229618);
 
             let (device, rail) = i2c_config::pmbus::vddcr_soc(i2c);
-            let mut vddcr_soc = Raa229618::new(&device, rail);
+            let mut vddcr_soc = PmbusDevice::new(&device, rail, &RAA229618);
 
             vdd_vcore.turn_on().unwrap();
             vddcr_soc.turn_on().unwrap();
@@ -5,9 +5,9 @@
 //! Client API for the I2C server
 //!
 //! This API allows for access to I2C devices.  The actual I2C bus
-//! communication occurs in a disjoint I2C server task; this API handles
-//! marshalling (and unmarshalling) of messages to (and replies from) this
-//! task to perform I2C operations.
+//! communication occurs in a disjoint I2C server task, with which we
+//! communicate via the Idol interface defined in `idl/i2c.idol`; this API
+//! layers conveniences for accessing a particular device on top of it.
 //!
 //! # I2C devices
 //!
@@ -19,24 +19,35 @@
 //! - The segment on the multiplexer, if a multiplexer is specified
 //! - The address of the device itself
 //!
+//! # SMBus Packet Error Checking
+//!
+//! If an [`I2cDevice`] has `pec` set, every transaction with it will be
+//! protected by an SMBus Packet Error Code, which is computed and checked by
+//! the I2C server:  a PEC byte is appended to a write that isn't followed by
+//! a read, and a PEC byte is read (and verified) at the end of any read,
+//! with a failure to match resulting in [`ResponseCode::PecMismatch`].
+//!
+//! # PMBus pages
+//!
+//! If an [`I2cDevice`] has `page` set, every transaction with it will be
+//! preceded by a write of the page to the device's PMBus `PAGE` register.
+//! The I2C server performs the page selection and the transaction together,
+//! so another client can't change the page out from under us.
+//!
 
 #![no_std]
 
+use derive_idol_err::IdolError;
+use serde::{Deserialize, Serialize};
 use zerocopy::{AsBytes, FromBytes};
 
 use userlib::*;
 
-#[derive(FromPrimitive, PartialEq)]
-pub enum Op {
-    WriteRead = 1,
-    WriteReadBlock = 2,
-}
-
 /// The response code returned from the I2C controller (or from the kernel in
 /// the case of [`ResponseCode::Dead`]).  These response codes pretty specific,
 /// not because the caller is expected to necessarily handle them differently,
 /// but to give upstack software some modicum of context surrounding the error.
-#[derive(Copy, Clone, Debug, FromPrimitive, PartialEq)]
+#[derive(Copy, Clone, Debug, FromPrimitive, PartialEq, IdolError)]
 #[repr(u32)]
 pub enum ResponseCode {
     /// Server has died
@@ -81,6 +92,51 @@ pub enum ResponseCode {
     BusLockedMux = 20,
     /// I2C controller appeared to be locked and was reset
     ControllerLocked = 21,
+    /// SMBus Packet Error Code (PEC) did not match the data received
+    PecMismatch = 22,
+}
+
+///
+/// An SMBus Packet Error Code (PEC):  a CRC-8 (with polynomial x^8 + x^2 +
+/// x + 1) over every byte of a transaction, including the address bytes.
+///
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Pec(u8);
+
+impl Pec {
+    pub fn update(&mut self, byte: u8) {
+        let mut crc = self.0 ^ byte;
+
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+
+        self.0 = crc;
+    }
+
+    pub fn value(&self) -> u8 {
+        self.0
+    }
+}
+
+///
+/// The amount to read in a transaction, as understood by each controller's
+/// driver
+///
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ReadLength {
+    /// Fixed length to read
+    Fixed(usize),
+    /// Read size is variable: first byte contains length
+    Variable,
+    /// Read size is variable, as with `Variable`, but the data are followed
+    /// by an SMBus PEC byte -- and the length byte is itself passed to
+    /// `putbyte` (at position 0) so that it can be included in the PEC
+    VariablePec,
 }
 
 ///
@@ -166,6 +222,116 @@ pub enum Segment {
     S8 = 8,
 }
 
+///
+/// Whether a device is present on its bus, as determined by whether it
+/// acknowledges its address.
+///
+#[derive(Copy, Clone, Debug, FromPrimitive, PartialEq, AsBytes)]
+#[repr(u8)]
+pub enum Presence {
+    Unknown = 0,
+    Present = 1,
+    Absent = 2,
+}
+
+///
+/// Counts of errors seen on a bus segment, or in transactions with a
+/// particular device.
+///
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ErrorStats {
+    /// address or data bytes that weren't acknowledged
+    pub nacks: u32,
+    /// transactions that timed out with the bus or controller locked
+    pub timeouts: u32,
+    /// transactions in which arbitration was lost
+    pub arbitration_lost: u32,
+    /// resets of the controller (and mux, if any) to recover the bus
+    pub bus_resets: u32,
+    /// failures to select a mux segment
+    pub mux_failures: u32,
+    /// SMBus reads whose packet error code didn't match the data
+    pub pec_errors: u32,
+}
+
+///
+/// The number of bytes in a [`ScanResult`] bitmap: one bit for each 7-bit
+/// address.
+///
+pub const SCAN_BITMAP_SIZE: usize = 128 / 8;
+
+///
+/// The result of scanning a bus segment, with a bit set for each address
+/// that acknowledged.
+///
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct ScanResult(pub [u8; SCAN_BITMAP_SIZE]);
+
+impl ScanResult {
+    ///
+    /// Returns true if a device acknowledged the specified address.
+    ///
+    pub fn is_present(&self, address: u8) -> bool {
+        let address = address as usize;
+
+        address < 128 && self.0[address / 8] & (1 << (address % 8)) != 0
+    }
+
+    ///
+    /// Returns an iterator over the addresses that acknowledged.
+    ///
+    pub fn addresses(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..128).filter(move |&address| self.is_present(address))
+    }
+}
+
+///
+/// Scans the bus segment identified by the specified controller, port, and
+/// (optional) mux and segment, returning the addresses that acknowledged.
+/// Reserved addresses are not scanned.
+///
+pub fn scan(
+    task: TaskId,
+    controller: Controller,
+    port: PortIndex,
+    segment: Option<(Mux, Segment)>,
+) -> Result<ScanResult, ResponseCode> {
+    let (mux, segment) = match segment {
+        Some((mux, segment)) => (mux as u8, segment as u8),
+        None => (0, 0),
+    };
+
+    let mut result = ScanResult::default();
+
+    I2c::from(task).scan(
+        controller as u8,
+        port.0,
+        mux,
+        segment,
+        &mut result.0,
+    )?;
+
+    Ok(result)
+}
+
+///
+/// Returns the counts of errors seen on the bus segment identified by the
+/// specified controller, port, and (optional) mux and segment.
+///
+pub fn bus_stats(
+    task: TaskId,
+    controller: Controller,
+    port: PortIndex,
+    segment: Option<(Mux, Segment)>,
+) -> Result<ErrorStats, ResponseCode> {
+    let (mux, segment) = match segment {
+        Some((mux, segment)) => (mux as u8, segment as u8),
+        None => (0, 0),
+    };
+
+    I2c::from(task).bus_stats(controller as u8, port.0, mux, segment)
+}
+
 ///
 /// The 5-tuple that uniquely identifies an I2C device.  The multiplexer and
 /// the segment are optional, but if one is present, the other must be.
@@ -177,48 +343,10 @@ pub struct I2cDevice {
     pub port: PortIndex,
     pub segment: Option<(Mux, Segment)>,
     pub address: u8,
-}
-
-type I2cMessage = (u8, Controller, PortIndex, Option<(Mux, Segment)>);
-
-pub trait Marshal<T> {
-    fn marshal(&self) -> T;
-    fn unmarshal(val: &T) -> Result<Self, ResponseCode>
-    where
-        Self: Sized;
-}
-
-impl Marshal<[u8; 4]> for I2cMessage {
-    fn marshal(&self) -> [u8; 4] {
-        [
-            self.0,
-            self.1 as u8,
-            self.2 .0 as u8,
-            match self.3 {
-                Some((mux, seg)) => {
-                    0b1000_0000 | ((mux as u8) << 4) | (seg as u8)
-                }
-                None => 0,
-            },
-        ]
-    }
-    fn unmarshal(val: &[u8; 4]) -> Result<Self, ResponseCode> {
-        Ok((
-            val[0],
-            Controller::from_u8(val[1]).ok_or(ResponseCode::BadController)?,
-            PortIndex(val[2]),
-            if val[3] == 0 {
-                None
-            } else {
-                Some((
-                    Mux::from_u8((val[3] & 0b0111_0000) >> 4)
-                        .ok_or(ResponseCode::BadMux)?,
-                    Segment::from_u8(val[3] & 0b0000_1111)
-                        .ok_or(ResponseCode::BadSegment)?,
-                ))
-            },
-        ))
-    }
+    /// use SMBus Packet Error Checking on every transaction
+    pub pec: bool,
+    /// PMBus page to select before every transaction, if any
+    pub page: Option<u8>,
 }
 
 impl core::fmt::Display for I2cDevice {
@@ -259,17 +387,125 @@ impl I2cDevice {
             port: port,
             segment: segment,
             address: address,
+            pec: false,
+            page: None,
         }
     }
 }
 
-impl From<ResponseCode> for u32 {
-    fn from(rc: ResponseCode) -> Self {
-        rc as u32
+impl I2cDevice {
+    ///
+    /// Returns the mux and segment of this device as they are sent to the
+    /// I2C server, where 0 denotes the absence of a mux.
+    ///
+    fn mux_segment(&self) -> (u8, u8) {
+        match self.segment {
+            Some((mux, segment)) => (mux as u8, segment as u8),
+            None => (0, 0),
+        }
+    }
+
+    ///
+    /// Performs a transaction with this device, with the PMBus page (if
+    /// any) and PEC of the device.  If `block` is set, the read is an SMBus
+    /// block read.
+    ///
+    fn transact(
+        &self,
+        wbuf: &[u8],
+        rbuf: &mut [u8],
+        block: bool,
+    ) -> Result<usize, ResponseCode> {
+        let (mux, segment) = self.mux_segment();
+
+        I2c::from(self.task).write_read(
+            self.controller as u8,
+            self.port.0,
+            mux,
+            segment,
+            self.address,
+            self.page,
+            block,
+            self.pec,
+            wbuf,
+            rbuf,
+        )
+    }
+
+    fn write_read(
+        &self,
+        wbuf: &[u8],
+        rbuf: &mut [u8],
+    ) -> Result<usize, ResponseCode> {
+        self.transact(wbuf, rbuf, false)
+    }
+
+    fn write_read_block(
+        &self,
+        wbuf: &[u8],
+        rbuf: &mut [u8],
+    ) -> Result<usize, ResponseCode> {
+        self.transact(wbuf, rbuf, true)
+    }
+
+    ///
+    /// Assembles an SMBus block -- a register, a byte count, and the data
+    /// itself -- into the specified buffer, returning its length.
+    ///
+    fn block<R: AsBytes>(
+        reg: R,
+        data: &[u8],
+        buf: &mut [u8],
+    ) -> Result<usize, ResponseCode> {
+        let reg = reg.as_bytes();
+        let len = reg.len() + 1 + data.len();
+
+        if len > buf.len() || data.len() > u8::MAX as usize {
+            return Err(ResponseCode::BadArg);
+        }
+
+        buf[..reg.len()].copy_from_slice(reg);
+        buf[reg.len()] = data.len() as u8;
+        buf[reg.len() + 1..len].copy_from_slice(data);
+
+        Ok(len)
+    }
+
+    ///
+    /// Indicates whether this device is present.  The I2C server caches the
+    /// presence of configured devices (updating it on every transaction), so
+    /// this will generally not result in any bus activity.
+    ///
+    pub fn is_present(&self) -> Result<bool, ResponseCode> {
+        let (mux, segment) = self.mux_segment();
+
+        let presence = I2c::from(self.task).device_present(
+            self.controller as u8,
+            self.port.0,
+            mux,
+            segment,
+            self.address,
+        )?;
+
+        Ok(presence == Presence::Present)
+    }
+
+    ///
+    /// Returns the counts of errors seen in transactions with this device.
+    /// Statistics are only kept for devices in the I2C configuration.
+    ///
+    pub fn error_stats(&self) -> Result<ErrorStats, ResponseCode> {
+        let (mux, segment) = self.mux_segment();
+
+        I2c::from(self.task).device_stats(
+            self.controller as u8,
+            self.port.0,
+            mux,
+            segment,
+            self.address,
+        )
     }
-}
 
-impl I2cDevice {
     ///
     /// Reads a register, with register address of type R and value of type V.
     ///
@@ -292,27 +528,8 @@ impl I2cDevice {
         reg: R,
     ) -> Result<V, ResponseCode> {
         let mut val = V::default();
-        let mut response = 0_usize;
-
-        let (code, _) = sys_send(
-            self.task,
-            Op::WriteRead as u16,
-            &Marshal::marshal(&(
-                self.address,
-                self.controller,
-                self.port,
-                self.segment,
-            )),
-            response.as_bytes_mut(),
-            &[Lease::from(reg.as_bytes()), Lease::from(val.as_bytes_mut())],
-        );
-
-        if code != 0 {
-            Err(ResponseCode::from_u32(code)
-                .ok_or(ResponseCode::BadResponse)?)
-        } else {
-            Ok(val)
-        }
+        self.write_read(reg.as_bytes(), val.as_bytes_mut())?;
+        Ok(val)
     }
 
     ///
@@ -325,27 +542,7 @@ impl I2cDevice {
         reg: R,
         buf: &mut [u8],
     ) -> Result<usize, ResponseCode> {
-        let mut response = 0_usize;
-
-        let (code, _) = sys_send(
-            self.task,
-            Op::WriteRead as u16,
-            &Marshal::marshal(&(
-                self.address,
-                self.controller,
-                self.port,
-                self.segment,
-            )),
-            response.as_bytes_mut(),
-            &[Lease::from(reg.as_bytes()), Lease::from(buf)],
-        );
-
-        if code != 0 {
-            Err(ResponseCode::from_u32(code)
-                .ok_or(ResponseCode::BadResponse)?)
-        } else {
-            Ok(response)
-        }
+        self.write_read(reg.as_bytes(), buf)
     }
 
     ///
@@ -360,27 +557,41 @@ impl I2cDevice {
         reg: R,
         buf: &mut [u8],
     ) -> Result<usize, ResponseCode> {
-        let mut response = 0_usize;
-
-        let (code, _) = sys_send(
-            self.task,
-            Op::WriteReadBlock as u16,
-            &Marshal::marshal(&(
-                self.address,
-                self.controller,
-                self.port,
-                self.segment,
-            )),
-            response.as_bytes_mut(),
-            &[Lease::from(reg.as_bytes()), Lease::from(buf)],
-        );
-
-        if code != 0 {
-            Err(ResponseCode::from_u32(code)
-                .ok_or(ResponseCode::BadResponse)?)
-        } else {
-            Ok(response)
-        }
+        self.write_read_block(reg.as_bytes(), buf)
+    }
+
+    ///
+    /// Performs an SMBus block write, in which the register is followed by
+    /// the number of bytes in the block, and then by the block itself.
+    ///
+    pub fn write_block<R: AsBytes>(
+        &self,
+        reg: R,
+        data: &[u8],
+    ) -> Result<(), ResponseCode> {
+        let mut buf = [0u8; 255];
+        let len = Self::block(reg, data, &mut buf)?;
+
+        self.write_read(&buf[..len], &mut [])?;
+        Ok(())
+    }
+
+    ///
+    /// Performs an SMBus block write-block read process call:  a block write
+    /// of `data`, followed (without an intervening STOP) by a block read into
+    /// the specified buffer, returning the number of bytes read.  As with
+    /// [`read_block`], the byte count is not present in the payload.
+    ///
+    pub fn block_process_call<R: AsBytes>(
+        &self,
+        reg: R,
+        data: &[u8],
+        buf: &mut [u8],
+    ) -> Result<usize, ResponseCode> {
+        let mut wbuf = [0u8; 255];
+        let len = Self::block(reg, data, &mut wbuf)?;
+
+        self.write_read_block(&wbuf[..len], buf)
     }
 
     ///
@@ -393,29 +604,9 @@ impl I2cDevice {
     pub fn read<V: Default + AsBytes + FromBytes>(
         &self,
     ) -> Result<V, ResponseCode> {
-        let empty = [0u8; 1];
         let mut val = V::default();
-        let mut response = 0_usize;
-
-        let (code, _) = sys_send(
-            self.task,
-            Op::WriteRead as u16,
-            &Marshal::marshal(&(
-                self.address,
-                self.controller,
-                self.port,
-                self.segment,
-            )),
-            response.as_bytes_mut(),
-            &[Lease::from(&empty[0..0]), Lease::from(val.as_bytes_mut())],
-        );
-
-        if code != 0 {
-            Err(ResponseCode::from_u32(code)
-                .ok_or(ResponseCode::BadResponse)?)
-        } else {
-            Ok(val)
-        }
+        self.write_read(&[], val.as_bytes_mut())?;
+        Ok(val)
     }
 
     ///
@@ -424,28 +615,7 @@ impl I2cDevice {
     /// the specified mutable slice, returning the number of bytes read.
     ///
     pub fn read_into(&self, buf: &mut [u8]) -> Result<usize, ResponseCode> {
-        let empty = [0u8; 1];
-        let mut response = 0_usize;
-
-        let (code, _) = sys_send(
-            self.task,
-            Op::WriteRead as u16,
-            &Marshal::marshal(&(
-                self.address,
-                self.controller,
-                self.port,
-                self.segment,
-            )),
-            response.as_bytes_mut(),
-            &[Lease::from(&empty[0..0]), Lease::from(buf)],
-        );
-
-        if code != 0 {
-            Err(ResponseCode::from_u32(code)
-                .ok_or(ResponseCode::BadResponse)?)
-        } else {
-            Ok(response)
-        }
+        self.write_read(&[], buf)
     }
 
     ///
@@ -453,27 +623,9 @@ impl I2cDevice {
     /// perform any follow-up reads.
     ///
     pub fn write(&self, buffer: &[u8]) -> Result<(), ResponseCode> {
-        let empty = [0u8; 1];
-        let mut response = 0_usize;
-
-        let (code, _) = sys_send(
-            self.task,
-            Op::WriteRead as u16,
-            &Marshal::marshal(&(
-                self.address,
-                self.controller,
-                self.port,
-                self.segment,
-            )),
-            response.as_bytes_mut(),
-            &[Lease::from(buffer), Lease::from(&empty[0..0])],
-        );
-
-        if code != 0 {
-            Err(ResponseCode::from_u32(code)
-                .ok_or(ResponseCode::BadResponse)?)
-        } else {
-            Ok(())
-        }
+        self.write_read(buffer, &mut [])?;
+        Ok(())
     }
 }
+
+include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
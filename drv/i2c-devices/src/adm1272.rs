@@ -4,7 +4,10 @@
 
 //! Driver for the ADM1272 hot-swap controller
 
-use crate::{CurrentSensor, TempSensor, VoltageSensor};
+use crate::{
+    CurrentSensor, PmbusStatus, PowerSensor, StatusSensor, TempSensor,
+    VoltageSensor,
+};
 use drv_i2c_api::*;
 use num_traits::float::FloatCore;
 use pmbus::commands::*;
@@ -36,7 +39,6 @@ impl From<Error> for ResponseCode {
     }
 }
 
-#[allow(dead_code)]
 struct Coefficients {
     voltage: pmbus::Coefficients,
     current: pmbus::Coefficients,
@@ -52,6 +54,23 @@ pub struct Adm1272 {
     coefficients: Option<Coefficients>,
     /// Our (cached) configuration
     config: Option<adm1272::PMON_CONFIG::CommandData>,
+    /// Energy accumulator as of our last reading of it
+    energy: Option<EnergyCount>,
+}
+
+//
+// READ_EIN is a block read that we interpret ourselves: a 16-bit power
+// accumulator, an 8-bit count of its rollovers, and a 24-bit count of the
+// power samples that have been accumulated.
+//
+const READ_EIN: u8 = 0x86;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct EnergyCount {
+    /// Accumulated power (including rollovers), modulo 2^24
+    energy: u32,
+    /// Number of power samples accumulated, modulo 2^24
+    samples: u32,
 }
 
 impl core::fmt::Display for Adm1272 {
@@ -77,6 +96,7 @@ impl Adm1272 {
             rsense: (rsense.0 * 1000.0).round() as i32,
             coefficients: None,
             config: None,
+            energy: None,
         }
     }
 
@@ -244,6 +264,60 @@ impl Adm1272 {
         let iout = pmbus_read!(self.device, adm1272::PEAK_IOUT)?;
         Ok(Amperes(iout.get(&self.load_coefficients()?.current)?.0))
     }
+
+    fn read_energy_count(&mut self) -> Result<EnergyCount, Error> {
+        let mut buf = [0u8; 6];
+
+        match self.device.read_block(READ_EIN, &mut buf) {
+            Ok(6) => Ok(EnergyCount {
+                energy: u32::from_le_bytes([buf[0], buf[1], buf[2], 0]),
+                samples: u32::from_le_bytes([buf[3], buf[4], buf[5], 0]),
+            }),
+            Ok(_) => Err(Error::BadData { cmd: READ_EIN }),
+            Err(code) => Err(Error::BadRead {
+                cmd: READ_EIN,
+                code,
+            }),
+        }
+    }
+
+    ///
+    /// Returns the average input power since the previous call, as
+    /// determined from the device's energy accumulator.  Unlike a single
+    /// reading of input power, this accounts for every sample that the
+    /// device has taken, so it can be multiplied by the time between calls
+    /// to account for energy.  Returns `None` on the first call (or if no
+    /// samples have been taken since the previous call).
+    ///
+    pub fn average_power(&mut self) -> Result<Option<Watts>, Error> {
+        const MASK: u32 = (1 << 24) - 1;
+
+        self.enable_vin_sampling()?;
+        let count = self.read_energy_count()?;
+
+        let last = match self.energy.replace(count) {
+            Some(last) => last,
+            None => return Ok(None),
+        };
+
+        let energy = count.energy.wrapping_sub(last.energy) & MASK;
+        let samples = count.samples.wrapping_sub(last.samples) & MASK;
+
+        if samples == 0 {
+            return Ok(None);
+        }
+
+        //
+        // The accumulator is in the same (direct format) units as READ_PIN,
+        // so we convert its average with our power coefficients.
+        //
+        let c = &self.load_coefficients()?.power;
+        let raw = energy as f32 / samples as f32;
+        let power =
+            (raw * 10.0f32.powi(-(c.R as i32)) - c.b as f32) / c.m as f32;
+
+        Ok(Some(Watts(power)))
+    }
 }
 
 impl TempSensor<Error> for Adm1272 {
@@ -268,3 +342,21 @@ impl VoltageSensor<Error> for Adm1272 {
         Ok(Volts(vout.get(&self.load_coefficients()?.voltage)?.0))
     }
 }
+
+impl PowerSensor<Error> for Adm1272 {
+    fn read_power(&mut self) -> Result<Watts, Error> {
+        self.enable_vin_sampling()?;
+        let pin = pmbus_read!(self.device, adm1272::READ_PIN)?;
+        Ok(Watts(pin.get(&self.load_coefficients()?.power)?.0))
+    }
+}
+
+impl StatusSensor<Error> for Adm1272 {
+    fn read_status(&mut self) -> Result<PmbusStatus, Error> {
+        pmbus_status!(self.device)
+    }
+
+    fn clear_faults(&mut self) -> Result<(), Error> {
+        pmbus_clear_faults!(self.device)
+    }
+}
@@ -9,15 +9,16 @@
 //! - [`adm1272`]: ADM1272 hot swap controller
 //! - [`adt7420`]: ADT7420 temperature sensor
 //! - [`ds2482`]: DS2482-100 1-wire initiator
-//! - [`isl68224`]: ISL68224 power controller
 //! - [`max6634`]: MAX6634 temperature sensor
 //! - [`max31790`]: MAX31790 fan controller
 //! - [`mcp9808`]: MCP9808 temperature sensor
 //! - [`pct2075`]: PCT2075 temperature sensor
-//! - [`raa229618`]: RAA229618 power controller
+//! - [`pmbus_config`]: PMBus regulator configuration verification and
+//!   programming
+//! - [`pmbus_device`]: Generic PMBus device (BMR491, ISL68224, RAA229618,
+//!   TPS546B24A and the like)
 //! - [`sbtsi`]: AMD SB-TSI temperature sensor
 //! - [`tmp116`]: TMP116 temperature sensor
-//! - [`tps546b24a`]: TPS546B24A buck converter
 
 #![no_std]
 
@@ -89,6 +90,102 @@ macro_rules! pmbus_write {
     }};
 }
 
+//
+// PMBus status is read (and faults cleared) via the raw command codes rather
+// than the `pmbus` crate's command data, as we want to report status
+// registers as the devices present them, unadorned.
+//
+macro_rules! pmbus_status {
+    ($device:expr) => {
+        crate::read_pmbus_status(&$device)
+            .map_err(|(cmd, code)| Error::BadRead { cmd, code })
+    };
+}
+
+macro_rules! pmbus_clear_faults {
+    ($device:expr) => {
+        $device
+            .write(&[crate::CLEAR_FAULTS])
+            .map_err(|code| Error::BadWrite {
+                cmd: crate::CLEAR_FAULTS,
+                code,
+            })
+    };
+}
+
+const CLEAR_FAULTS: u8 = 0x03;
+const STATUS_WORD: u8 = 0x79;
+const STATUS_VOUT: u8 = 0x7a;
+const STATUS_IOUT: u8 = 0x7b;
+const STATUS_TEMPERATURE: u8 = 0x7d;
+
+/// PMBus status for a rail.  The summary in `word` is always read; the more
+/// detailed status registers are only read if `word` indicates that they
+/// have something to report (and are zero otherwise).
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct PmbusStatus {
+    pub word: u16,
+    pub vout: u8,
+    pub iout: u8,
+    pub temperature: u8,
+}
+
+impl PmbusStatus {
+    pub const VOUT: u16 = 1 << 15;
+    pub const IOUT_POUT: u16 = 1 << 14;
+    pub const INPUT: u16 = 1 << 13;
+    pub const POWER_GOOD_N: u16 = 1 << 11;
+    pub const OFF: u16 = 1 << 6;
+    pub const VOUT_OV_FAULT: u16 = 1 << 5;
+    pub const IOUT_OC_FAULT: u16 = 1 << 4;
+    pub const VIN_UV_FAULT: u16 = 1 << 3;
+    pub const TEMPERATURE: u16 = 1 << 2;
+    pub const CML: u16 = 1 << 1;
+
+    /// Bits in `word` that indicate a fault (or warning) on the rail itself,
+    /// as opposed to its being off or having seen a communication error.
+    pub const FAULTS: u16 = PmbusStatus::VOUT
+        | PmbusStatus::IOUT_POUT
+        | PmbusStatus::INPUT
+        | PmbusStatus::VOUT_OV_FAULT
+        | PmbusStatus::IOUT_OC_FAULT
+        | PmbusStatus::VIN_UV_FAULT
+        | PmbusStatus::TEMPERATURE;
+
+    pub fn is_faulted(&self) -> bool {
+        self.word & PmbusStatus::FAULTS != 0
+    }
+}
+
+fn read_pmbus_status(
+    device: &drv_i2c_api::I2cDevice,
+) -> Result<PmbusStatus, (u8, drv_i2c_api::ResponseCode)> {
+    let read = |cmd| device.read_reg::<u8, u8>(cmd).map_err(|code| (cmd, code));
+
+    let word = device
+        .read_reg::<u8, u16>(STATUS_WORD)
+        .map_err(|code| (STATUS_WORD, code))?;
+
+    let mut status = PmbusStatus {
+        word,
+        ..Default::default()
+    };
+
+    if word & PmbusStatus::VOUT != 0 {
+        status.vout = read(STATUS_VOUT)?;
+    }
+
+    if word & PmbusStatus::IOUT_POUT != 0 {
+        status.iout = read(STATUS_IOUT)?;
+    }
+
+    if word & PmbusStatus::TEMPERATURE != 0 {
+        status.temperature = read(STATUS_TEMPERATURE)?;
+    }
+
+    Ok(status)
+}
+
 pub trait TempSensor<T: core::convert::Into<drv_i2c_api::ResponseCode>> {
     fn read_temperature(&mut self) -> Result<userlib::units::Celsius, T>;
 }
@@ -105,16 +202,19 @@ pub trait VoltageSensor<T: core::convert::Into<drv_i2c_api::ResponseCode>> {
     fn read_vout(&mut self) -> Result<userlib::units::Volts, T>;
 }
 
+pub trait StatusSensor<T: core::convert::Into<drv_i2c_api::ResponseCode>> {
+    fn read_status(&mut self) -> Result<PmbusStatus, T>;
+    fn clear_faults(&mut self) -> Result<(), T>;
+}
+
 pub mod adm1272;
 pub mod adt7420;
-pub mod bmr491;
 pub mod ds2482;
-pub mod isl68224;
 pub mod max31790;
 pub mod max6634;
 pub mod mcp9808;
 pub mod pct2075;
-pub mod raa229618;
+pub mod pmbus_config;
+pub mod pmbus_device;
 pub mod sbtsi;
 pub mod tmp116;
-pub mod tps546b24a;
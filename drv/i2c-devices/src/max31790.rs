@@ -307,6 +307,14 @@ impl Max31790 {
         }
     }
 
+    /// Returns a mask of the fans (bit 0 for the fan at index 0, etc.) for
+    /// which the controller has detected a fault -- e.g., a tach count that
+    /// has overflowed because the fan isn't turning.
+    pub fn fan_faults(&self) -> Result<u8, ResponseCode> {
+        let status = read_reg8(&self.device, Register::FanFaultStatus1)?;
+        Ok(status & ((1 << MAX_FANS) - 1))
+    }
+
     /// Set the PWM duty cycle for a fan
     pub fn set_pwm(&self, fan: Fan, pwm: PWMDuty) -> Result<(), ResponseCode> {
         let perc = core::cmp::min(pwm.0, 100) as f32;
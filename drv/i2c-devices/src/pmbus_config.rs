@@ -0,0 +1,192 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! PMBus regulator configuration verification and programming
+//!
+//! A regulator's configuration is described by a configuration blob (see
+//! the `pmbus_blob` crate for its format).  A device's configuration can be
+//! verified against a blob at any time; programming it (writing each
+//! setting and then committing them to NVM via `STORE_USER_ALL`) requires
+//! that the configuration first be explicitly unlocked.
+
+use drv_i2c_api::*;
+pub use pmbus_blob::{
+    BadBlob, Blob, Setting, BLOB_MAGIC, BLOB_VERSION, MAX_VALUE_LEN, NO_PAGE,
+};
+
+const STORE_USER_ALL: u8 = 0x15;
+
+#[derive(Debug)]
+pub enum Error {
+    BadRead { cmd: u8, code: ResponseCode },
+    BadWrite { cmd: u8, code: ResponseCode },
+    BadBlob { offset: usize },
+    Locked,
+    Mismatch { page: Option<u8>, cmd: u8 },
+}
+
+impl From<BadBlob> for Error {
+    fn from(err: BadBlob) -> Self {
+        Error::BadBlob { offset: err.offset }
+    }
+}
+
+impl From<Error> for ResponseCode {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::BadRead { code, .. } => code,
+            Error::BadWrite { code, .. } => code,
+            Error::BadBlob { .. } | Error::Locked => ResponseCode::BadArg,
+            Error::Mismatch { .. } => ResponseCode::BadResponse,
+        }
+    }
+}
+
+/// A register whose value did not match its setting
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Mismatch {
+    pub page: Option<u8>,
+    pub cmd: u8,
+    pub expected: [u8; MAX_VALUE_LEN],
+    pub actual: [u8; MAX_VALUE_LEN],
+    pub len: usize,
+}
+
+pub struct PmbusConfig {
+    device: I2cDevice,
+    unlocked: bool,
+}
+
+impl core::fmt::Display for PmbusConfig {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "pmbus config: {}", &self.device)
+    }
+}
+
+impl PmbusConfig {
+    pub fn new(device: &I2cDevice) -> Self {
+        PmbusConfig {
+            device: *device,
+            unlocked: false,
+        }
+    }
+
+    fn paged(&self, page: Option<u8>) -> I2cDevice {
+        let mut device = self.device;
+        device.page = page;
+        device
+    }
+
+    ///
+    /// Reads back every register in the blob, calling `mismatch` for each
+    /// one whose value differs from its setting, and returning the number
+    /// of registers that differed.
+    ///
+    pub fn verify(
+        &self,
+        blob: &Blob,
+        mut mismatch: impl FnMut(&Mismatch),
+    ) -> Result<usize, Error> {
+        let mut count = 0;
+
+        for setting in blob.settings() {
+            let len = setting.value.len();
+            let mut actual = [0u8; MAX_VALUE_LEN];
+
+            self.paged(setting.page)
+                .read_reg_into(setting.cmd, &mut actual[..len])
+                .map_err(|code| Error::BadRead {
+                    cmd: setting.cmd,
+                    code,
+                })?;
+
+            if actual[..len] != *setting.value {
+                let mut expected = [0u8; MAX_VALUE_LEN];
+                expected[..len].copy_from_slice(setting.value);
+
+                mismatch(&Mismatch {
+                    page: setting.page,
+                    cmd: setting.cmd,
+                    expected,
+                    actual,
+                    len,
+                });
+
+                count += 1;
+            }
+        }
+
+        Ok(count)
+    }
+
+    ///
+    /// Unlocks the configuration for programming.  This remains in effect
+    /// until the configuration is stored or explicitly locked.
+    ///
+    pub fn unlock(&mut self) {
+        self.unlocked = true;
+    }
+
+    pub fn lock(&mut self) {
+        self.unlocked = false;
+    }
+
+    ///
+    /// Writes every setting in the blob to the device, and then verifies
+    /// them, failing on the first mismatch.  This does not commit the
+    /// settings to NVM; see [`PmbusConfig::store`].
+    ///
+    pub fn program(&mut self, blob: &Blob) -> Result<(), Error> {
+        if !self.unlocked {
+            return Err(Error::Locked);
+        }
+
+        for setting in blob.settings() {
+            let len = setting.value.len();
+            let mut payload = [0u8; MAX_VALUE_LEN + 1];
+            payload[0] = setting.cmd;
+            payload[1..=len].copy_from_slice(setting.value);
+
+            self.paged(setting.page).write(&payload[..=len]).map_err(
+                |code| Error::BadWrite {
+                    cmd: setting.cmd,
+                    code,
+                },
+            )?;
+        }
+
+        let mut first = None;
+
+        self.verify(blob, |m| {
+            if first.is_none() {
+                first = Some((m.page, m.cmd));
+            }
+        })?;
+
+        match first {
+            Some((page, cmd)) => Err(Error::Mismatch { page, cmd }),
+            None => Ok(()),
+        }
+    }
+
+    ///
+    /// Commits the device's current configuration to its NVM via
+    /// `STORE_USER_ALL`, and locks the configuration.  Note that parts
+    /// generally have a limited number of NVM writes.
+    ///
+    pub fn store(&mut self) -> Result<(), Error> {
+        if !self.unlocked {
+            return Err(Error::Locked);
+        }
+
+        self.unlocked = false;
+
+        self.device
+            .write(&[STORE_USER_ALL])
+            .map_err(|code| Error::BadWrite {
+                cmd: STORE_USER_ALL,
+                code,
+            })
+    }
+}
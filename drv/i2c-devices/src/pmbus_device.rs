@@ -0,0 +1,233 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Generic PMBus device driver
+//!
+//! Most PMBus parts differ only in which of the standard commands they
+//! support, in how they encode the values that they report, and in whether
+//! they have multiple rails (selected via `PAGE`).  This driver implements
+//! the standard commands, with the differences between parts captured in
+//! [`Quirks`]:  each part's entry reads its values through the part's own
+//! command module in the `pmbus` crate, which knows how the part encodes
+//! them.  Supporting a new part generally requires only a [`Quirks`]
+//! constant here (and the part's name in build/i2c's list of PMBus parts).
+//! (Parts with more substantive differences -- e.g., the ADM1272, whose
+//! DIRECT coefficients depend on its configuration -- retain their own
+//! drivers.)
+//!
+//! On a paged part, every transaction is performed with the I2C device's
+//! `page` set to our rail:  the I2C server selects the page and performs the
+//! transaction together, so a client talking to another rail on the same
+//! part can't change the page between the two.
+
+use crate::{
+    CurrentSensor, PmbusStatus, PowerSensor, StatusSensor, TempSensor,
+    VoltageSensor,
+};
+use drv_i2c_api::*;
+use pmbus::commands::{self, bmr491, isl68224, raa229618, tps546b24a};
+use pmbus::VOutModeCommandData;
+use userlib::units::*;
+
+//
+// Each of these evaluates to a function that reads a value from a part via
+// the command in the part's module, decoding it as the part encodes it.
+// (`VOUT` is decoded according to the rail's `VOUT_MODE`, which the caller
+// provides.)
+//
+macro_rules! read_vout {
+    ($part:ident) => {{
+        fn read(
+            device: &I2cDevice,
+            mode: VOutModeCommandData,
+        ) -> Result<Volts, Error> {
+            let vout = pmbus_read!(device, $part::READ_VOUT)?;
+            Ok(Volts(vout.get(mode)?.0))
+        }
+
+        read
+    }};
+}
+
+macro_rules! read_value {
+    ($part:ident::$cmd:ident, $unit:ident) => {{
+        fn read(device: &I2cDevice) -> Result<$unit, Error> {
+            let value = pmbus_read!(device, $part::$cmd)?;
+            Ok($unit(value.get()?.0))
+        }
+
+        read
+    }};
+}
+
+/// The differences between parts
+#[derive(Copy, Clone)]
+pub struct Quirks {
+    /// The part has multiple rails, selected via `PAGE`
+    pub paged: bool,
+    vout: fn(&I2cDevice, VOutModeCommandData) -> Result<Volts, Error>,
+    iout: fn(&I2cDevice) -> Result<Amperes, Error>,
+    /// `None` if the part doesn't report temperature
+    temperature: Option<fn(&I2cDevice) -> Result<Celsius, Error>>,
+    /// `None` if the part doesn't report output power
+    power: Option<fn(&I2cDevice) -> Result<Watts, Error>>,
+}
+
+//
+// Parts supported by this driver.  Each is named as the part is in the
+// application's I2C configuration, uppercased; the power task's rail table
+// (generated by build/i2c) refers to them by that name.
+//
+
+pub const BMR491: Quirks = Quirks {
+    paged: false,
+    vout: read_vout!(bmr491),
+    iout: read_value!(bmr491::READ_IOUT, Amperes),
+    temperature: Some(read_value!(bmr491::READ_TEMPERATURE_1, Celsius)),
+    power: Some(read_value!(bmr491::READ_POUT, Watts)),
+};
+
+pub const ISL68224: Quirks = Quirks {
+    paged: true,
+    vout: read_vout!(isl68224),
+    iout: read_value!(isl68224::READ_IOUT, Amperes),
+    temperature: None,
+    power: Some(read_value!(isl68224::READ_POUT, Watts)),
+};
+
+pub const RAA229618: Quirks = Quirks {
+    paged: true,
+    vout: read_vout!(raa229618),
+    iout: read_value!(raa229618::READ_IOUT, Amperes),
+    temperature: Some(read_value!(raa229618::READ_TEMPERATURE_1, Celsius)),
+    power: Some(read_value!(raa229618::READ_POUT, Watts)),
+};
+
+pub const TPS546B24A: Quirks = Quirks {
+    paged: false,
+    vout: read_vout!(tps546b24a),
+    iout: read_value!(tps546b24a::READ_IOUT, Amperes),
+    temperature: Some(read_value!(tps546b24a::READ_TEMPERATURE_1, Celsius)),
+    power: None,
+};
+
+pub struct PmbusDevice {
+    device: I2cDevice,
+    quirks: &'static Quirks,
+    mode: Option<VOutModeCommandData>,
+}
+
+impl core::fmt::Display for PmbusDevice {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "pmbus: {}", &self.device)
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    BadRead { cmd: u8, code: ResponseCode },
+    BadWrite { cmd: u8, code: ResponseCode },
+    BadData { cmd: u8 },
+    InvalidData { err: pmbus::Error },
+    Unsupported,
+}
+
+impl From<pmbus::Error> for Error {
+    fn from(err: pmbus::Error) -> Self {
+        Error::InvalidData { err }
+    }
+}
+
+impl From<Error> for ResponseCode {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::BadRead { code, .. } => code,
+            Error::BadWrite { code, .. } => code,
+            Error::BadData { .. } | Error::InvalidData { .. } => {
+                ResponseCode::BadResponse
+            }
+            Error::Unsupported => ResponseCode::NoRegister,
+        }
+    }
+}
+
+impl PmbusDevice {
+    pub fn new(device: &I2cDevice, rail: u8, quirks: &'static Quirks) -> Self {
+        let mut device = *device;
+
+        if quirks.paged {
+            device.page = Some(rail);
+        }
+
+        PmbusDevice {
+            device,
+            quirks,
+            mode: None,
+        }
+    }
+
+    ///
+    /// Returns the rail's `VOUT_MODE`, which determines the encoding of its
+    /// `VOUT` values.
+    ///
+    fn read_mode(&mut self) -> Result<VOutModeCommandData, Error> {
+        Ok(match self.mode {
+            None => {
+                let mode = pmbus_read!(self.device, commands::VOUT_MODE)?;
+                self.mode = Some(mode);
+                mode
+            }
+            Some(mode) => mode,
+        })
+    }
+
+    pub fn turn_off(&mut self) -> Result<(), Error> {
+        let mut operation = pmbus_read!(self.device, commands::OPERATION)?;
+        operation.set_on_off_state(commands::OPERATION::OnOffState::Off);
+        pmbus_write!(self.device, commands::OPERATION, operation)
+    }
+
+    pub fn turn_on(&mut self) -> Result<(), Error> {
+        let mut operation = pmbus_read!(self.device, commands::OPERATION)?;
+        operation.set_on_off_state(commands::OPERATION::OnOffState::On);
+        pmbus_write!(self.device, commands::OPERATION, operation)
+    }
+}
+
+impl VoltageSensor<Error> for PmbusDevice {
+    fn read_vout(&mut self) -> Result<Volts, Error> {
+        let mode = self.read_mode()?;
+        (self.quirks.vout)(&self.device, mode)
+    }
+}
+
+impl CurrentSensor<Error> for PmbusDevice {
+    fn read_iout(&mut self) -> Result<Amperes, Error> {
+        (self.quirks.iout)(&self.device)
+    }
+}
+
+impl TempSensor<Error> for PmbusDevice {
+    fn read_temperature(&mut self) -> Result<Celsius, Error> {
+        let read = self.quirks.temperature.ok_or(Error::Unsupported)?;
+        read(&self.device)
+    }
+}
+
+impl PowerSensor<Error> for PmbusDevice {
+    fn read_power(&mut self) -> Result<Watts, Error> {
+        let read = self.quirks.power.ok_or(Error::Unsupported)?;
+        read(&self.device)
+    }
+}
+
+impl StatusSensor<Error> for PmbusDevice {
+    fn read_status(&mut self) -> Result<PmbusStatus, Error> {
+        pmbus_status!(self.device)
+    }
+
+    fn clear_faults(&mut self) -> Result<(), Error> {
+        pmbus_clear_faults!(self.device)
+    }
+}
@@ -0,0 +1,175 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! I2C mux drivers
+//!
+//! This crate contains what we know about each I2C mux part -- its registers
+//! and how a segment is selected -- along with a driver for each part.  The
+//! definitions are shared by the drivers (which manage muxes as an
+//! initiator) and by tasks that emulate a mux as a target (e.g., the SPD
+//! proxy), so that the two can't disagree about the part.  The drivers are
+//! independent of any I2C controller:  they reach their mux through an
+//! [`I2cMuxHardware`], which each controller's driver implements.
+//!
+//! - [`ltc4306`]: LTC4306 4-channel mux with bus buffer
+//! - [`max7358`]: MAX7358 8-channel mux with lockup detection
+//! - [`pca954x`]: PCA9545 and PCA9546 4-channel and PCA9548 8-channel muxes
+
+#![no_std]
+
+use drv_i2c_api::{Controller, Mux, PortIndex, ReadLength, ResponseCode};
+
+pub mod ltc4306;
+pub mod max7358;
+pub mod pca954x;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum I2cKonamiCode {
+    Read,
+    Write,
+}
+
+///
+/// The operations that a mux driver needs to perform on the controller
+/// upstream of its mux, and on the GPIO pins that enable and reset it.  `P`
+/// is the controller's description of such a pin.  Note that this is
+/// deliberately object-safe, allowing the [`I2cMuxDriver`] trait to itself
+/// be a trait object.
+///
+pub trait I2cMuxHardware<P> {
+    /// Performs a write followed by a read (either of which may be empty)
+    /// on the mux's controller, fetching bytes to write from `getbyte` and
+    /// passing bytes read to `putbyte`.
+    fn write_read(
+        &self,
+        addr: u8,
+        wlen: usize,
+        getbyte: &dyn Fn(usize) -> Option<u8>,
+        rlen: ReadLength,
+        putbyte: &mut dyn FnMut(usize, u8) -> Option<()>,
+    ) -> Result<(), ResponseCode>;
+
+    /// Performs the specified sequence of zero-byte operations (see
+    /// [`max7358`]).
+    fn send_konami_code(
+        &self,
+        addr: u8,
+        ops: &[I2cKonamiCode],
+    ) -> Result<(), ResponseCode>;
+
+    /// Configures a pin as an output, driven high.
+    fn configure_output(&self, pin: &P);
+
+    /// Drives a pin (already configured as an output) low or high.
+    fn set_output(&self, pin: &P, high: bool);
+}
+
+///
+/// A trait to express an I2C mux driver.
+///
+pub trait I2cMuxDriver<P> {
+    /// Configure the mux, specifying the mux and the hardware upstream of it.
+    fn configure(
+        &self,
+        mux: &I2cMux<P>,
+        hardware: &dyn I2cMuxHardware<P>,
+    ) -> Result<(), ResponseCode>;
+
+    /// Reset the mux
+    fn reset(
+        &self,
+        mux: &I2cMux<P>,
+        hardware: &dyn I2cMuxHardware<P>,
+    ) -> Result<(), ResponseCode>;
+
+    /// Enable the specified segment on the specified mux
+    fn enable_segment(
+        &self,
+        mux: &I2cMux<P>,
+        hardware: &dyn I2cMuxHardware<P>,
+        segment: drv_i2c_api::Segment,
+    ) -> Result<(), ResponseCode>;
+}
+
+pub struct I2cMux<'a, P> {
+    pub controller: Controller,
+    pub port: PortIndex,
+    pub id: Mux,
+    pub driver: &'a dyn I2cMuxDriver<P>,
+    /// pin driven high to enable the mux, if any
+    pub enable: Option<P>,
+    /// active-low reset pin, if any
+    pub reset: Option<P>,
+    pub address: u8,
+}
+
+impl<P> I2cMux<'_, P> {
+    /// A convenience routine to translate an error induced by in-band
+    /// management into one that can be returned to a caller
+    fn error_code(&self, code: ResponseCode) -> ResponseCode {
+        match code {
+            ResponseCode::NoDevice => ResponseCode::BadMuxAddress,
+            ResponseCode::NoRegister => ResponseCode::BadMuxRegister,
+            ResponseCode::BusLocked => ResponseCode::BusLockedMux,
+            ResponseCode::BusReset => ResponseCode::BusResetMux,
+            _ => code,
+        }
+    }
+
+    fn configure(
+        &self,
+        hardware: &dyn I2cMuxHardware<P>,
+    ) -> Result<(), ResponseCode> {
+        //
+        // Both our enable pin and our (active-low) reset pin are to be
+        // driven high.
+        //
+        for pin in self.enable.iter().chain(self.reset.iter()) {
+            hardware.configure_output(pin);
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// Resets the mux by pulsing its reset pin -- or, if it has none, by
+    /// bouncing its enable pin.  On all of our parts, this disconnects all
+    /// segments.
+    ///
+    fn reset(
+        &self,
+        hardware: &dyn I2cMuxHardware<P>,
+    ) -> Result<(), ResponseCode> {
+        if let Some(pin) = self.reset.as_ref().or(self.enable.as_ref()) {
+            hardware.set_output(pin, false);
+            hardware.set_output(pin, true);
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// Writes the bytes in `wbuf` to the mux and then reads `rbuf` back from
+    /// it, translating any error.
+    ///
+    fn write_read(
+        &self,
+        hardware: &dyn I2cMuxHardware<P>,
+        wbuf: &[u8],
+        rbuf: &mut [u8],
+    ) -> Result<(), ResponseCode> {
+        hardware
+            .write_read(
+                self.address,
+                wbuf.len(),
+                &|pos| Some(wbuf[pos]),
+                ReadLength::Fixed(rbuf.len()),
+                &mut |pos, byte| {
+                    rbuf[pos] = byte;
+                    Some(())
+                },
+            )
+            .map_err(|code| self.error_code(code))
+    }
+}
@@ -0,0 +1,260 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Driver for the LTC4306 I2C mux
+//!
+//! We stick with the LTC4306 nomenclature, which has segments (buses)
+//! starting at 1, and names the registers with their number.
+
+use crate::*;
+use bitfield::bitfield;
+use drv_i2c_api::Segment;
+use userlib::*;
+
+pub const REGISTER_0: u8 = 0;
+pub const REGISTER_1: u8 = 1;
+pub const REGISTER_2: u8 = 2;
+pub const REGISTER_3: u8 = 3;
+
+bitfield! {
+    #[derive(Copy, Clone, PartialEq)]
+    pub struct Register0(u8);
+    connected, set_connected: 7;
+    not_alert1, _: 6;
+    not_alert2, _: 5;
+    not_alert3, _: 4;
+    not_alert4, _: 3;
+    not_failed, set_not_failed: 2;
+    latched_timeout, _: 1;
+    timeout, _: 0;
+}
+
+bitfield! {
+    #[derive(Copy, Clone, PartialEq)]
+    pub struct Register1(u8);
+    upstream_accelerators_enable, set_upstream_accelerators_enable: 7;
+    downstream_accelerators_enable, set_downstream_accelerators_enable: 6;
+    gpio1_output_state, set_gpio1_output_state: 5;
+    gpio2_output_state, set_gpio2_output_state: 4;
+    gpio1_logic_state, _: 1;
+    gpio2_logic_state, _: 0;
+}
+
+#[derive(Copy, Clone, Debug, FromPrimitive, PartialEq)]
+pub enum Timeout {
+    TimeoutDisabled = 0b00,
+    Timeout30ms = 0b01,
+    Timeout15ms = 0b10,
+    Timeout7point5ms = 0b11,
+}
+
+impl From<u8> for Timeout {
+    fn from(value: u8) -> Self {
+        Timeout::from_u8(value).unwrap()
+    }
+}
+
+impl From<Timeout> for u8 {
+    fn from(value: Timeout) -> Self {
+        value as u8
+    }
+}
+
+bitfield! {
+    #[derive(Copy, Clone, PartialEq)]
+    pub struct Register2(u8);
+    gpio1_mode_input, set_gpio1_mode_input: 7;
+    gpio2_mode_input, set_gpio2_mode_input: 6;
+    connect_regardless, set_connect_regardless: 5;
+    gpio1_push_pull, set_gpio1_push_pull: 4;
+    gpio2_push_pull, set_gpio2_push_pull: 3;
+    mass_write_enabled, set_mass_write_enabled: 2;
+    from into Timeout, timeout, set_timeout: 1, 0;
+}
+
+bitfield! {
+    #[derive(Copy, Clone, PartialEq)]
+    pub struct Register3(u8);
+    bus1_connected, set_bus1_connected: 7;
+    bus2_connected, set_bus2_connected: 6;
+    bus3_connected, set_bus3_connected: 5;
+    bus4_connected, set_bus4_connected: 4;
+    bus1_active, _: 3;
+    bus2_active, _: 2;
+    bus3_active, _: 1;
+    bus4_active, _: 0;
+}
+
+///
+/// Returns the value of register 3 that connects the specified segment (and
+/// only that segment), or `None` if the part doesn't have it.
+///
+pub fn control(segment: Segment) -> Option<Register3> {
+    let mut reg3 = Register3(0);
+
+    match segment {
+        Segment::S1 => reg3.set_bus1_connected(true),
+        Segment::S2 => reg3.set_bus2_connected(true),
+        Segment::S3 => reg3.set_bus3_connected(true),
+        Segment::S4 => reg3.set_bus4_connected(true),
+        _ => return None,
+    }
+
+    Some(reg3)
+}
+
+///
+/// Returns the segment connected by a value of register 3, or `None` if
+/// either no segment or more than one segment is connected.
+///
+pub fn connected(reg3: Register3) -> Option<Segment> {
+    match (
+        reg3.bus1_connected(),
+        reg3.bus2_connected(),
+        reg3.bus3_connected(),
+        reg3.bus4_connected(),
+    ) {
+        (true, false, false, false) => Some(Segment::S1),
+        (false, true, false, false) => Some(Segment::S2),
+        (false, false, true, false) => Some(Segment::S3),
+        (false, false, false, true) => Some(Segment::S4),
+        _ => None,
+    }
+}
+
+///
+/// Interprets register 0 after a segment has been connected.
+///
+pub fn status(reg0: Register0) -> Result<(), ResponseCode> {
+    if !reg0.not_failed() {
+        Err(ResponseCode::SegmentDisconnected)
+    } else if !reg0.connected() {
+        Err(ResponseCode::MuxDisconnected)
+    } else {
+        Ok(())
+    }
+}
+
+pub struct Ltc4306;
+
+fn read_reg_u8<P>(
+    mux: &I2cMux<P>,
+    hardware: &dyn I2cMuxHardware<P>,
+    reg: u8,
+) -> Result<u8, ResponseCode> {
+    let mut rval = [0u8; 1];
+    mux.write_read(hardware, &[reg], &mut rval)?;
+    Ok(rval[0])
+}
+
+fn write_reg_u8<P>(
+    mux: &I2cMux<P>,
+    hardware: &dyn I2cMuxHardware<P>,
+    reg: u8,
+    val: u8,
+) -> Result<(), ResponseCode> {
+    mux.write_read(hardware, &[reg, val], &mut [])
+}
+
+impl<P> I2cMuxDriver<P> for Ltc4306 {
+    fn configure(
+        &self,
+        mux: &I2cMux<P>,
+        hardware: &dyn I2cMuxHardware<P>,
+    ) -> Result<(), ResponseCode> {
+        mux.configure(hardware)
+    }
+
+    fn enable_segment(
+        &self,
+        mux: &I2cMux<P>,
+        hardware: &dyn I2cMuxHardware<P>,
+        segment: Segment,
+    ) -> Result<(), ResponseCode> {
+        let reg3 = control(segment).ok_or(ResponseCode::SegmentNotFound)?;
+
+        write_reg_u8(mux, hardware, REGISTER_3, reg3.0)?;
+        let reg0 = Register0(read_reg_u8(mux, hardware, REGISTER_0)?);
+
+        status(reg0)
+    }
+
+    fn reset(
+        &self,
+        mux: &I2cMux<P>,
+        hardware: &dyn I2cMuxHardware<P>,
+    ) -> Result<(), ResponseCode> {
+        mux.reset(hardware)
+    }
+}
+
+///
+/// Virtual LTC4306, for a task emulating the part as a target.  The part is
+/// pretty simple, but this virtualization is even simpler:  we do not
+/// support enabling any combination of segments, and don't support any of
+/// its lockup detection.
+///
+pub mod target {
+    use super::*;
+
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    pub enum State {
+        /// idle
+        Idle,
+        /// received REGISTER_3; presumed write
+        AwaitingSegment,
+        /// received REGISTER_0; presumed read
+        TransmitStatus,
+        /// something has gone wrong
+        Error,
+        /// operation is complete
+        Done,
+    }
+
+    impl State {
+        pub fn init() -> Self {
+            State::Idle
+        }
+
+        ///
+        /// Handles a received byte, calling `segment` with the index
+        /// (starting at 0) of a newly connected segment -- or with `None`
+        /// if no single segment is connected.
+        ///
+        pub fn rx(
+            &self,
+            byte: u8,
+            mut segment: impl FnMut(Option<u8>),
+        ) -> Self {
+            match self {
+                State::AwaitingSegment => {
+                    segment(connected(Register3(byte)).map(|s| s as u8 - 1));
+                    State::Done
+                }
+                State::Idle => match byte {
+                    REGISTER_0 => State::TransmitStatus,
+                    REGISTER_3 => State::AwaitingSegment,
+                    _ => State::Error,
+                },
+                _ => State::Error,
+            }
+        }
+
+        pub fn tx(&self) -> (Option<u8>, Self) {
+            match self {
+                State::TransmitStatus => {
+                    //
+                    // We're fine, everything's fine here. How are you?
+                    //
+                    let mut reg0 = Register0(0);
+                    reg0.set_connected(true);
+                    reg0.set_not_failed(true);
+
+                    (Some(reg0.0), State::Done)
+                }
+                _ => (None, State::Error),
+            }
+        }
+    }
+}
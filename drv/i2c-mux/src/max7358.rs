@@ -6,14 +6,12 @@
 
 use crate::*;
 use bitfield::bitfield;
-use drv_i2c_api::{ResponseCode, Segment};
+use drv_i2c_api::Segment;
 use ringbuf::*;
 use userlib::*;
 
-pub struct Max7358;
-
 #[derive(Copy, Clone, Debug, FromPrimitive, PartialEq)]
-enum Register {
+pub enum Register {
     SwitchControl = 0x00,
     Configuration = 0x01,
     FlushOutSequence = 0x02,
@@ -61,6 +59,16 @@ bitfield! {
     interrupt_enabled, set_interrupt_enabled: 0;
 }
 
+///
+/// Returns the switch control value that selects the specified segment (and
+/// only that segment).
+///
+pub fn control(segment: Segment) -> SwitchControl {
+    SwitchControl(1 << (segment as u8 - 1))
+}
+
+pub struct Max7358;
+
 #[derive(Copy, Clone, PartialEq)]
 enum Trace {
     Read(Register, u8),
@@ -70,40 +78,25 @@ enum Trace {
 
 ringbuf!(Trace, 32, Trace::None);
 
-fn read_regs(
-    mux: &I2cMux,
-    controller: &I2cController,
+fn read_regs<P>(
+    mux: &I2cMux<P>,
+    hardware: &dyn I2cMuxHardware<P>,
     rbuf: &mut [u8],
-    ctrl: &I2cControl,
 ) -> Result<(), ResponseCode> {
-    match controller.write_read(
-        mux.address,
-        0,
-        |_| Some(0),
-        ReadLength::Fixed(rbuf.len()),
-        |pos, byte| {
-            rbuf[pos] = byte;
-            Some(())
-        },
-        ctrl,
-    ) {
-        Err(code) => Err(mux.error_code(code)),
-        _ => {
-            for i in 0..rbuf.len() {
-                ringbuf_entry!(Trace::Read(Register::from(i as u8), rbuf[i]));
-            }
-
-            Ok(())
-        }
+    mux.write_read(hardware, &[], rbuf)?;
+
+    for (i, &byte) in rbuf.iter().enumerate() {
+        ringbuf_entry!(Trace::Read(Register::from(i as u8), byte));
     }
+
+    Ok(())
 }
 
-fn write_reg(
-    mux: &I2cMux,
-    controller: &I2cController,
+fn write_reg<P>(
+    mux: &I2cMux<P>,
+    hardware: &dyn I2cMuxHardware<P>,
     reg: Register,
     val: u8,
-    ctrl: &I2cControl,
 ) -> Result<(), ResponseCode> {
     let mut wbuf = [0u8; 3];
 
@@ -116,35 +109,23 @@ fn write_reg(
     let index = reg as usize;
 
     if index > 0 {
-        read_regs(mux, controller, &mut wbuf[0..index], ctrl)?;
+        read_regs(mux, hardware, &mut wbuf[0..index])?;
     }
 
     ringbuf_entry!(Trace::Write(reg, val));
 
     wbuf[index] = val;
 
-    match controller.write_read(
-        mux.address,
-        index + 1,
-        |pos| Some(wbuf[pos]),
-        ReadLength::Fixed(0),
-        |_, _| Some(()),
-        ctrl,
-    ) {
-        Err(code) => Err(mux.error_code(code)),
-        _ => Ok(()),
-    }
+    mux.write_read(hardware, &wbuf[0..=index], &mut [])
 }
 
-impl I2cMuxDriver for Max7358 {
+impl<P> I2cMuxDriver<P> for Max7358 {
     fn configure(
         &self,
-        mux: &I2cMux,
-        controller: &I2cController,
-        gpio: &sys_api::Sys,
-        ctrl: &I2cControl,
+        mux: &I2cMux<P>,
+        hardware: &dyn I2cMuxHardware<P>,
     ) -> Result<(), ResponseCode> {
-        mux.configure(gpio)?;
+        mux.configure(hardware)?;
 
         //
         // The MAX7358 has a really, really regrettable idea:  it has a
@@ -174,9 +155,9 @@ impl I2cMuxDriver for Max7358 {
         // controller entirely several times over.
         //
         let mut scratch = [0u8; 1];
-        read_regs(mux, controller, &mut scratch[0..1], ctrl)?;
+        read_regs(mux, hardware, &mut scratch[0..1])?;
 
-        controller.send_konami_code(
+        hardware.send_konami_code(
             mux.address,
             &[
                 I2cKonamiCode::Write,
@@ -184,57 +165,28 @@ impl I2cMuxDriver for Max7358 {
                 I2cKonamiCode::Write,
                 I2cKonamiCode::Read,
             ],
-            ctrl,
         )?;
 
         let reg = SwitchControl(0);
-        write_reg(mux, controller, Register::SwitchControl, reg.0, ctrl)
+        write_reg(mux, hardware, Register::SwitchControl, reg.0)
     }
 
     fn enable_segment(
         &self,
-        mux: &I2cMux,
-        controller: &I2cController,
+        mux: &I2cMux<P>,
+        hardware: &dyn I2cMuxHardware<P>,
         segment: Segment,
-        ctrl: &I2cControl,
     ) -> Result<(), ResponseCode> {
-        let mut reg = SwitchControl(0);
-
-        match segment {
-            Segment::S1 => {
-                reg.set_channel0_selected(true);
-            }
-            Segment::S2 => {
-                reg.set_channel1_selected(true);
-            }
-            Segment::S3 => {
-                reg.set_channel2_selected(true);
-            }
-            Segment::S4 => {
-                reg.set_channel3_selected(true);
-            }
-            Segment::S5 => {
-                reg.set_channel4_selected(true);
-            }
-            Segment::S6 => {
-                reg.set_channel5_selected(true);
-            }
-            Segment::S7 => {
-                reg.set_channel6_selected(true);
-            }
-            Segment::S8 => {
-                reg.set_channel7_selected(true);
-            }
-        }
-
-        write_reg(mux, controller, Register::SwitchControl, reg.0, ctrl)
+        let reg = control(segment);
+
+        write_reg(mux, hardware, Register::SwitchControl, reg.0)
     }
 
     fn reset(
         &self,
-        mux: &I2cMux,
-        gpio: &sys_api::Sys,
-    ) -> Result<(), drv_i2c_api::ResponseCode> {
-        mux.reset(gpio)
+        mux: &I2cMux<P>,
+        hardware: &dyn I2cMuxHardware<P>,
+    ) -> Result<(), ResponseCode> {
+        mux.reset(hardware)
     }
 }
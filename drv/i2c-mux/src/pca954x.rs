@@ -0,0 +1,111 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Driver for the PCA9545, PCA9546 and PCA9548 I2C muxes
+//!
+//! These parts have a single control register, in which each bit enables a
+//! channel.  The PCA9545 and PCA9546 have four channels; on the PCA9545, the
+//! upper four bits of the control register reflect (read-only) interrupt
+//! state.
+
+use crate::*;
+use bitfield::bitfield;
+use drv_i2c_api::Segment;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Part {
+    Pca9545,
+    Pca9546,
+    Pca9548,
+}
+
+impl Part {
+    pub fn channels(&self) -> u8 {
+        match self {
+            Part::Pca9545 | Part::Pca9546 => 4,
+            Part::Pca9548 => 8,
+        }
+    }
+}
+
+bitfield! {
+    #[derive(Copy, Clone, PartialEq)]
+    pub struct ControlRegister(u8);
+    channel7_enabled, set_channel7_enabled: 7;
+    channel6_enabled, set_channel6_enabled: 6;
+    channel5_enabled, set_channel5_enabled: 5;
+    channel4_enabled, set_channel4_enabled: 4;
+    channel3_enabled, set_channel3_enabled: 3;
+    channel2_enabled, set_channel2_enabled: 2;
+    channel1_enabled, set_channel1_enabled: 1;
+    channel0_enabled, set_channel0_enabled: 0;
+}
+
+///
+/// Returns the control register value that enables the specified segment
+/// (and only that segment), or `None` if the part doesn't have it.
+///
+pub fn control(part: Part, segment: Segment) -> Option<ControlRegister> {
+    let channel = segment as u8 - 1;
+
+    if channel >= part.channels() {
+        return None;
+    }
+
+    Some(ControlRegister(1 << channel))
+}
+
+pub struct Pca9545;
+pub struct Pca9546;
+pub struct Pca9548;
+
+fn enable_segment<P>(
+    part: Part,
+    mux: &I2cMux<P>,
+    hardware: &dyn I2cMuxHardware<P>,
+    segment: Segment,
+) -> Result<(), ResponseCode> {
+    let reg = control(part, segment).ok_or(ResponseCode::SegmentNotFound)?;
+
+    //
+    // This part has but one register -- any write is to the control
+    // register.
+    //
+    mux.write_read(hardware, &[reg.0], &mut [])
+}
+
+macro_rules! pca954x_driver {
+    ($driver:ident, $part:ident) => {
+        impl<P> I2cMuxDriver<P> for $driver {
+            fn configure(
+                &self,
+                mux: &I2cMux<P>,
+                hardware: &dyn I2cMuxHardware<P>,
+            ) -> Result<(), ResponseCode> {
+                mux.configure(hardware)
+            }
+
+            fn enable_segment(
+                &self,
+                mux: &I2cMux<P>,
+                hardware: &dyn I2cMuxHardware<P>,
+                segment: Segment,
+            ) -> Result<(), ResponseCode> {
+                enable_segment(Part::$part, mux, hardware, segment)
+            }
+
+            fn reset(
+                &self,
+                mux: &I2cMux<P>,
+                hardware: &dyn I2cMuxHardware<P>,
+            ) -> Result<(), ResponseCode> {
+                mux.reset(hardware)
+            }
+        }
+    };
+}
+
+pca954x_driver!(Pca9545, Pca9545);
+pca954x_driver!(Pca9546, Pca9546);
+pca954x_driver!(Pca9548, Pca9548);
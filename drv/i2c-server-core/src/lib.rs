@@ -0,0 +1,880 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Controller-independent I2C server logic
+//!
+//! The I2C servers for each family of controller (`drv-stm32h7-i2c-server`
+//! and `drv-lpc55-i2c-server`) implement the `I2c` interface defined in
+//! `idl/i2c.idol` with the same semantics.  This crate contains everything
+//! in that implementation that doesn't depend on the controller:  selecting
+//! bus segments (including the pins of their ports, and their muxes), SMBus
+//! block reads, PECs and PMBus pages, scanning, caching device presence,
+//! recovering from a locked bus, and keeping error statistics (see
+//! [`stats`]).  Each server implements [`I2cHardware`] for its controllers,
+//! pins and muxes, and runs a [`Server`] on top of it.
+
+#![no_std]
+
+use drv_i2c_api::*;
+use fixedmap::*;
+use idol_runtime::{Leased, RequestError, R, W};
+use ringbuf::*;
+use userlib::*;
+
+pub mod stats;
+
+use stats::{BusSegment, SegmentStats};
+
+/// The PMBus `PAGE` command, written to select a rail on multi-rail devices
+const PMBUS_PAGE: u8 = 0x00;
+
+///
+/// A device that the application configuration places on a bus that this
+/// server controls.  The server keeps a presence cache for each such device.
+///
+pub struct I2cDeviceConfig {
+    pub controller: Controller,
+    pub port: PortIndex,
+    pub segment: Option<(Mux, Segment)>,
+    pub address: u8,
+    pub removable: bool,
+}
+
+///
+/// The controller-specific parts of an I2C server:  its controllers, the
+/// pins that connect them to their ports, and its muxes.  Each of these is
+/// identified by its index (from 0 up to [`I2cHardware::ncontrollers`],
+/// [`I2cHardware::npins`] and [`I2cHardware::nmuxes`], respectively).
+///
+pub trait I2cHardware {
+    /// Returns the number of controllers.
+    fn ncontrollers(&self) -> usize;
+
+    /// Returns the identifier of a controller.
+    fn controller(&self, index: usize) -> Controller;
+
+    /// Configures every controller.  This is called once, by
+    /// [`Server::new`], after a port has been selected on each.
+    fn configure_controllers(&self);
+
+    /// Performs a write followed by a read (either of which may be empty)
+    /// on a controller, fetching bytes to write from `getbyte` and passing
+    /// bytes read to `putbyte`.
+    fn write_read(
+        &self,
+        controller: usize,
+        addr: u8,
+        wlen: usize,
+        getbyte: impl Fn(usize) -> Option<u8>,
+        rlen: ReadLength,
+        putbyte: impl FnMut(usize, u8) -> Option<()>,
+    ) -> Result<(), ResponseCode>;
+
+    /// Resets a controller.
+    fn reset(&self, controller: usize);
+
+    /// Returns the number of pins.  (Each may be a set of GPIO pins.)
+    fn npins(&self) -> usize;
+
+    /// Returns the controller and port that a pin connects.
+    fn pin(&self, index: usize) -> (Controller, PortIndex);
+
+    /// Configures a pin for its I2C function -- or, if `enable` is false,
+    /// deconfigures it, such that it doesn't drive its port.
+    fn configure_pin(&self, index: usize, enable: bool);
+
+    /// Returns the number of muxes.
+    fn nmuxes(&self) -> usize;
+
+    /// Returns the controller, port and identifier of a mux.
+    fn mux(&self, index: usize) -> (Controller, PortIndex, Mux);
+
+    /// Configures a mux on its controller; its port must already be
+    /// selected.
+    fn configure_mux(
+        &self,
+        index: usize,
+        controller: usize,
+    ) -> Result<(), ResponseCode>;
+
+    /// Enables a segment on a mux, disabling any other; its port must
+    /// already be selected.
+    fn enable_segment(
+        &self,
+        index: usize,
+        controller: usize,
+        segment: Segment,
+    ) -> Result<(), ResponseCode>;
+
+    /// Resets a mux, disconnecting all of its segments.
+    fn reset_mux(
+        &self,
+        index: usize,
+        controller: usize,
+    ) -> Result<(), ResponseCode>;
+}
+
+ringbuf!(Option<ResponseCode>, 16, None);
+
+type PortMap = FixedMap<Controller, PortIndex, 8>;
+type MuxMap = FixedMap<Mux, Segment, 4>;
+
+///
+/// An I2C server for `N` configured devices.
+///
+pub struct Server<H: I2cHardware, const N: usize> {
+    hardware: H,
+    devices: [I2cDeviceConfig; N],
+    presence: [Presence; N],
+    segment_stats: SegmentStats,
+    device_stats: [ErrorStats; N],
+    portmap: PortMap,
+    muxmap: MuxMap,
+}
+
+impl<H: I2cHardware, const N: usize> Server<H, N> {
+    ///
+    /// Creates a server, selecting a port on each controller, configuring
+    /// the controllers, and then configuring every mux.  The controllers
+    /// must already be enabled.
+    ///
+    pub fn new(hardware: H, devices: [I2cDeviceConfig; N]) -> Self {
+        let mut server = Self {
+            hardware,
+            devices,
+            presence: [Presence::Unknown; N],
+            segment_stats: SegmentStats::new(),
+            device_stats: [ErrorStats::default(); N],
+            portmap: PortMap::new(),
+            muxmap: MuxMap::new(),
+        };
+
+        server.configure_pins();
+        server.hardware.configure_controllers();
+        server.configure_muxes();
+        server
+    }
+
+    ///
+    /// Fields messages.
+    ///
+    pub fn run(&mut self) -> ! {
+        let mut buffer = [0; idl::INCOMING_SIZE];
+
+        loop {
+            idol_runtime::dispatch(&mut buffer, self);
+        }
+    }
+
+    ///
+    /// Returns the index of a controller.
+    ///
+    fn lookup_controller(
+        &self,
+        controller: Controller,
+    ) -> Result<usize, ResponseCode> {
+        (0..self.hardware.ncontrollers())
+            .find(|&index| self.hardware.controller(index) == controller)
+            .ok_or(ResponseCode::BadController)
+    }
+
+    ///
+    /// Configures the pins of the first port of each controller.
+    ///
+    fn configure_pins(&mut self) {
+        for index in 0..self.hardware.npins() {
+            let (controller, port) = self.hardware.pin(index);
+
+            match self.portmap.get(controller) {
+                Some(current) if current != port => {
+                    //
+                    // If we have already enabled this controller with a
+                    // different port, we don't want to enable this pin.
+                    //
+                    continue;
+                }
+                _ => {}
+            }
+
+            self.hardware.configure_pin(index, true);
+            self.portmap.insert(controller, port);
+        }
+    }
+
+    ///
+    /// Configures the pins of a controller to select the specified port,
+    /// failing if the controller or the port is unknown.
+    ///
+    fn configure_port(
+        &mut self,
+        controller: Controller,
+        port: PortIndex,
+    ) -> Result<(), ResponseCode> {
+        self.lookup_controller(controller)?;
+
+        let npins = self.hardware.npins();
+
+        (0..npins)
+            .find(|&index| self.hardware.pin(index) == (controller, port))
+            .ok_or(ResponseCode::BadPort)?;
+
+        let current = self.portmap.get(controller).unwrap();
+
+        if current == port {
+            return Ok(());
+        }
+
+        //
+        // We will now iterate over all pins, de-configuring any that match
+        // our old port, and configuring any that match our new port.
+        //
+        for index in 0..npins {
+            let (c, p) = self.hardware.pin(index);
+
+            if c != controller {
+                continue;
+            }
+
+            if p == current {
+                self.hardware.configure_pin(index, false);
+            } else if p == port {
+                self.hardware.configure_pin(index, true);
+            }
+        }
+
+        self.portmap.insert(controller, port);
+
+        Ok(())
+    }
+
+    ///
+    /// Performs a write followed by a read on a controller (see
+    /// [`I2cHardware::write_read`]).
+    ///
+    fn transfer(
+        &self,
+        controller: Controller,
+        addr: u8,
+        wlen: usize,
+        getbyte: impl Fn(usize) -> Option<u8>,
+        rlen: ReadLength,
+        putbyte: impl FnMut(usize, u8) -> Option<()>,
+    ) -> Result<(), ResponseCode> {
+        let controller = self.lookup_controller(controller)?;
+
+        self.hardware
+            .write_read(controller, addr, wlen, getbyte, rlen, putbyte)
+    }
+
+    fn configure_muxes(&mut self) {
+        for index in 0..self.hardware.nmuxes() {
+            let (controller, port, _) = self.hardware.mux(index);
+            self.configure_port(controller, port).unwrap();
+            let c = self.lookup_controller(controller).unwrap();
+
+            loop {
+                match self.hardware.configure_mux(index, c) {
+                    Ok(_) => {
+                        break;
+                    }
+                    Err(code) => {
+                        ringbuf_entry!(Some(code));
+                        self.reset_if_needed(code, controller, port, None);
+                    }
+                }
+            }
+        }
+    }
+
+    fn configure_mux(
+        &mut self,
+        controller: Controller,
+        port: PortIndex,
+        mux: Option<(Mux, Segment)>,
+    ) -> Result<(), ResponseCode> {
+        let (id, segment) = match mux {
+            Some(mux) => mux,
+            None => return Ok(()),
+        };
+
+        let index = (0..self.hardware.nmuxes())
+            .find(|&index| self.hardware.mux(index) == (controller, port, id))
+            .ok_or(ResponseCode::MuxNotFound)?;
+        let c = self.lookup_controller(controller)?;
+
+        // Determine if the current segment matches our specified segment...
+        if let Some(current) = self.muxmap.get(id) {
+            if current == segment {
+                return Ok(());
+            }
+
+            // Beyond this point, we want any failure to set our new
+            // segment to leave our segment unset rather than having
+            // it point to the old segment.
+            self.muxmap.remove(id);
+        }
+
+        // If we're here, our mux is valid, but the current segment is
+        // not the specfied segment; we will now call upon our
+        // driver to enable this segment.
+        self.hardware.enable_segment(index, c, segment)?;
+        self.muxmap.insert(id, segment);
+
+        Ok(())
+    }
+
+    ///
+    /// Resets the controller and/or muxes as the specified error requires,
+    /// returning true if a reset was performed.  A locked or reset bus resets
+    /// the controller along with every mux on the port, as a hung segment
+    /// behind any of them may be the culprit; a mux that has lost its
+    /// upstream connection (or the connection to its segment) is itself
+    /// reset.  Any mux that is reset will have its segments disconnected, so
+    /// we forget the segment that we had selected on it.
+    ///
+    fn reset_if_needed(
+        &mut self,
+        code: ResponseCode,
+        controller: Controller,
+        port: PortIndex,
+        mux: Option<(Mux, Segment)>,
+    ) -> bool {
+        ringbuf_entry!(Some(code));
+
+        let all = match code {
+            ResponseCode::BusLocked
+            | ResponseCode::BusLockedMux
+            | ResponseCode::BusReset
+            | ResponseCode::BusResetMux
+            | ResponseCode::ControllerLocked => true,
+            ResponseCode::MuxDisconnected
+            | ResponseCode::SegmentDisconnected => false,
+            _ => {
+                return false;
+            }
+        };
+
+        let c = match self.lookup_controller(controller) {
+            Ok(c) => c,
+            Err(_) => return false,
+        };
+
+        // First, bounce our I2C controller if the bus itself is in trouble
+        if all {
+            self.hardware.reset(c);
+        }
+
+        // And now reset the muxes, eating any errors.
+        for index in 0..self.hardware.nmuxes() {
+            let (mux_controller, mux_port, id) = self.hardware.mux(index);
+
+            if mux_controller != controller || mux_port != port {
+                continue;
+            }
+
+            if !all && mux.map(|(id, _)| id) != Some(id) {
+                continue;
+            }
+
+            ringbuf_entry!(None);
+            let _ = self.hardware.reset_mux(index, c);
+            self.muxmap.remove(id);
+        }
+
+        true
+    }
+
+    ///
+    /// Decodes a bus segment as specified by a client, without selecting it.
+    ///
+    fn segment(
+        controller: u8,
+        port: u8,
+        mux: u8,
+        segment: u8,
+    ) -> Result<BusSegment, ResponseCode> {
+        let controller = Controller::from_u8(controller)
+            .ok_or(ResponseCode::BadController)?;
+
+        let mux = match (mux, segment) {
+            (0, 0) => None,
+            (mux, segment) => Some((
+                Mux::from_u8(mux).ok_or(ResponseCode::BadMux)?,
+                Segment::from_u8(segment).ok_or(ResponseCode::BadSegment)?,
+            )),
+        };
+
+        Ok((controller, PortIndex(port), mux))
+    }
+
+    ///
+    /// Decodes a bus segment as specified by a client, and configures the
+    /// port and mux (if any) to select it.
+    ///
+    fn select(
+        &mut self,
+        controller: u8,
+        port: u8,
+        mux: u8,
+        segment: u8,
+    ) -> Result<BusSegment, ResponseCode> {
+        let (controller, port, mux) =
+            Self::segment(controller, port, mux, segment)?;
+
+        self.configure_port(controller, port)?;
+
+        match self.configure_mux(controller, port, mux) {
+            Ok(_) => Ok((controller, port, mux)),
+            Err(code) => {
+                self.error(code, controller, port, mux, None);
+                Err(code)
+            }
+        }
+    }
+
+    ///
+    /// Returns the index of a configured device, if any.
+    ///
+    fn device_index(
+        &self,
+        controller: Controller,
+        port: PortIndex,
+        mux: Option<(Mux, Segment)>,
+        addr: u8,
+    ) -> Option<usize> {
+        self.devices.iter().position(|d| {
+            d.controller == controller
+                && d.port == port
+                && d.segment == mux
+                && d.address == addr
+        })
+    }
+
+    ///
+    /// Handles an error on a bus segment, resetting the controller and mux
+    /// if needed, and records it against the segment -- and against the
+    /// device, if one was being addressed and it is configured.
+    ///
+    fn error(
+        &mut self,
+        code: ResponseCode,
+        controller: Controller,
+        port: PortIndex,
+        mux: Option<(Mux, Segment)>,
+        addr: Option<u8>,
+    ) {
+        let reset = self.reset_if_needed(code, controller, port, mux);
+        let segment = (controller, port, mux);
+
+        if let Some(stats) = self.segment_stats.entry(segment) {
+            stats::record(stats, code, reset);
+        }
+
+        let device = addr
+            .and_then(|addr| self.device_index(controller, port, mux, addr));
+
+        if let Some(index) = device {
+            stats::record(&mut self.device_stats[index], code, reset);
+        }
+    }
+
+    ///
+    /// Updates the presence cache for a configured device given the result
+    /// of a transaction with it.  Only results that tell us something about
+    /// the device's presence (success or a NACK of its address) are cached.
+    ///
+    fn update_presence(
+        &mut self,
+        controller: Controller,
+        port: PortIndex,
+        mux: Option<(Mux, Segment)>,
+        addr: u8,
+        result: Result<(), ResponseCode>,
+    ) {
+        let presence = match result {
+            Ok(_) => Presence::Present,
+            Err(ResponseCode::NoDevice) => Presence::Absent,
+            Err(_) => return,
+        };
+
+        if let Some(index) = self.device_index(controller, port, mux, addr) {
+            self.presence[index] = presence;
+        }
+    }
+
+    ///
+    /// Probes for a device on an already selected bus segment by performing
+    /// a one-byte read, updating its cached presence.
+    ///
+    fn probe(
+        &mut self,
+        controller: Controller,
+        port: PortIndex,
+        mux: Option<(Mux, Segment)>,
+        addr: u8,
+    ) -> Result<Presence, ResponseCode> {
+        let result = self.transfer(
+            controller,
+            addr,
+            0,
+            |_| None,
+            ReadLength::Fixed(1),
+            |_, _| Some(()),
+        );
+
+        self.update_presence(controller, port, mux, addr, result);
+
+        match result {
+            Ok(_) => Ok(Presence::Present),
+            Err(ResponseCode::NoDevice) => Ok(Presence::Absent),
+            Err(code) => {
+                self.error(code, controller, port, mux, Some(addr));
+                Err(code)
+            }
+        }
+    }
+
+    ///
+    /// Selects a PMBus page on a device on an already selected bus segment
+    /// by writing to its `PAGE` register, appending a PEC byte if `pec` is
+    /// set.
+    ///
+    fn select_page(
+        &mut self,
+        controller: Controller,
+        port: PortIndex,
+        mux: Option<(Mux, Segment)>,
+        addr: u8,
+        page: u8,
+        pec: bool,
+    ) -> Result<(), ResponseCode> {
+        let mut crc = Pec::default();
+
+        for byte in &[addr << 1, PMBUS_PAGE, page] {
+            crc.update(*byte);
+        }
+
+        let wbuf = [PMBUS_PAGE, page, crc.value()];
+        let wlen = if pec { 3 } else { 2 };
+
+        let result = self.transfer(
+            controller,
+            addr,
+            wlen,
+            |pos| Some(wbuf[pos]),
+            ReadLength::Fixed(0),
+            |_, _| Some(()),
+        );
+
+        self.update_presence(controller, port, mux, addr, result);
+
+        if let Err(code) = result {
+            self.error(code, controller, port, mux, Some(addr));
+        }
+
+        result
+    }
+
+    ///
+    /// Performs a write followed by a read (either of which may be empty),
+    /// returning the number of bytes read.  If `page` is specified, the
+    /// PMBus page is selected first; as we handle one request at a time, no
+    /// other client's transaction can come between the two.  If `block` is
+    /// set, the read is an SMBus block read; if `pec` is set, the
+    /// transaction is protected by an SMBus Packet Error Code.
+    ///
+    #[allow(clippy::too_many_arguments)]
+    fn write_read(
+        &mut self,
+        controller: u8,
+        port: u8,
+        mux: u8,
+        segment: u8,
+        addr: u8,
+        wbuf: Leased<R, [u8]>,
+        rbuf: Leased<W, [u8]>,
+        page: Option<u8>,
+        block: bool,
+        pec: bool,
+    ) -> Result<usize, ResponseCode> {
+        if let Some(_) = ReservedAddress::from_u8(addr) {
+            return Err(ResponseCode::ReservedAddress);
+        }
+
+        let (controller, port, mux) =
+            self.select(controller, port, mux, segment)?;
+
+        if wbuf.len() == 0 && rbuf.len() == 0 {
+            // We must have either a write OR a read -- while perhaps valid to
+            // support both being zero as a way of testing an address for a
+            // NACK, it's not a mode that we (currently) support.
+            return Err(ResponseCode::BadArg);
+        }
+
+        //
+        // With PEC, the PEC byte follows the read if there is one, and
+        // otherwise follows the write.
+        //
+        let rpec = pec && (block || rbuf.len() > 0);
+        let wpec = pec && !rpec;
+
+        let wlen = wbuf.len() + if wpec { 1 } else { 0 };
+        let rlen = match (block, rpec) {
+            (true, true) => ReadLength::VariablePec,
+            (true, false) => ReadLength::Variable,
+            (false, true) => ReadLength::Fixed(rbuf.len() + 1),
+            (false, false) => ReadLength::Fixed(rbuf.len()),
+        };
+
+        if wlen > 255 || rbuf.len() + if rpec { 1 } else { 0 } > 255 {
+            // For now, we don't support writing or reading more than 255
+            // bytes.
+            return Err(ResponseCode::BadArg);
+        }
+
+        if let Some(page) = page {
+            self.select_page(controller, port, mux, addr, page, pec)?;
+        }
+
+        //
+        // Our PEC covers every byte of the transaction, including each
+        // address byte.  We compute it over our write up front, as our write
+        // bytes are fetched one at a time as they are sent.
+        //
+        let mut crc = Pec::default();
+
+        if pec && wbuf.len() > 0 {
+            crc.update(addr << 1);
+
+            for pos in 0..wbuf.len() {
+                crc.update(wbuf.read_at(pos).ok_or(ResponseCode::BadArg)?);
+            }
+        }
+
+        let wcrc = crc.value();
+
+        if rpec {
+            crc.update((addr << 1) | 1);
+        }
+
+        // For a block read with PEC, the byte count is passed to us first.
+        let skip = if block && rpec { 1 } else { 0 };
+        let mut datalen = rbuf.len();
+        let mut received = None;
+        let mut nread = 0;
+
+        let result = self.transfer(
+            controller,
+            addr,
+            wlen,
+            |pos| {
+                if pos < wbuf.len() {
+                    wbuf.read_at(pos)
+                } else {
+                    Some(wcrc)
+                }
+            },
+            rlen,
+            |pos, byte| {
+                if pos < skip {
+                    datalen = byte as usize;
+                    crc.update(byte);
+                    return Some(());
+                }
+
+                let pos = pos - skip;
+
+                if rpec {
+                    if pos == datalen {
+                        received = Some(byte);
+                        return Some(());
+                    }
+
+                    crc.update(byte);
+                }
+
+                if pos + 1 > nread {
+                    nread = pos + 1;
+                }
+
+                rbuf.write_at(pos, byte).ok()
+            },
+        );
+
+        self.update_presence(controller, port, mux, addr, result);
+
+        match result {
+            Err(code) => {
+                self.error(code, controller, port, mux, Some(addr));
+                Err(code)
+            }
+            Ok(_) if rpec && received != Some(crc.value()) => {
+                let code = ResponseCode::PecMismatch;
+                self.error(code, controller, port, mux, Some(addr));
+                Err(code)
+            }
+            Ok(_) => Ok(nread),
+        }
+    }
+
+    fn scan(
+        &mut self,
+        controller: u8,
+        port: u8,
+        mux: u8,
+        segment: u8,
+        found: &mut ScanResult,
+    ) -> Result<usize, ResponseCode> {
+        let (controller, port, mux) =
+            self.select(controller, port, mux, segment)?;
+
+        let mut count = 0;
+
+        for addr in 0..128u8 {
+            if let Some(_) = ReservedAddress::from_u8(addr) {
+                continue;
+            }
+
+            if self.probe(controller, port, mux, addr)? == Presence::Present {
+                found.0[addr as usize / 8] |= 1 << (addr % 8);
+                count += 1;
+            }
+        }
+
+        Ok(count)
+    }
+
+    fn device_present(
+        &mut self,
+        controller: u8,
+        port: u8,
+        mux: u8,
+        segment: u8,
+        addr: u8,
+    ) -> Result<Presence, ResponseCode> {
+        if let Some(_) = ReservedAddress::from_u8(addr) {
+            return Err(ResponseCode::ReservedAddress);
+        }
+
+        let (controller, port, mux) =
+            self.select(controller, port, mux, segment)?;
+
+        //
+        // If this is a configured device whose presence we know, we return
+        // it without touching the bus -- unless it's a removable device that
+        // was absent, in which case we probe it to see if it has since been
+        // inserted.
+        //
+        let index = self.device_index(controller, port, mux, addr);
+
+        if let Some(index) = index {
+            match self.presence[index] {
+                Presence::Present => return Ok(Presence::Present),
+                Presence::Absent if !self.devices[index].removable => {
+                    return Ok(Presence::Absent);
+                }
+                _ => {}
+            }
+        }
+
+        self.probe(controller, port, mux, addr)
+    }
+}
+
+impl<H: I2cHardware, const N: usize> idl::InOrderI2cImpl for Server<H, N> {
+    fn write_read(
+        &mut self,
+        _: &RecvMessage,
+        controller: u8,
+        port: u8,
+        mux: u8,
+        segment: u8,
+        address: u8,
+        page: Option<u8>,
+        block: bool,
+        pec: bool,
+        wbuf: Leased<R, [u8]>,
+        rbuf: Leased<W, [u8]>,
+    ) -> Result<usize, RequestError<ResponseCode>> {
+        Ok(Server::write_read(
+            self, controller, port, mux, segment, address, wbuf, rbuf, page,
+            block, pec,
+        )?)
+    }
+
+    fn scan(
+        &mut self,
+        _: &RecvMessage,
+        controller: u8,
+        port: u8,
+        mux: u8,
+        segment: u8,
+        found: Leased<W, [u8]>,
+    ) -> Result<usize, RequestError<ResponseCode>> {
+        if found.len() < SCAN_BITMAP_SIZE {
+            return Err(ResponseCode::BadArg.into());
+        }
+
+        let mut result = ScanResult::default();
+        let count =
+            Server::scan(self, controller, port, mux, segment, &mut result)?;
+
+        found
+            .write_range(0..SCAN_BITMAP_SIZE, &result.0)
+            .map_err(|_| RequestError::went_away())?;
+
+        Ok(count)
+    }
+
+    fn device_present(
+        &mut self,
+        _: &RecvMessage,
+        controller: u8,
+        port: u8,
+        mux: u8,
+        segment: u8,
+        address: u8,
+    ) -> Result<Presence, RequestError<ResponseCode>> {
+        Ok(Server::device_present(
+            self, controller, port, mux, segment, address,
+        )?)
+    }
+
+    fn bus_stats(
+        &mut self,
+        _: &RecvMessage,
+        controller: u8,
+        port: u8,
+        mux: u8,
+        segment: u8,
+    ) -> Result<ErrorStats, RequestError<ResponseCode>> {
+        let segment = Self::segment(controller, port, mux, segment)?;
+
+        Ok(self.segment_stats.get(segment))
+    }
+
+    fn device_stats(
+        &mut self,
+        _: &RecvMessage,
+        controller: u8,
+        port: u8,
+        mux: u8,
+        segment: u8,
+        address: u8,
+    ) -> Result<ErrorStats, RequestError<ResponseCode>> {
+        let (controller, port, mux) =
+            Self::segment(controller, port, mux, segment)?;
+
+        match self.device_index(controller, port, mux, address) {
+            Some(index) => Ok(self.device_stats[index]),
+            None => Err(ResponseCode::BadArg.into()),
+        }
+    }
+}
+
+mod idl {
+    use super::{ErrorStats, Presence, ResponseCode};
+
+    include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
+}
@@ -0,0 +1,102 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! I2C error statistics
+//!
+//! Errors are counted both by bus segment (that is, by controller, port and
+//! mux segment) and by configured device, allowing a marginal bus -- or a
+//! marginal device -- to be found from telemetry.  Segments are added to
+//! our table as they first see errors; should the table fill, errors on any
+//! additional segments are counted only against their devices.
+
+use drv_i2c_api::{
+    Controller, ErrorStats, Mux, PortIndex, ResponseCode, Segment,
+};
+
+/// A bus segment: a controller, a port, and a mux segment (if any)
+pub type BusSegment = (Controller, PortIndex, Option<(Mux, Segment)>);
+
+/// Maximum number of bus segments for which we keep statistics
+const MAX_SEGMENTS: usize = 16;
+
+pub struct SegmentStats {
+    segments: [Option<(BusSegment, ErrorStats)>; MAX_SEGMENTS],
+}
+
+impl SegmentStats {
+    pub fn new() -> Self {
+        Self {
+            segments: [None; MAX_SEGMENTS],
+        }
+    }
+
+    /// Returns the statistics for a segment; a segment that has never seen
+    /// an error has no entry, and all of its counts are zero.
+    pub fn get(&self, segment: BusSegment) -> ErrorStats {
+        self.segments
+            .iter()
+            .flatten()
+            .find(|(s, _)| *s == segment)
+            .map(|(_, stats)| *stats)
+            .unwrap_or_default()
+    }
+
+    /// Returns the statistics for a segment, adding an entry for it if
+    /// needed (and if there is room).
+    pub fn entry(&mut self, segment: BusSegment) -> Option<&mut ErrorStats> {
+        let index = match self
+            .segments
+            .iter()
+            .position(|e| matches!(e, Some((s, _)) if *s == segment))
+        {
+            Some(index) => index,
+            None => {
+                let index = self.segments.iter().position(|e| e.is_none())?;
+                self.segments[index] = Some((segment, ErrorStats::default()));
+                index
+            }
+        };
+
+        self.segments[index].as_mut().map(|(_, stats)| stats)
+    }
+}
+
+///
+/// Records an error, noting whether the bus was reset to recover from it.
+///
+pub fn record(stats: &mut ErrorStats, code: ResponseCode, reset: bool) {
+    match code {
+        ResponseCode::NoDevice | ResponseCode::NoRegister => {
+            stats.nacks += 1;
+        }
+        ResponseCode::BusLocked
+        | ResponseCode::BusLockedMux
+        | ResponseCode::ControllerLocked => {
+            stats.timeouts += 1;
+        }
+        ResponseCode::BusReset | ResponseCode::BusResetMux => {
+            stats.arbitration_lost += 1;
+        }
+        ResponseCode::PecMismatch => {
+            stats.pec_errors += 1;
+        }
+        _ => {}
+    }
+
+    match code {
+        ResponseCode::BadMuxAddress
+        | ResponseCode::BadMuxRegister
+        | ResponseCode::BusLockedMux
+        | ResponseCode::BusResetMux
+        | ResponseCode::MuxDisconnected
+        | ResponseCode::SegmentDisconnected => {
+            stats.mux_failures += 1;
+        }
+        _ => {}
+    }
+
+    if reset {
+        stats.bus_resets += 1;
+    }
+}
@@ -0,0 +1,29 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Client API for the I2C target server
+//!
+//! The I2C target server emulates devices (as specified in the application
+//! configuration) on a controller operating as an I2C target; this API
+//! allows other tasks to update (and read back) what those devices expose to
+//! the initiator.
+
+#![no_std]
+
+use derive_idol_err::IdolError;
+use userlib::*;
+
+#[derive(Copy, Clone, Debug, FromPrimitive, PartialEq, IdolError)]
+pub enum TargetError {
+    /// No device is emulated at the specified address
+    BadAddress = 1,
+    /// The emulated device is not of the kind that the operation requires
+    BadDevice = 2,
+    /// The emulated device does not have the specified register
+    BadRegister = 3,
+    /// The specified offset or length exceeds the memory or register
+    BadLength = 4,
+}
+
+include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
@@ -0,0 +1,198 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! A driver for the LPC55 I2C interface
+//!
+//! This server implements the `I2c` interface defined in `idl/i2c.idol` by
+//! running the controller-independent server in `drv-i2c-server-core` (and
+//! therefore with the same semantics as the STM32H7 server) on top of the
+//! LPC55 controllers, pins and muxes specified by the application
+//! configuration (`config.i2c`).
+
+#![no_std]
+#![no_main]
+
+use drv_i2c_api::*;
+use drv_i2c_server_core::{I2cHardware, Server};
+use drv_lpc55_gpio_api::{
+    AltFn, Digimode, Gpio, Invert, Mode, Opendrain, Slew,
+};
+use drv_lpc55_i2c::*;
+use drv_lpc55_syscon_api::{Peripheral, Syscon};
+
+use userlib::*;
+
+task_slot!(SYSCON, syscon_driver);
+task_slot!(GPIO, gpio_driver);
+
+include!(concat!(env!("OUT_DIR"), "/i2c_config.rs"));
+
+///
+/// Our controllers, pins and muxes.
+///
+struct Hardware<'a> {
+    controllers: &'a [I2cController<'a>],
+    pins: &'a [I2cPin],
+    muxes: &'a [I2cMux<'a>],
+}
+
+impl<'a> Hardware<'a> {
+    ///
+    /// Returns a mux, along with the bus upstream of it (on the specified
+    /// controller) for its driver.
+    ///
+    fn lookup_mux(
+        &self,
+        index: usize,
+        controller: usize,
+    ) -> (&'a I2cMux<'a>, I2cMuxBus<'a>) {
+        let bus = I2cMuxBus {
+            controller: &self.controllers[controller],
+            gpio: Gpio::from(GPIO.get_task_id()),
+        };
+
+        (&self.muxes[index], bus)
+    }
+}
+
+impl I2cHardware for Hardware<'_> {
+    fn ncontrollers(&self) -> usize {
+        self.controllers.len()
+    }
+
+    fn controller(&self, index: usize) -> Controller {
+        self.controllers[index].controller
+    }
+
+    fn configure_controllers(&self) {
+        for controller in self.controllers {
+            controller.configure();
+        }
+    }
+
+    fn write_read(
+        &self,
+        controller: usize,
+        addr: u8,
+        wlen: usize,
+        getbyte: impl Fn(usize) -> Option<u8>,
+        rlen: ReadLength,
+        putbyte: impl FnMut(usize, u8) -> Option<()>,
+    ) -> Result<(), ResponseCode> {
+        self.controllers[controller]
+            .write_read(addr, wlen, getbyte, rlen, putbyte)
+    }
+
+    fn reset(&self, controller: usize) {
+        let syscon = Syscon::from(SYSCON.get_task_id());
+        self.controllers[controller].reset(&syscon);
+    }
+
+    fn npins(&self) -> usize {
+        self.pins.len()
+    }
+
+    fn pin(&self, index: usize) -> (Controller, PortIndex) {
+        let pin = &self.pins[index];
+        (pin.controller, pin.port)
+    }
+
+    ///
+    /// Configures the pins to their I2C function -- or, to deconfigure them,
+    /// to be GPIOs (which are inputs by default), which will assure that we
+    /// don't leave SCL and SDA pulled high.  We configure them as digital
+    /// with no pull, and rely on the I2C function to drive them open-drain.
+    ///
+    fn configure_pin(&self, index: usize, enable: bool) {
+        let pin = &self.pins[index];
+        let gpio = Gpio::from(GPIO.get_task_id());
+
+        for &p in pin.gpio_pins {
+            let function = if enable { pin.function } else { AltFn::Alt0 };
+
+            gpio.iocon_configure(
+                p,
+                function,
+                Mode::NoPull,
+                Slew::Standard,
+                Invert::Disable,
+                Digimode::Digital,
+                Opendrain::Normal,
+            )
+            .unwrap();
+        }
+    }
+
+    fn nmuxes(&self) -> usize {
+        self.muxes.len()
+    }
+
+    fn mux(&self, index: usize) -> (Controller, PortIndex, Mux) {
+        let mux = &self.muxes[index];
+        (mux.controller, mux.port, mux.id)
+    }
+
+    fn configure_mux(
+        &self,
+        index: usize,
+        controller: usize,
+    ) -> Result<(), ResponseCode> {
+        let (mux, bus) = self.lookup_mux(index, controller);
+
+        mux.driver.configure(mux, &bus)
+    }
+
+    fn enable_segment(
+        &self,
+        index: usize,
+        controller: usize,
+        segment: Segment,
+    ) -> Result<(), ResponseCode> {
+        let (mux, bus) = self.lookup_mux(index, controller);
+
+        mux.driver.enable_segment(mux, &bus, segment)
+    }
+
+    fn reset_mux(
+        &self,
+        index: usize,
+        controller: usize,
+    ) -> Result<(), ResponseCode> {
+        let (mux, bus) = self.lookup_mux(index, controller);
+
+        mux.driver.reset(mux, &bus)
+    }
+}
+
+#[export_name = "main"]
+fn main() -> ! {
+    let controllers = i2c_config::controllers();
+    let pins = i2c_config::pins();
+    let muxes = i2c_config::muxes();
+
+    // Turn the actual peripheral on so that we can interact with it.
+    turn_on_flexcomm(&controllers);
+
+    let hardware = Hardware {
+        controllers: &controllers,
+        pins: &pins,
+        muxes: &muxes,
+    };
+
+    // Configure our pins, controllers and muxes, and field messages.
+    let mut server = Server::new(hardware, i2c_config::device_configs());
+    server.run();
+}
+
+fn turn_on_flexcomm(controllers: &[I2cController]) {
+    let syscon = Syscon::from(SYSCON.get_task_id());
+
+    // Our pins are configured via IOCON, which must itself be on.
+    syscon.enable_clock(Peripheral::Iocon);
+    syscon.leave_reset(Peripheral::Iocon);
+
+    for controller in controllers {
+        controller.enable(&syscon);
+    }
+}
@@ -0,0 +1,393 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! A driver for the LPC55 I2C interface
+//!
+//! Each I2C controller is a FLEXCOMM operating in I2C mode.  As with the
+//! STM32H7 driver, a controller can have its pins on any number of ports
+//! (with one active at a time), and each port can have any number of muxes.
+//!
+//! Unlike the STM32H7 driver, this driver polls the controller rather than
+//! waiting for its interrupt.  So that a hung bus doesn't hang the server,
+//! we enable the controller's timeout:  should the bus stall for longer than
+//! [`TIMEOUT`], the transaction fails with `BusLocked` -- and the
+//! controller should be reset.
+
+#![no_std]
+
+use drv_i2c_api::ResponseCode;
+use drv_i2c_mux::I2cKonamiCode;
+use drv_lpc55_gpio_api as gpio_api;
+use drv_lpc55_syscon_api as syscon_api;
+use lpc55_pac as device;
+use ringbuf::*;
+
+pub use drv_i2c_api::ReadLength;
+
+pub type RegisterBlock = device::i2c0::RegisterBlock;
+pub type FlexcommRegisterBlock = device::flexcomm0::RegisterBlock;
+
+///
+/// Our timeout, in units of 16 I2C function clocks.  With our 12 MHz main
+/// clock divided by 10, this is 25 ms (the SMBus minimum timeout).
+///
+pub const TIMEOUT: u32 = 1875;
+
+pub struct I2cPin {
+    pub controller: drv_i2c_api::Controller,
+    pub port: drv_i2c_api::PortIndex,
+    pub gpio_pins: &'static [gpio_api::Pin],
+    pub function: gpio_api::AltFn,
+}
+
+pub struct I2cController<'a> {
+    pub controller: drv_i2c_api::Controller,
+    pub peripheral: syscon_api::Peripheral,
+    pub flexcomm: &'a FlexcommRegisterBlock,
+    pub registers: &'a RegisterBlock,
+}
+
+pub type I2cMux<'a> = drv_i2c_mux::I2cMux<'a, I2cPin>;
+
+///
+/// The controller upstream of a mux, along with the [`gpio_api::Gpio`] task
+/// that owns the mux's enable and reset pins, for use by a mux driver.
+///
+pub struct I2cMuxBus<'a> {
+    pub controller: &'a I2cController<'a>,
+    pub gpio: gpio_api::Gpio,
+}
+
+impl drv_i2c_mux::I2cMuxHardware<I2cPin> for I2cMuxBus<'_> {
+    fn write_read(
+        &self,
+        addr: u8,
+        wlen: usize,
+        getbyte: &dyn Fn(usize) -> Option<u8>,
+        rlen: ReadLength,
+        putbyte: &mut dyn FnMut(usize, u8) -> Option<()>,
+    ) -> Result<(), ResponseCode> {
+        self.controller
+            .write_read(addr, wlen, getbyte, rlen, putbyte)
+    }
+
+    ///
+    /// The controller can't perform a zero-byte read (which is why build/i2c
+    /// won't configure a MAX7358 on an LPC55).
+    ///
+    fn send_konami_code(
+        &self,
+        _addr: u8,
+        _ops: &[I2cKonamiCode],
+    ) -> Result<(), ResponseCode> {
+        Err(ResponseCode::BadArg)
+    }
+
+    fn configure_output(&self, pin: &I2cPin) {
+        for &p in pin.gpio_pins {
+            // Set the pin high _before_ switching to output to avoid
+            // glitching.
+            self.gpio.set_val(p, gpio_api::Value::One).unwrap();
+
+            self.gpio
+                .iocon_configure(
+                    p,
+                    pin.function,
+                    gpio_api::Mode::NoPull,
+                    gpio_api::Slew::Standard,
+                    gpio_api::Invert::Disable,
+                    gpio_api::Digimode::Digital,
+                    gpio_api::Opendrain::Normal,
+                )
+                .unwrap();
+
+            self.gpio.set_dir(p, gpio_api::Direction::Output).unwrap();
+        }
+    }
+
+    fn set_output(&self, pin: &I2cPin, high: bool) {
+        for &p in pin.gpio_pins {
+            let value = if high {
+                gpio_api::Value::One
+            } else {
+                gpio_api::Value::Zero
+            };
+
+            self.gpio.set_val(p, value).unwrap();
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Trace {
+    Timeout(u32),
+    ArbitrationLost(u32),
+    StartStopError(u32),
+    Unexpected(u32),
+    Reset,
+    None,
+}
+
+ringbuf!(Trace, 16, Trace::None);
+
+impl<'a> I2cController<'a> {
+    pub fn enable(&self, syscon: &syscon_api::Syscon) {
+        syscon.enable_clock(self.peripheral);
+        syscon.leave_reset(self.peripheral);
+    }
+
+    pub fn configure(&self) {
+        let i2c = self.registers;
+
+        // Set I2C mode
+        self.flexcomm.pselid.write(|w| w.persel().i2c());
+
+        //
+        // Our main clock is 12 MHz; we divide it by 10 for an I2C function
+        // clock of 1.2 MHz.  SCL is then high for 6 function clocks and low
+        // for 6 function clocks, for a 100 kHz SCL.
+        //
+        i2c.clkdiv.modify(|_, w| unsafe { w.divval().bits(0x9) });
+        i2c.msttime
+            .modify(|_, w| w.mstsclhigh().bits(0x4).mstscllow().bits(0x4));
+
+        // Set our timeout (with the minimum of 15 in its low bits)...
+        i2c.timeout
+            .write(|w| unsafe { w.bits((TIMEOUT << 4) | 0xf) });
+
+        // ...and enable it along with the controller itself.
+        i2c.cfg
+            .modify(|_, w| w.msten().enabled().timeouten().enabled());
+    }
+
+    ///
+    /// Resets the controller.  The I2C block has no reset of its own, so we
+    /// reset the entire FLEXCOMM (and therefore must configure it again).
+    ///
+    pub fn reset(&self, syscon: &syscon_api::Syscon) {
+        ringbuf_entry!(Trace::Reset);
+
+        syscon.enter_reset(self.peripheral);
+        syscon.leave_reset(self.peripheral);
+
+        self.configure();
+    }
+
+    ///
+    /// Waits for the controller to be ready for software, failing if the
+    /// bus has timed out or if we've lost arbitration.
+    ///
+    fn wait(&self) -> Result<(), ResponseCode> {
+        let i2c = self.registers;
+
+        loop {
+            let stat = i2c.stat.read();
+
+            if stat.eventtimeout().bit_is_set()
+                || stat.scltimeout().bit_is_set()
+            {
+                ringbuf_entry!(Trace::Timeout(stat.bits()));
+                i2c.stat.write(|w| {
+                    w.eventtimeout().set_bit().scltimeout().set_bit()
+                });
+                return Err(ResponseCode::BusLocked);
+            }
+
+            if stat.mstarbloss().bit_is_set() {
+                ringbuf_entry!(Trace::ArbitrationLost(stat.bits()));
+                i2c.stat.write(|w| w.mstarbloss().set_bit());
+                return Err(ResponseCode::BusReset);
+            }
+
+            if stat.mstststperr().bit_is_set() {
+                ringbuf_entry!(Trace::StartStopError(stat.bits()));
+                i2c.stat.write(|w| w.mstststperr().set_bit());
+                return Err(ResponseCode::BusReset);
+            }
+
+            if !stat.mstpending().is_in_progress() {
+                return Ok(());
+            }
+        }
+    }
+
+    ///
+    /// Sends a START (or a repeated START) with the specified address byte,
+    /// returning once the device has acknowledged it.
+    ///
+    fn start(&self, byte: u8) -> Result<(), ResponseCode> {
+        let i2c = self.registers;
+
+        i2c.mstdat.modify(|_, w| unsafe { w.data().bits(byte) });
+        i2c.mstctl.write(|w| w.mststart().start());
+
+        self.wait()?;
+
+        let stat = i2c.stat.read();
+        let state = stat.mststate();
+
+        if state.is_nack_address() {
+            Err(ResponseCode::NoDevice)
+        } else if (byte & 1 == 0 && state.is_transmit_ready())
+            || (byte & 1 == 1 && state.is_receive_ready())
+        {
+            Ok(())
+        } else {
+            ringbuf_entry!(Trace::Unexpected(stat.bits()));
+            Err(ResponseCode::ControllerLocked)
+        }
+    }
+
+    fn stop(&self) -> Result<(), ResponseCode> {
+        let i2c = self.registers;
+
+        i2c.mstctl.write(|w| w.mststop().stop());
+
+        self.wait()?;
+
+        if !i2c.stat.read().mststate().is_idle() {
+            return Err(ResponseCode::ControllerLocked);
+        }
+
+        Ok(())
+    }
+
+    /// Perform a write to and then a read from the specified device.  Either
+    /// the write length or the read length can be zero, but one of these must
+    /// be non-zero.  Additionally, both lengths must be less than 256 bytes.
+    /// Note that the controller reads a byte as soon as a device acknowledges
+    /// its address, so a read is always at least one byte.
+    pub fn write_read(
+        &self,
+        addr: u8,
+        wlen: usize,
+        getbyte: impl Fn(usize) -> Option<u8>,
+        rlen: ReadLength,
+        putbyte: impl FnMut(usize, u8) -> Option<()>,
+    ) -> Result<(), ResponseCode> {
+        // Assert our preconditions as described above
+        assert!(wlen > 0 || rlen != ReadLength::Fixed(0));
+        assert!(wlen <= 255);
+
+        if let ReadLength::Fixed(rlen) = rlen {
+            assert!(rlen <= 255);
+        }
+
+        let result = self.transfer(addr, wlen, getbyte, rlen, putbyte);
+
+        match result {
+            //
+            // If the bus has locked or we have lost arbitration, there is
+            // no STOP to be sent; the controller must be reset.
+            //
+            Err(ResponseCode::BusLocked) | Err(ResponseCode::BusReset) => {
+                result
+            }
+
+            //
+            // Otherwise, whether we succeeded or not (e.g., the device NACK'd
+            // us), we send a STOP to release the bus.
+            //
+            _ => result.and(self.stop()),
+        }
+    }
+
+    fn transfer(
+        &self,
+        addr: u8,
+        wlen: usize,
+        getbyte: impl Fn(usize) -> Option<u8>,
+        mut rlen: ReadLength,
+        mut putbyte: impl FnMut(usize, u8) -> Option<()>,
+    ) -> Result<(), ResponseCode> {
+        let i2c = self.registers;
+
+        if wlen > 0 {
+            self.start(addr << 1)?;
+
+            for pos in 0..wlen {
+                let byte = getbyte(pos).ok_or(ResponseCode::BadArg)?;
+
+                i2c.mstdat.modify(|_, w| unsafe { w.data().bits(byte) });
+                i2c.mstctl.write(|w| w.mstcontinue().continue_());
+
+                self.wait()?;
+
+                let stat = i2c.stat.read();
+
+                if stat.mststate().is_nack_data() {
+                    return Err(ResponseCode::NoRegister);
+                }
+
+                if !stat.mststate().is_transmit_ready() {
+                    ringbuf_entry!(Trace::Unexpected(stat.bits()));
+                    return Err(ResponseCode::ControllerLocked);
+                }
+            }
+        }
+
+        if rlen == ReadLength::Fixed(0) {
+            return Ok(());
+        }
+
+        //
+        // If we have both a write and a read, we deliberately do not send a
+        // STOP between them to force the RESTART (many devices do not permit
+        // a STOP between a register address write and a subsequent read).
+        // Once the device acknowledges its address, the controller receives
+        // our first byte.
+        //
+        self.start((addr << 1) | 1)?;
+
+        let mut pos = 0;
+        let mut first = true;
+
+        loop {
+            if let ReadLength::Fixed(rlen) = rlen {
+                if pos >= rlen {
+                    break;
+                }
+            }
+
+            if !first {
+                i2c.mstctl.write(|w| w.mstcontinue().continue_());
+
+                self.wait()?;
+
+                let stat = i2c.stat.read();
+
+                if !stat.mststate().is_receive_ready() {
+                    ringbuf_entry!(Trace::Unexpected(stat.bits()));
+                    return Err(ResponseCode::BadResponse);
+                }
+            }
+
+            first = false;
+
+            // Read it!
+            let byte = i2c.mstdat.read().data().bits();
+
+            match rlen {
+                ReadLength::Variable => {
+                    rlen = ReadLength::Fixed(byte.into());
+                }
+
+                ReadLength::VariablePec => {
+                    putbyte(pos, byte).ok_or(ResponseCode::BadArg)?;
+                    pos += 1;
+
+                    // Our data are followed by a PEC byte.
+                    rlen = ReadLength::Fixed(pos + byte as usize + 1);
+                }
+
+                ReadLength::Fixed(_) => {
+                    putbyte(pos, byte).ok_or(ResponseCode::BadArg)?;
+                    pos += 1;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -2,7 +2,7 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-fn main() {
+fn main() -> Result<(), Box<dyn std::error::Error>> {
     build_util::expose_target_board();
 
     let disposition = build_i2c::Disposition::Initiator;
@@ -11,4 +11,6 @@ fn main() {
         println!("code generation failed: {}", e);
         std::process::exit(1);
     }
+
+    Ok(())
 }
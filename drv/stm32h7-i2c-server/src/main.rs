@@ -3,146 +3,160 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 //! A driver for the STM32H7 I2C interface
+//!
+//! This server implements the `I2c` interface defined in `idl/i2c.idol` by
+//! running the controller-independent server in `drv-i2c-server-core` on
+//! top of the STM32H7 controllers, pins and muxes specified by the
+//! application configuration (`config.i2c`).
 
 #![no_std]
 #![no_main]
 
 use drv_i2c_api::*;
+use drv_i2c_server_core::{I2cHardware, Server};
 use drv_stm32h7_i2c::*;
 use drv_stm32xx_sys_api::{OutputType, Pull, Speed, Sys};
 
-use fixedmap::*;
-use ringbuf::*;
 use userlib::*;
 
 task_slot!(SYS, sys);
 
-fn lookup_controller<'a>(
-    controllers: &'a [I2cController],
-    controller: Controller,
-) -> Result<&'a I2cController<'a>, ResponseCode> {
-    for i in 0..controllers.len() {
-        if controllers[i].controller == controller {
-            return Ok(&controllers[i]);
-        }
-    }
-
-    Err(ResponseCode::BadController)
-}
+include!(concat!(env!("OUT_DIR"), "/i2c_config.rs"));
 
 ///
-/// Validates a port for the specified controller.
+/// Our controllers, pins and muxes.
 ///
-fn validate_port<'a>(
+struct Hardware<'a> {
+    controllers: &'a [I2cController<'a>],
     pins: &'a [I2cPin],
-    controller: Controller,
-    port: PortIndex,
-) -> Result<(), ResponseCode> {
-    pins.iter()
-        .find(|pin| pin.controller == controller && pin.port == port)
-        .ok_or(ResponseCode::BadPort)?;
-
-    Ok(())
+    muxes: &'a [I2cMux<'a>],
+    ctrl: &'a I2cControl,
 }
 
-fn find_mux(
-    controller: &I2cController,
-    port: PortIndex,
-    muxes: &[I2cMux],
-    mux: Option<(Mux, Segment)>,
-    mut func: impl FnMut(&I2cMux, Mux, Segment) -> Result<(), ResponseCode>,
-) -> Result<(), ResponseCode> {
-    match mux {
-        Some((id, segment)) => {
-            for mux in muxes {
-                if mux.controller != controller.controller {
-                    continue;
-                }
-
-                if mux.port != port || mux.id != id {
-                    continue;
-                }
-
-                return func(mux, id, segment);
-            }
-
-            Err(ResponseCode::MuxNotFound)
-        }
-        None => Ok(()),
+impl<'a> Hardware<'a> {
+    ///
+    /// Returns a mux, along with the bus upstream of it (on the specified
+    /// controller) for its driver.
+    ///
+    fn lookup_mux(
+        &self,
+        index: usize,
+        controller: usize,
+    ) -> (&'a I2cMux<'a>, I2cMuxBus<'a>) {
+        let bus = I2cMuxBus {
+            controller: &self.controllers[controller],
+            sys: Sys::from(SYS.get_task_id()),
+            ctrl: self.ctrl,
+        };
+
+        (&self.muxes[index], bus)
     }
 }
 
-fn configure_mux(
-    map: &mut MuxMap,
-    controller: &I2cController,
-    port: PortIndex,
-    mux: Option<(Mux, Segment)>,
-    muxes: &[I2cMux],
-    ctrl: &I2cControl,
-) -> Result<(), ResponseCode> {
-    find_mux(controller, port, muxes, mux, |mux, id, segment| {
-        // Determine if the current segment matches our specified segment...
-        if let Some(current) = map.get(id) {
-            if current == segment {
-                return Ok(());
-            }
-
-            // Beyond this point, we want any failure to set our new
-            // segment to leave our segment unset rather than having
-            // it point to the old segment.
-            map.remove(id);
+impl I2cHardware for Hardware<'_> {
+    fn ncontrollers(&self) -> usize {
+        self.controllers.len()
+    }
+
+    fn controller(&self, index: usize) -> Controller {
+        self.controllers[index].controller
+    }
+
+    fn configure_controllers(&self) {
+        for controller in self.controllers {
+            controller.configure();
+            sys_irq_control(controller.notification, true);
         }
+    }
 
-        // If we're here, our mux is valid, but the current segment is
-        // not the specfied segment; we will now call upon our
-        // driver to enable this segment.
-        mux.driver.enable_segment(mux, controller, segment, ctrl)?;
-        map.insert(id, segment);
+    fn write_read(
+        &self,
+        controller: usize,
+        addr: u8,
+        wlen: usize,
+        getbyte: impl Fn(usize) -> Option<u8>,
+        rlen: ReadLength,
+        putbyte: impl FnMut(usize, u8) -> Option<()>,
+    ) -> Result<(), ResponseCode> {
+        self.controllers[controller]
+            .write_read(addr, wlen, getbyte, rlen, putbyte, self.ctrl)
+    }
 
-        Ok(())
-    })
-}
+    fn reset(&self, controller: usize) {
+        self.controllers[controller].reset();
+    }
+
+    fn npins(&self) -> usize {
+        self.pins.len()
+    }
+
+    fn pin(&self, index: usize) -> (Controller, PortIndex) {
+        let pin = &self.pins[index];
+        (pin.controller, pin.port)
+    }
 
-ringbuf!(Option<ResponseCode>, 16, None);
-
-fn reset_if_needed(
-    code: ResponseCode,
-    controller: &I2cController,
-    port: PortIndex,
-    muxes: &[I2cMux],
-    mux: Option<(Mux, Segment)>,
-) {
-    ringbuf_entry!(Some(code));
-
-    match code {
-        ResponseCode::BusLocked
-        | ResponseCode::BusLockedMux
-        | ResponseCode::BusReset
-        | ResponseCode::BusResetMux
-        | ResponseCode::ControllerLocked => {}
-        _ => {
-            return;
+    fn configure_pin(&self, index: usize, enable: bool) {
+        let pin = &self.pins[index];
+        let sys = Sys::from(SYS.get_task_id());
+
+        if enable {
+            sys.gpio_configure_alternate(
+                pin.gpio_pins,
+                OutputType::OpenDrain,
+                Speed::High,
+                Pull::None,
+                pin.function,
+            )
+            .unwrap();
+        } else {
+            //
+            // We de-configure a port by setting its pins to `Mode::input`,
+            // which will assure that we don't leave SCL and SDA pulled high.
+            //
+            sys.gpio_configure_input(pin.gpio_pins, Pull::None).unwrap();
         }
     }
 
-    let sys = SYS.get_task_id();
-    let sys = Sys::from(sys);
+    fn nmuxes(&self) -> usize {
+        self.muxes.len()
+    }
 
-    // First, bounce our I2C controller
-    controller.reset();
+    fn mux(&self, index: usize) -> (Controller, PortIndex, Mux) {
+        let mux = &self.muxes[index];
+        (mux.controller, mux.port, mux.id)
+    }
 
-    // And now reset the mux, eating any errors.
-    let _ = find_mux(controller, port, muxes, mux, |mux, _, _| {
-        ringbuf_entry!(None);
-        mux.driver.reset(&mux, &sys)?;
-        Ok(())
-    });
-}
+    fn configure_mux(
+        &self,
+        index: usize,
+        controller: usize,
+    ) -> Result<(), ResponseCode> {
+        let (mux, bus) = self.lookup_mux(index, controller);
 
-type PortMap = FixedMap<Controller, PortIndex, 8>;
-type MuxMap = FixedMap<Mux, Segment, 4>;
+        mux.driver.configure(mux, &bus)
+    }
 
-include!(concat!(env!("OUT_DIR"), "/i2c_config.rs"));
+    fn enable_segment(
+        &self,
+        index: usize,
+        controller: usize,
+        segment: Segment,
+    ) -> Result<(), ResponseCode> {
+        let (mux, bus) = self.lookup_mux(index, controller);
+
+        mux.driver.enable_segment(mux, &bus, segment)
+    }
+
+    fn reset_mux(
+        &self,
+        index: usize,
+        controller: usize,
+    ) -> Result<(), ResponseCode> {
+        let (mux, bus) = self.lookup_mux(index, controller);
+
+        mux.driver.reset(mux, &bus)
+    }
+}
 
 #[export_name = "main"]
 fn main() -> ! {
@@ -150,17 +164,8 @@ fn main() -> ! {
     let pins = i2c_config::pins();
     let muxes = i2c_config::muxes();
 
-    // This is our actual mutable state
-    let mut portmap = PortMap::new();
-    let mut muxmap = MuxMap::new();
-
     // Turn the actual peripheral on so that we can interact with it.
     turn_on_i2c(&controllers);
-    configure_pins(&controllers, &pins, &mut portmap);
-    configure_controllers(&controllers);
-
-    // Field messages.
-    let mut buffer = [0; 4];
 
     let ctrl = I2cControl {
         enable: |notification| {
@@ -171,98 +176,16 @@ fn main() -> ! {
         },
     };
 
-    configure_muxes(&muxes, &controllers, &pins, &mut portmap, &ctrl);
-
-    loop {
-        hl::recv_without_notification(&mut buffer, |op, msg| match op {
-            Op::WriteRead | Op::WriteReadBlock => {
-                let (payload, caller) = msg
-                    .fixed_with_leases::<[u8; 4], usize>(2)
-                    .ok_or(ResponseCode::BadArg)?;
-
-                let (addr, controller, port, mux) =
-                    Marshal::unmarshal(payload)?;
-
-                if let Some(_) = ReservedAddress::from_u8(addr) {
-                    return Err(ResponseCode::ReservedAddress);
-                }
-
-                let controller = lookup_controller(&controllers, controller)?;
-                validate_port(&pins, controller.controller, port)?;
-
-                configure_port(&mut portmap, controller, port, &pins);
-
-                match configure_mux(
-                    &mut muxmap,
-                    controller,
-                    port,
-                    mux,
-                    &muxes,
-                    &ctrl,
-                ) {
-                    Ok(_) => {}
-                    Err(code) => {
-                        reset_if_needed(code, controller, port, &muxes, mux);
-                        return Err(code);
-                    }
-                }
-
-                let wbuf = caller.borrow(0);
-                let winfo = wbuf.info().ok_or(ResponseCode::BadArg)?;
-
-                if !winfo.attributes.contains(LeaseAttributes::READ) {
-                    return Err(ResponseCode::BadArg);
-                }
-
-                let rbuf = caller.borrow(1);
-                let rinfo = rbuf.info().ok_or(ResponseCode::BadArg)?;
-
-                if winfo.len == 0 && rinfo.len == 0 {
-                    // We must have either a write OR a read -- while perhaps
-                    // valid to support both being zero as a way of testing an
-                    // address for a NACK, it's not a mode that we (currently)
-                    // support.
-                    return Err(ResponseCode::BadArg);
-                }
-
-                if winfo.len > 255 || rinfo.len > 255 {
-                    // For now, we don't support writing or reading more than
-                    // 255 bytes.
-                    return Err(ResponseCode::BadArg);
-                }
-
-                let mut nread = 0;
-
-                match controller.write_read(
-                    addr,
-                    winfo.len,
-                    |pos| wbuf.read_at(pos),
-                    if op == Op::WriteRead {
-                        ReadLength::Fixed(rinfo.len)
-                    } else {
-                        ReadLength::Variable
-                    },
-                    |pos, byte| {
-                        if pos + 1 > nread {
-                            nread = pos + 1;
-                        }
-
-                        rbuf.write_at(pos, byte)
-                    },
-                    &ctrl,
-                ) {
-                    Err(code) => {
-                        reset_if_needed(code, controller, port, &muxes, mux);
-                        Err(code)
-                    }
-                    Ok(_) => {
-                        caller.reply(nread);
-                        Ok(())
-                    }
-                }
-            }
-        });
-    }
+    let hardware = Hardware {
+        controllers: &controllers,
+        pins: &pins,
+        muxes: &muxes,
+        ctrl: &ctrl,
+    };
+
+    // Configure our pins, controllers and muxes, and field messages.
+    let mut server = Server::new(hardware, i2c_config::device_configs());
+    server.run();
 }
 
 fn turn_on_i2c(controllers: &[I2cController]) {
@@ -272,121 +195,3 @@ fn turn_on_i2c(controllers: &[I2cController]) {
         controller.enable(&sys);
     }
 }
-
-fn configure_controllers(controllers: &[I2cController]) {
-    for controller in controllers {
-        controller.configure();
-        sys_irq_control(controller.notification, true);
-    }
-}
-
-fn configure_port(
-    map: &mut PortMap,
-    controller: &I2cController,
-    port: PortIndex,
-    pins: &[I2cPin],
-) {
-    let current = map.get(controller.controller).unwrap();
-
-    if current == port {
-        return;
-    }
-
-    let sys = SYS.get_task_id();
-    let sys = Sys::from(sys);
-
-    //
-    // We will now iterate over all pins, de-configuring any that match our
-    // old port, and configuring any that match our new port.
-    //
-    for pin in pins
-        .iter()
-        .filter(|p| p.controller == controller.controller)
-    {
-        if pin.port == current {
-            //
-            // We de-configure our current port by setting the pins to
-            // `Mode::input`, which will assure that we don't leave SCL and
-            // SDA pulled high.
-            //
-            sys.gpio_configure_input(pin.gpio_pins, Pull::None).unwrap();
-        } else if pin.port == port {
-            // Configure our new port!
-            sys.gpio_configure_alternate(
-                pin.gpio_pins,
-                OutputType::OpenDrain,
-                Speed::High,
-                Pull::None,
-                pin.function,
-            )
-            .unwrap();
-        }
-    }
-
-    map.insert(controller.controller, port);
-}
-
-fn configure_pins(
-    controllers: &[I2cController],
-    pins: &[I2cPin],
-    map: &mut PortMap,
-) {
-    let sys = SYS.get_task_id();
-    let sys = Sys::from(sys);
-
-    for pin in pins {
-        let controller =
-            lookup_controller(controllers, pin.controller).ok().unwrap();
-
-        match map.get(controller.controller) {
-            Some(port) if port != pin.port => {
-                //
-                // If we have already enabled this controller with a different
-                // port, we don't want to enable this pin.
-                //
-                continue;
-            }
-            _ => {}
-        }
-
-        sys.gpio_configure_alternate(
-            pin.gpio_pins,
-            OutputType::OpenDrain,
-            Speed::High,
-            Pull::None,
-            pin.function,
-        )
-        .unwrap();
-
-        map.insert(controller.controller, pin.port);
-    }
-}
-
-fn configure_muxes(
-    muxes: &[I2cMux],
-    controllers: &[I2cController],
-    pins: &[I2cPin],
-    map: &mut PortMap,
-    ctrl: &I2cControl,
-) {
-    let sys = SYS.get_task_id();
-    let sys = Sys::from(sys);
-
-    for mux in muxes {
-        let controller =
-            lookup_controller(controllers, mux.controller).unwrap();
-        configure_port(map, controller, mux.port, pins);
-
-        loop {
-            match mux.driver.configure(&mux, controller, &sys, ctrl) {
-                Ok(_) => {
-                    break;
-                }
-                Err(code) => {
-                    ringbuf_entry!(Some(code));
-                    reset_if_needed(code, controller, mux.port, muxes, None);
-                }
-            }
-        }
-    }
-}
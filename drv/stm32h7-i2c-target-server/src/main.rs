@@ -0,0 +1,201 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! A server for an STM32H7 I2C controller operating as a target
+//!
+//! This server emulates the devices specified (via `emulate`) for the
+//! controller that the application configuration marks as a target, allowing
+//! the SP to present register-mapped devices to an initiator (e.g., a
+//! management interface to the host over SMBus).  Exactly one controller may
+//! emulate devices; a target controller that doesn't emulate devices is left
+//! to other tasks (e.g., the SPD proxy).  Other tasks update the
+//! contents of those devices via the `I2cTarget` interface defined in
+//! `idl/i2c-target.idol`.
+//!
+//! Messages are handled while we are waiting for activity on the bus.  As
+//! the controller stretches the clock until we have dealt with each byte, a
+//! message that arrives in the midst of a transaction delays the initiator
+//! (but does not otherwise disrupt it).
+
+#![no_std]
+#![no_main]
+
+use core::cell::RefCell;
+use drv_i2c_target_api::TargetError;
+use drv_stm32h7_i2c::*;
+use drv_stm32xx_sys_api::*;
+use i2c_config::targets::{MEMORY_SIZE, NUM_DEVICES, NUM_REGISTERS};
+use idol_runtime::{Leased, NotificationHandler, RequestError, R, W};
+use userlib::*;
+
+task_slot!(SYS, sys);
+
+include!(concat!(env!("OUT_DIR"), "/i2c_config.rs"));
+
+type Emulator = i2c_emulator::Emulator<NUM_DEVICES, NUM_REGISTERS, MEMORY_SIZE>;
+
+fn target_error(err: i2c_emulator::Error) -> TargetError {
+    match err {
+        i2c_emulator::Error::BadAddress => TargetError::BadAddress,
+        i2c_emulator::Error::BadDevice => TargetError::BadDevice,
+        i2c_emulator::Error::BadRegister => TargetError::BadRegister,
+        i2c_emulator::Error::BadLength => TargetError::BadLength,
+    }
+}
+
+fn configure_pins(pins: &[I2cPin]) {
+    let sys = SYS.get_task_id();
+    let sys = Sys::from(sys);
+
+    for pin in pins {
+        sys.gpio_configure_alternate(
+            pin.gpio_pins,
+            OutputType::OpenDrain,
+            Speed::High,
+            Pull::None,
+            pin.function,
+        )
+        .unwrap();
+    }
+}
+
+struct ServerImpl<'a> {
+    emulator: &'a RefCell<Emulator>,
+    notification: u32,
+    interrupted: bool,
+}
+
+impl idl::InOrderI2cTargetImpl for ServerImpl<'_> {
+    fn write_memory(
+        &mut self,
+        _: &RecvMessage,
+        address: u8,
+        offset: usize,
+        data: Leased<R, [u8]>,
+    ) -> Result<(), RequestError<TargetError>> {
+        let mut emulator = self.emulator.borrow_mut();
+        let memory = emulator
+            .memory_mut(address, offset, data.len())
+            .map_err(target_error)?;
+
+        data.read_range(0..memory.len(), memory)
+            .map_err(|_| RequestError::went_away())
+    }
+
+    fn read_memory(
+        &mut self,
+        _: &RecvMessage,
+        address: u8,
+        offset: usize,
+        data: Leased<W, [u8]>,
+    ) -> Result<usize, RequestError<TargetError>> {
+        let emulator = self.emulator.borrow();
+        let memory = emulator.memory(address, offset).map_err(target_error)?;
+        let len = core::cmp::min(memory.len(), data.len());
+
+        data.write_range(0..len, &memory[..len])
+            .map_err(|_| RequestError::went_away())?;
+
+        Ok(len)
+    }
+
+    fn write_register(
+        &mut self,
+        _: &RecvMessage,
+        address: u8,
+        code: u8,
+        data: Leased<R, [u8]>,
+    ) -> Result<(), RequestError<TargetError>> {
+        let mut emulator = self.emulator.borrow_mut();
+        let register = emulator
+            .register_mut(address, code, data.len())
+            .map_err(target_error)?;
+
+        data.read_range(0..register.len(), register)
+            .map_err(|_| RequestError::went_away())
+    }
+
+    fn read_register(
+        &mut self,
+        _: &RecvMessage,
+        address: u8,
+        code: u8,
+        data: Leased<W, [u8]>,
+    ) -> Result<usize, RequestError<TargetError>> {
+        let emulator = self.emulator.borrow();
+        let register =
+            emulator.register(address, code).map_err(target_error)?;
+
+        if register.len() > data.len() {
+            return Err(TargetError::BadLength.into());
+        }
+
+        data.write_range(0..register.len(), register)
+            .map_err(|_| RequestError::went_away())?;
+
+        Ok(register.len())
+    }
+}
+
+impl NotificationHandler for ServerImpl<'_> {
+    fn current_notification_mask(&self) -> u32 {
+        self.notification
+    }
+
+    fn handle_notification(&mut self, _bits: u32) {
+        self.interrupted = true;
+    }
+}
+
+#[export_name = "main"]
+fn main() -> ! {
+    // build/i2c guarantees that there is exactly one controller emulating
+    // devices
+    let [controller] = i2c_config::controllers();
+    let pins = i2c_config::pins();
+
+    // Enable the controller
+    let sys = Sys::from(SYS.get_task_id());
+
+    controller.enable(&sys);
+
+    // Configure our pins
+    configure_pins(&pins);
+
+    let emulator = RefCell::new(Emulator::new(
+        i2c_config::targets::devices(),
+        i2c_config::targets::registers(),
+    ));
+
+    let mut server = ServerImpl {
+        emulator: &emulator,
+        notification: controller.notification,
+        interrupted: false,
+    };
+
+    let mut buffer = [0; idl::INCOMING_SIZE];
+
+    controller.operate_as_target_with(
+        |notification| {
+            sys_irq_control(notification, true);
+        },
+        |_| {
+            // Field messages until our interrupt fires.
+            server.interrupted = false;
+
+            while !server.interrupted {
+                idol_runtime::dispatch_n(&mut buffer, &mut server);
+            }
+        },
+        |addr| emulator.borrow_mut().initiate(addr),
+        |addr, byte| emulator.borrow_mut().rx(addr, byte),
+        |addr| emulator.borrow_mut().tx(addr),
+    )
+}
+
+mod idl {
+    use super::TargetError;
+
+    include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
+}
@@ -21,9 +21,8 @@ pub type RegisterBlock = device::i2c3::RegisterBlock;
 #[cfg(any(feature = "h743", feature = "h753"))]
 pub type RegisterBlock = device::i2c1::RegisterBlock;
 
-pub mod ltc4306;
-pub mod max7358;
-pub mod pca9548;
+pub use drv_i2c_api::ReadLength;
+pub use drv_i2c_mux::I2cKonamiCode;
 
 use ringbuf::*;
 use userlib::*;
@@ -48,68 +47,68 @@ pub struct I2cController<'a> {
 /// A structure that defines interrupt control flow functions that will be
 /// used to pass control flow into the kernel to either enable or wait for
 /// interrupts.  Note that this is deliberately a struct and not a trait,
-/// allowing the [`I2cMuxDriver`] trait to itself be a trait object.
+/// allowing an [`I2cMuxBus`] to be used as a trait object.
 ///
 pub struct I2cControl {
     pub enable: fn(u32),
     pub wfi: fn(u32),
 }
 
-#[derive(Copy, Clone, PartialEq)]
-pub enum I2cKonamiCode {
-    Read,
-    Write,
-}
+pub type I2cMux<'a> = drv_i2c_mux::I2cMux<'a, I2cPin>;
 
 ///
-/// A trait to express an I2C mux driver.
+/// The controller upstream of a mux, along with the [`sys_api::Sys`] task
+/// that owns the mux's enable and reset pins, for use by a mux driver.
 ///
-pub trait I2cMuxDriver {
-    /// Configure the mux, specifying the mux and controller, but also an
-    /// instance to a [`Gpio`] task.
-    fn configure(
-        &self,
-        mux: &I2cMux,
-        controller: &I2cController,
-        sys: &sys_api::Sys,
-        ctrl: &I2cControl,
-    ) -> Result<(), drv_i2c_api::ResponseCode>;
+pub struct I2cMuxBus<'a> {
+    pub controller: &'a I2cController<'a>,
+    pub sys: sys_api::Sys,
+    pub ctrl: &'a I2cControl,
+}
 
-    /// Reset the mux
-    fn reset(
+impl drv_i2c_mux::I2cMuxHardware<I2cPin> for I2cMuxBus<'_> {
+    fn write_read(
         &self,
-        mux: &I2cMux,
-        sys: &sys_api::Sys,
-    ) -> Result<(), drv_i2c_api::ResponseCode>;
+        addr: u8,
+        wlen: usize,
+        getbyte: &dyn Fn(usize) -> Option<u8>,
+        rlen: ReadLength,
+        putbyte: &mut dyn FnMut(usize, u8) -> Option<()>,
+    ) -> Result<(), drv_i2c_api::ResponseCode> {
+        self.controller
+            .write_read(addr, wlen, getbyte, rlen, putbyte, self.ctrl)
+    }
 
-    /// Enable the specified segment on the specified mux
-    fn enable_segment(
+    fn send_konami_code(
         &self,
-        mux: &I2cMux,
-        controller: &I2cController,
-        segment: drv_i2c_api::Segment,
-        ctrl: &I2cControl,
-    ) -> Result<(), drv_i2c_api::ResponseCode>;
-}
+        addr: u8,
+        ops: &[I2cKonamiCode],
+    ) -> Result<(), drv_i2c_api::ResponseCode> {
+        self.controller.send_konami_code(addr, ops, self.ctrl)
+    }
 
-pub struct I2cMux<'a> {
-    pub controller: drv_i2c_api::Controller,
-    pub port: drv_i2c_api::PortIndex,
-    pub id: drv_i2c_api::Mux,
-    pub driver: &'a dyn I2cMuxDriver,
-    pub enable: Option<I2cPin>,
-    pub address: u8,
-}
+    fn configure_output(&self, pin: &I2cPin) {
+        // Set the pins to high _before_ switching to output to avoid
+        // glitching.
+        self.sys.gpio_set(pin.gpio_pins).unwrap();
+        // Now, expose them as outputs.
+        self.sys
+            .gpio_configure_output(
+                pin.gpio_pins,
+                sys_api::OutputType::PushPull,
+                sys_api::Speed::High,
+                sys_api::Pull::None,
+            )
+            .unwrap();
+    }
 
-///
-/// An enum describing the amount to read
-///
-#[derive(Copy, Clone, Debug, PartialEq)]
-pub enum ReadLength {
-    /// Fixed length to read
-    Fixed(usize),
-    /// Read size is variable: first byte contains length
-    Variable,
+    fn set_output(&self, pin: &I2cPin, high: bool) {
+        if high {
+            self.sys.gpio_set(pin.gpio_pins).unwrap();
+        } else {
+            self.sys.gpio_reset(pin.gpio_pins).unwrap();
+        }
+    }
 }
 
 #[derive(Copy, Clone, PartialEq)]
@@ -141,58 +140,6 @@ enum Trace {
 
 ringbuf!(Trace, 48, Trace::None);
 
-impl<'a> I2cMux<'_> {
-    /// A convenience routine to translate an error induced by in-band
-    /// management into one that can be returned to a caller
-    fn error_code(
-        &self,
-        code: drv_i2c_api::ResponseCode,
-    ) -> drv_i2c_api::ResponseCode {
-        use drv_i2c_api::ResponseCode;
-
-        match code {
-            ResponseCode::NoDevice => ResponseCode::BadMuxAddress,
-            ResponseCode::NoRegister => ResponseCode::BadMuxRegister,
-            ResponseCode::BusLocked => ResponseCode::BusLockedMux,
-            ResponseCode::BusReset => ResponseCode::BusResetMux,
-            _ => code,
-        }
-    }
-
-    fn configure(
-        &self,
-        sys: &sys_api::Sys,
-    ) -> Result<(), drv_i2c_api::ResponseCode> {
-        if let Some(pin) = &self.enable {
-            // Set the pins to high _before_ switching to output to avoid
-            // glitching.
-            sys.gpio_set(pin.gpio_pins).unwrap();
-            // Now, expose them as outputs.
-            sys.gpio_configure_output(
-                pin.gpio_pins,
-                sys_api::OutputType::PushPull,
-                sys_api::Speed::High,
-                sys_api::Pull::None,
-            )
-            .unwrap();
-        }
-
-        Ok(())
-    }
-
-    fn reset(
-        &self,
-        sys: &sys_api::Sys,
-    ) -> Result<(), drv_i2c_api::ResponseCode> {
-        if let Some(pin) = &self.enable {
-            sys.gpio_reset(pin.gpio_pins).unwrap();
-            sys.gpio_set(pin.gpio_pins).unwrap();
-        }
-
-        Ok(())
-    }
-}
-
 impl<'a> I2cController<'a> {
     pub fn enable(&self, sys: &sys_api::Sys) {
         sys.enable_clock(self.peripheral);
@@ -586,6 +533,25 @@ impl<'a> I2cController<'a> {
                     continue;
                 }
 
+                if rlen == ReadLength::VariablePec {
+                    let nbytes = byte
+                        .checked_add(1)
+                        .ok_or(drv_i2c_api::ResponseCode::BadArg)?;
+
+                    #[rustfmt::skip]
+                    i2c.cr2.modify(|_, w| { w
+                        .nbytes().bits(nbytes)
+                        .reload().clear_bit()
+                    });
+
+                    putbyte(pos, byte)
+                        .ok_or(drv_i2c_api::ResponseCode::BadArg)?;
+                    pos += 1;
+
+                    rlen = ReadLength::Fixed(pos + nbytes as usize);
+                    continue;
+                }
+
                 putbyte(pos, byte).ok_or(drv_i2c_api::ResponseCode::BadArg)?;
                 pos += 1;
             }
@@ -743,6 +709,28 @@ impl<'a> I2cController<'a> {
     pub fn operate_as_target<'b>(
         &self,
         ctrl: &I2cControl,
+        initiate: impl FnMut(u8) -> bool,
+        rxbyte: impl FnMut(u8, u8),
+        txbyte: impl FnMut(u8) -> Option<u8>,
+    ) -> ! {
+        self.operate_as_target_with(
+            ctrl.enable,
+            ctrl.wfi,
+            initiate,
+            rxbyte,
+            txbyte,
+        )
+    }
+
+    ///
+    /// Like [`operate_as_target`], but with a closure to wait for our
+    /// interrupt, allowing a server to do other work (e.g., handle messages
+    /// from other tasks) while there is no activity on the bus.
+    ///
+    pub fn operate_as_target_with(
+        &self,
+        enable: fn(u32),
+        mut wfi: impl FnMut(u32),
         mut initiate: impl FnMut(u8) -> bool,
         mut rxbyte: impl FnMut(u8, u8),
         mut txbyte: impl FnMut(u8) -> Option<u8>,
@@ -752,7 +740,7 @@ impl<'a> I2cController<'a> {
         let i2c = self.registers;
         let notification = self.notification;
 
-        (ctrl.enable)(notification);
+        enable(notification);
 
         'addrloop: loop {
             let (is_write, addr) = loop {
@@ -770,8 +758,8 @@ impl<'a> I2cController<'a> {
                 }
 
                 ringbuf_entry!(Trace::WaitAddr);
-                (ctrl.wfi)(notification);
-                (ctrl.enable)(notification);
+                wfi(notification);
+                enable(notification);
             };
 
             // Flush our TXDR
@@ -840,8 +828,8 @@ impl<'a> I2cController<'a> {
                     }
 
                     ringbuf_entry!(Trace::WaitRx);
-                    (ctrl.wfi)(notification);
-                    (ctrl.enable)(notification);
+                    wfi(notification);
+                    enable(notification);
                 }
             }
 
@@ -901,8 +889,8 @@ impl<'a> I2cController<'a> {
                 }
 
                 ringbuf_entry!(Trace::WaitTx);
-                (ctrl.wfi)(notification);
-                (ctrl.enable)(notification);
+                wfi(notification);
+                enable(notification);
             }
         }
     }
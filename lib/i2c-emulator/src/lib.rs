@@ -0,0 +1,518 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Emulation of EEPROM-like and register-mapped I2C devices
+//!
+//! An EEPROM-like device behaves like a (small) I2C EEPROM:  the first byte
+//! written in a transaction sets the current offset, with subsequent bytes
+//! written at (and reads made from) the current offset, which increments
+//! (and wraps) with each byte.
+//!
+//! A register-mapped device behaves like an SMBus device:  the first byte
+//! written in a transaction is a command code, selecting a register that
+//! subsequent bytes are written to (or read from).  A block register is
+//! preceded by its byte count in both directions.
+//!
+//! The devices are specified by the application configuration (from which
+//! `build/i2c` generates their descriptions); the emulator itself knows
+//! nothing of controllers, and is driven by a target server that calls
+//! [`Emulator::initiate`], [`Emulator::rx`], and [`Emulator::tx`] as the
+//! initiator addresses it and transfers bytes.
+
+#![no_std]
+
+///
+/// A device emulated by a controller operating as a target, as specified by
+/// the application configuration.  An EEPROM-like device is a region of
+/// memory addressed by a one-byte offset; a register-mapped device has a set
+/// of SMBus commands (see [`I2cTargetRegister`]).
+///
+pub struct I2cTargetDevice {
+    pub address: u8,
+    pub kind: I2cTargetKind,
+}
+
+pub enum I2cTargetKind {
+    Eeprom { base: usize, size: usize },
+    Registers,
+}
+
+///
+/// A register of an emulated register-mapped device, occupying `size` bytes
+/// of target memory at `base`.  A `block` register is read (and written) as
+/// an SMBus block, and may contain fewer than `size` bytes.
+///
+pub struct I2cTargetRegister {
+    pub address: u8,
+    pub code: u8,
+    pub base: usize,
+    pub size: usize,
+    pub block: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Error {
+    /// No device is emulated at the specified address
+    BadAddress,
+    /// The emulated device is not of the kind that the operation requires
+    BadDevice,
+    /// The emulated device does not have the specified register
+    BadRegister,
+    /// The specified offset or length exceeds the memory or register
+    BadLength,
+}
+
+///
+/// An emulator for `D` devices with a total of `R` registers, backed by `M`
+/// bytes of memory.
+///
+pub struct Emulator<const D: usize, const R: usize, const M: usize> {
+    devices: [I2cTargetDevice; D],
+    registers: [I2cTargetRegister; R],
+    memory: [u8; M],
+
+    /// current length of each register (always its size if not a block)
+    lengths: [usize; R],
+
+    /// current offset of each EEPROM-like device
+    offsets: [usize; D],
+
+    /// device being addressed, if any
+    device: Option<usize>,
+
+    /// register selected by the last command code, if any
+    register: Option<usize>,
+
+    /// true if the next byte written is an offset or a command code
+    first: bool,
+
+    /// position within the selected register
+    pos: usize,
+}
+
+impl<const D: usize, const R: usize, const M: usize> Emulator<D, R, M> {
+    pub fn new(
+        devices: [I2cTargetDevice; D],
+        registers: [I2cTargetRegister; R],
+    ) -> Self {
+        let mut lengths = [0; R];
+
+        for (length, r) in lengths.iter_mut().zip(registers.iter()) {
+            *length = if r.block { 0 } else { r.size };
+        }
+
+        Self {
+            devices,
+            registers,
+            memory: [0; M],
+            lengths,
+            offsets: [0; D],
+            device: None,
+            register: None,
+            first: false,
+            pos: 0,
+        }
+    }
+
+    fn lookup_device(&self, address: u8) -> Result<usize, Error> {
+        self.devices
+            .iter()
+            .position(|d| d.address == address)
+            .ok_or(Error::BadAddress)
+    }
+
+    fn lookup_register(&self, address: u8, code: u8) -> Result<usize, Error> {
+        let device = self.lookup_device(address)?;
+
+        match self.devices[device].kind {
+            I2cTargetKind::Registers => {}
+            I2cTargetKind::Eeprom { .. } => {
+                return Err(Error::BadDevice);
+            }
+        }
+
+        self.registers
+            .iter()
+            .position(|r| r.address == address && r.code == code)
+            .ok_or(Error::BadRegister)
+    }
+
+    fn eeprom(
+        &self,
+        address: u8,
+        offset: usize,
+    ) -> Result<(usize, usize), Error> {
+        let device = self.lookup_device(address)?;
+
+        match self.devices[device].kind {
+            I2cTargetKind::Eeprom { base, size } if offset <= size => {
+                Ok((base + offset, size - offset))
+            }
+            I2cTargetKind::Eeprom { .. } => Err(Error::BadLength),
+            I2cTargetKind::Registers => Err(Error::BadDevice),
+        }
+    }
+
+    ///
+    /// Returns the memory of an EEPROM-like device from the specified offset
+    /// to its end.
+    ///
+    pub fn memory(&self, address: u8, offset: usize) -> Result<&[u8], Error> {
+        let (base, len) = self.eeprom(address, offset)?;
+        Ok(&self.memory[base..base + len])
+    }
+
+    ///
+    /// Returns `len` bytes of the memory of an EEPROM-like device from the
+    /// specified offset, to be written.
+    ///
+    pub fn memory_mut(
+        &mut self,
+        address: u8,
+        offset: usize,
+        len: usize,
+    ) -> Result<&mut [u8], Error> {
+        let (base, avail) = self.eeprom(address, offset)?;
+
+        if len > avail {
+            return Err(Error::BadLength);
+        }
+
+        Ok(&mut self.memory[base..base + len])
+    }
+
+    ///
+    /// Returns the current contents of a register.
+    ///
+    pub fn register(&self, address: u8, code: u8) -> Result<&[u8], Error> {
+        let ndx = self.lookup_register(address, code)?;
+        let base = self.registers[ndx].base;
+
+        Ok(&self.memory[base..base + self.lengths[ndx]])
+    }
+
+    ///
+    /// Returns the contents of a register, to be written with `len` bytes.
+    /// For a block register, this becomes the byte count of the block.
+    ///
+    pub fn register_mut(
+        &mut self,
+        address: u8,
+        code: u8,
+        len: usize,
+    ) -> Result<&mut [u8], Error> {
+        let ndx = self.lookup_register(address, code)?;
+        let r = &self.registers[ndx];
+
+        if len > r.size || (!r.block && len != r.size) {
+            return Err(Error::BadLength);
+        }
+
+        self.lengths[ndx] = len;
+        Ok(&mut self.memory[r.base..r.base + len])
+    }
+
+    ///
+    /// Called when the initiator addresses a device (including via a
+    /// repeated START), returning true if we are emulating it.  A repeated
+    /// START to the same device leaves its selected register in place, so
+    /// that a command code can be written and the register then read.
+    ///
+    pub fn initiate(&mut self, address: u8) -> bool {
+        let device = self.lookup_device(address).ok();
+
+        if device != self.device {
+            self.register = None;
+        }
+
+        self.device = device;
+        self.first = true;
+        self.pos = 0;
+
+        device.is_some()
+    }
+
+    pub fn rx(&mut self, _address: u8, byte: u8) {
+        let device = match self.device {
+            Some(device) => device,
+            None => return,
+        };
+
+        let first = self.first;
+        self.first = false;
+
+        match self.devices[device].kind {
+            I2cTargetKind::Eeprom { base, size } => {
+                let offset = &mut self.offsets[device];
+
+                if first {
+                    *offset = byte as usize % size;
+                } else {
+                    self.memory[base + *offset] = byte;
+                    *offset = (*offset + 1) % size;
+                }
+            }
+
+            I2cTargetKind::Registers => {
+                let address = self.devices[device].address;
+
+                if first {
+                    self.register = self.lookup_register(address, byte).ok();
+                    self.pos = 0;
+                    return;
+                }
+
+                let ndx = match self.register {
+                    Some(ndx) => ndx,
+                    None => return,
+                };
+
+                let r = &self.registers[ndx];
+                let pos = self.pos;
+                self.pos += 1;
+
+                if !r.block {
+                    if pos < r.size {
+                        self.memory[r.base + pos] = byte;
+                    }
+                } else if pos == 0 {
+                    self.lengths[ndx] = core::cmp::min(byte as usize, r.size);
+                } else if pos - 1 < self.lengths[ndx] {
+                    self.memory[r.base + pos - 1] = byte;
+                }
+            }
+        }
+    }
+
+    pub fn tx(&mut self, _address: u8) -> Option<u8> {
+        let device = self.device?;
+
+        match self.devices[device].kind {
+            I2cTargetKind::Eeprom { base, size } => {
+                let offset = &mut self.offsets[device];
+                let byte = self.memory[base + *offset];
+                *offset = (*offset + 1) % size;
+
+                Some(byte)
+            }
+
+            I2cTargetKind::Registers => {
+                let ndx = self.register?;
+                let r = &self.registers[ndx];
+                let len = self.lengths[ndx];
+                let pos = self.pos;
+                self.pos += 1;
+
+                if !r.block {
+                    if pos < len {
+                        Some(self.memory[r.base + pos])
+                    } else {
+                        None
+                    }
+                } else if pos == 0 {
+                    Some(len as u8)
+                } else if pos - 1 < len {
+                    Some(self.memory[r.base + pos - 1])
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EEPROM: u8 = 0x50;
+    const SMBUS: u8 = 0x60;
+
+    //
+    // A 4-byte EEPROM at 0x50, and a device at 0x60 with a 2-byte word
+    // register (0x01) and a block register of up to 4 bytes (0x02).
+    //
+    fn emulator() -> Emulator<2, 2, 10> {
+        Emulator::new(
+            [
+                I2cTargetDevice {
+                    address: EEPROM,
+                    kind: I2cTargetKind::Eeprom { base: 0, size: 4 },
+                },
+                I2cTargetDevice {
+                    address: SMBUS,
+                    kind: I2cTargetKind::Registers,
+                },
+            ],
+            [
+                I2cTargetRegister {
+                    address: SMBUS,
+                    code: 0x01,
+                    base: 4,
+                    size: 2,
+                    block: false,
+                },
+                I2cTargetRegister {
+                    address: SMBUS,
+                    code: 0x02,
+                    base: 6,
+                    size: 4,
+                    block: true,
+                },
+            ],
+        )
+    }
+
+    fn write(e: &mut Emulator<2, 2, 10>, address: u8, bytes: &[u8]) {
+        assert!(e.initiate(address));
+
+        for byte in bytes {
+            e.rx(address, *byte);
+        }
+    }
+
+    fn read(e: &mut Emulator<2, 2, 10>, address: u8) -> [Option<u8>; 6] {
+        let mut rval = [None; 6];
+        assert!(e.initiate(address));
+
+        for byte in rval.iter_mut() {
+            *byte = e.tx(address);
+        }
+
+        rval
+    }
+
+    #[test]
+    fn unknown_address() {
+        let mut e = emulator();
+        assert!(!e.initiate(0x70));
+        e.rx(0x70, 0x01);
+        assert_eq!(e.tx(0x70), None);
+        assert_eq!(e.memory(0x70, 0), Err(Error::BadAddress));
+    }
+
+    #[test]
+    fn eeprom_offset_wrap() {
+        let mut e = emulator();
+
+        // Writing from offset 3 wraps around to offset 0.
+        write(&mut e, EEPROM, &[3, 0xa, 0xb, 0xc]);
+        assert_eq!(e.memory(EEPROM, 0), Ok(&[0xb, 0xc, 0, 0xa][..]));
+
+        // An offset beyond the end of the device wraps, too.
+        write(&mut e, EEPROM, &[6]);
+        assert!(e.initiate(EEPROM));
+        assert_eq!(e.tx(EEPROM), Some(0));
+        assert_eq!(e.tx(EEPROM), Some(0xa));
+        assert_eq!(e.tx(EEPROM), Some(0xb));
+        assert_eq!(e.tx(EEPROM), Some(0xc));
+        assert_eq!(e.tx(EEPROM), Some(0));
+    }
+
+    #[test]
+    fn eeprom_memory() {
+        let mut e = emulator();
+
+        e.memory_mut(EEPROM, 1, 3)
+            .unwrap()
+            .copy_from_slice(&[1, 2, 3]);
+        assert_eq!(e.memory(EEPROM, 2), Ok(&[2, 3][..]));
+        assert_eq!(e.memory(EEPROM, 4), Ok(&[][..]));
+        assert_eq!(e.memory(EEPROM, 5), Err(Error::BadLength));
+        assert_eq!(e.memory_mut(EEPROM, 2, 3).err(), Some(Error::BadLength));
+        assert_eq!(e.memory(SMBUS, 0), Err(Error::BadDevice));
+    }
+
+    #[test]
+    fn register_write_read() {
+        let mut e = emulator();
+
+        write(&mut e, SMBUS, &[0x01, 0x34, 0x12, 0xff]);
+        assert_eq!(e.register(SMBUS, 0x01), Ok(&[0x34, 0x12][..]));
+
+        write(&mut e, SMBUS, &[0x01]);
+        assert_eq!(
+            read(&mut e, SMBUS),
+            [Some(0x34), Some(0x12), None, None, None, None]
+        );
+
+        assert_eq!(e.register(SMBUS, 0x03), Err(Error::BadRegister));
+        assert_eq!(e.register(EEPROM, 0x01), Err(Error::BadDevice));
+        assert_eq!(
+            e.register_mut(SMBUS, 0x01, 1).err(),
+            Some(Error::BadLength)
+        );
+    }
+
+    #[test]
+    fn block_byte_count() {
+        let mut e = emulator();
+
+        // An empty block reads as just its byte count.
+        write(&mut e, SMBUS, &[0x02]);
+        assert_eq!(
+            read(&mut e, SMBUS),
+            [Some(0), None, None, None, None, None]
+        );
+
+        // Bytes beyond the byte count are dropped.
+        write(&mut e, SMBUS, &[0x02, 2, 0xa, 0xb, 0xc]);
+        assert_eq!(e.register(SMBUS, 0x02), Ok(&[0xa, 0xb][..]));
+
+        // A byte count beyond the block's size is clamped to it.
+        write(&mut e, SMBUS, &[0x02, 9, 1, 2, 3, 4, 5]);
+        assert_eq!(e.register(SMBUS, 0x02), Ok(&[1, 2, 3, 4][..]));
+
+        write(&mut e, SMBUS, &[0x02]);
+        assert_eq!(
+            read(&mut e, SMBUS),
+            [Some(4), Some(1), Some(2), Some(3), Some(4), None]
+        );
+
+        // Writing via the API sets the byte count.
+        e.register_mut(SMBUS, 0x02, 1).unwrap()[0] = 0x5a;
+        write(&mut e, SMBUS, &[0x02]);
+        assert_eq!(
+            read(&mut e, SMBUS),
+            [Some(1), Some(0x5a), None, None, None, None]
+        );
+    }
+
+    #[test]
+    fn repeated_start() {
+        let mut e = emulator();
+        e.register_mut(SMBUS, 0x01, 2)
+            .unwrap()
+            .copy_from_slice(&[7, 8]);
+
+        // Command code, then a repeated START into a read.
+        assert!(e.initiate(SMBUS));
+        e.rx(SMBUS, 0x01);
+        assert!(e.initiate(SMBUS));
+        assert_eq!(e.tx(SMBUS), Some(7));
+        assert_eq!(e.tx(SMBUS), Some(8));
+        assert_eq!(e.tx(SMBUS), None);
+
+        // A repeated START restarts the read from the register's start...
+        assert!(e.initiate(SMBUS));
+        assert_eq!(e.tx(SMBUS), Some(7));
+
+        // ...but addressing another device forgets the register.
+        assert!(e.initiate(EEPROM));
+        assert!(e.initiate(SMBUS));
+        assert_eq!(e.tx(SMBUS), None);
+
+        // Likewise, an EEPROM read after a repeated START picks up at the
+        // offset that was written.
+        e.memory_mut(EEPROM, 0, 4)
+            .unwrap()
+            .copy_from_slice(&[1, 2, 3, 4]);
+        assert!(e.initiate(EEPROM));
+        e.rx(EEPROM, 2);
+        assert!(e.initiate(EEPROM));
+        assert_eq!(e.tx(EEPROM), Some(3));
+        assert_eq!(e.tx(EEPROM), Some(4));
+        assert_eq!(e.tx(EEPROM), Some(1));
+    }
+}
@@ -0,0 +1,230 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! PMBus regulator configuration blobs
+//!
+//! A regulator's configuration (e.g., `VOUT_COMMAND`, its fault limits, and
+//! `ON_OFF_CONFIG`) is described by a configuration blob:  a list of
+//! expected register values.  The blob format is:
+//!
+//! - The magic bytes `PMBC`, followed by a version byte ([`BLOB_VERSION`])
+//! - Any number of settings, each consisting of a page byte (or
+//!   [`NO_PAGE`] if the register isn't paged), a command code byte, a
+//!   length byte (between 1 and [`MAX_VALUE_LEN`]), and the value itself,
+//!   in the order that the device sends it (i.e., little-endian).
+//!
+//! Block commands are not supported.  Verifying and programming a device
+//! against a blob is the business of `drv_i2c_devices::pmbus_config`.
+
+#![no_std]
+
+/// The magic bytes that begin a configuration blob
+pub const BLOB_MAGIC: [u8; 4] = *b"PMBC";
+
+/// The version of the configuration blob format
+pub const BLOB_VERSION: u8 = 1;
+
+/// The page value in a setting that indicates that the register isn't paged
+pub const NO_PAGE: u8 = 0xff;
+
+/// The maximum length of a setting's value
+pub const MAX_VALUE_LEN: usize = 4;
+
+/// A malformed blob, with the offset of its first malformed byte
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BadBlob {
+    pub offset: usize,
+}
+
+/// A single expected register value
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Setting<'a> {
+    pub page: Option<u8>,
+    pub cmd: u8,
+    pub value: &'a [u8],
+}
+
+///
+/// A validated configuration blob.
+///
+#[derive(Copy, Clone, Debug)]
+pub struct Blob<'a> {
+    settings: &'a [u8],
+}
+
+impl<'a> Blob<'a> {
+    ///
+    /// Validates the specified blob, returning the offset of the first
+    /// malformed byte on failure.
+    ///
+    pub fn new(blob: &'a [u8]) -> Result<Self, BadBlob> {
+        let header = BLOB_MAGIC.len() + 1;
+
+        if blob.len() < header || blob[..BLOB_MAGIC.len()] != BLOB_MAGIC {
+            return Err(BadBlob { offset: 0 });
+        }
+
+        if blob[BLOB_MAGIC.len()] != BLOB_VERSION {
+            return Err(BadBlob {
+                offset: BLOB_MAGIC.len(),
+            });
+        }
+
+        let settings = &blob[header..];
+        let mut offset = 0;
+
+        while offset < settings.len() {
+            if offset + 3 > settings.len() {
+                return Err(BadBlob {
+                    offset: header + offset,
+                });
+            }
+
+            let len = settings[offset + 2] as usize;
+
+            if len == 0
+                || len > MAX_VALUE_LEN
+                || offset + 3 + len > settings.len()
+            {
+                return Err(BadBlob {
+                    offset: header + offset + 2,
+                });
+            }
+
+            offset += 3 + len;
+        }
+
+        Ok(Self { settings })
+    }
+
+    ///
+    /// Returns an iterator over the settings in the blob.
+    ///
+    pub fn settings(&self) -> impl Iterator<Item = Setting<'a>> {
+        let mut settings = self.settings;
+
+        core::iter::from_fn(move || {
+            if settings.is_empty() {
+                return None;
+            }
+
+            let len = settings[2] as usize;
+            let setting = Setting {
+                page: match settings[0] {
+                    NO_PAGE => None,
+                    page => Some(page),
+                },
+                cmd: settings[1],
+                value: &settings[3..3 + len],
+            };
+
+            settings = &settings[3 + len..];
+            Some(setting)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: [u8; 5] = [b'P', b'M', b'B', b'C', BLOB_VERSION];
+
+    fn blob(settings: &[u8]) -> [u8; 64] {
+        let mut blob = [0u8; 64];
+        blob[..HEADER.len()].copy_from_slice(&HEADER);
+        blob[HEADER.len()..HEADER.len() + settings.len()]
+            .copy_from_slice(settings);
+        blob
+    }
+
+    fn check(settings: &[u8]) -> Result<usize, BadBlob> {
+        let buf = blob(settings);
+        let blob = Blob::new(&buf[..HEADER.len() + settings.len()])?;
+        Ok(blob.settings().count())
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(check(&[]), Ok(0));
+    }
+
+    #[test]
+    fn bad_magic() {
+        assert_eq!(Blob::new(b"PMBX\x01").err(), Some(BadBlob { offset: 0 }));
+        assert_eq!(Blob::new(b"PMB").err(), Some(BadBlob { offset: 0 }));
+        assert_eq!(Blob::new(b"").err(), Some(BadBlob { offset: 0 }));
+    }
+
+    #[test]
+    fn bad_version() {
+        assert_eq!(Blob::new(b"PMBC\x02").err(), Some(BadBlob { offset: 4 }));
+        assert_eq!(Blob::new(b"PMBC\x00").err(), Some(BadBlob { offset: 4 }));
+    }
+
+    #[test]
+    fn zero_length() {
+        assert_eq!(check(&[NO_PAGE, 0x21, 0]), Err(BadBlob { offset: 7 }));
+    }
+
+    #[test]
+    fn over_long() {
+        let len = MAX_VALUE_LEN as u8 + 1;
+        let setting = [0, 0x21, len, 1, 2, 3, 4, 5];
+        assert_eq!(check(&setting), Err(BadBlob { offset: 7 }));
+    }
+
+    #[test]
+    fn truncated_header() {
+        let setting = [NO_PAGE, 0x01, 1, 0x80, NO_PAGE, 0x02];
+        assert_eq!(check(&setting), Err(BadBlob { offset: 9 }));
+    }
+
+    #[test]
+    fn truncated_value() {
+        let setting = [NO_PAGE, 0x01, 1, 0x80, 1, 0x21, 2, 0x00];
+        assert_eq!(check(&setting), Err(BadBlob { offset: 11 }));
+    }
+
+    #[test]
+    fn settings() {
+        let settings = [
+            NO_PAGE, 0x01, 1, 0x80, //
+            1, 0x21, 2, 0x34, 0x12, //
+            0, 0x40, 4, 1, 2, 3, 4,
+        ];
+        let buf = blob(&settings);
+        let blob = Blob::new(&buf[..HEADER.len() + settings.len()]).unwrap();
+        let mut iter = blob.settings();
+
+        assert_eq!(
+            iter.next(),
+            Some(Setting {
+                page: None,
+                cmd: 0x01,
+                value: &[0x80],
+            })
+        );
+
+        assert_eq!(
+            iter.next(),
+            Some(Setting {
+                page: Some(1),
+                cmd: 0x21,
+                value: &[0x34, 0x12],
+            })
+        );
+
+        assert_eq!(
+            iter.next(),
+            Some(Setting {
+                page: Some(0),
+                cmd: 0x40,
+                value: &[1, 2, 3, 4],
+            })
+        );
+
+        assert_eq!(iter.next(), None);
+    }
+}
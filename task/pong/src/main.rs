@@ -7,6 +7,7 @@
 
 use userlib::*;
 
+#[cfg(not(feature = "no-leds"))]
 task_slot!(USER_LEDS, user_leds);
 
 #[export_name = "main"]
@@ -16,9 +17,11 @@ pub fn main() -> ! {
 
     let mut response: u32 = 0;
 
+    #[cfg(not(feature = "no-leds"))]
     let user_leds = drv_user_leds_api::UserLeds::from(USER_LEDS.get_task_id());
 
-    let mut current = 0;
+    #[cfg(not(feature = "no-leds"))]
+    let mut current = 0usize;
     let mut msg = [0; 16];
     let mut dl = INTERVAL;
     sys_set_timer(Some(dl), TIMER_NOTIFICATION);
@@ -35,18 +38,27 @@ pub fn main() -> ! {
             dl += INTERVAL;
             sys_set_timer(Some(dl), TIMER_NOTIFICATION);
 
-            // Toggle the current LED -- and if we've run out, start over
-            loop {
-                match user_leds.led_toggle(current >> 1) {
-                    Ok(_) => {
-                        current = current + 1;
-                        break;
-                    }
-                    Err(drv_user_leds_api::LedError::NotPresent) => {
-                        current = 0;
-                    }
-                };
-            }
+            #[cfg(not(feature = "no-leds"))]
+            toggle_next_led(&user_leds, &mut current);
         }
     }
 }
+
+#[cfg(not(feature = "no-leds"))]
+fn toggle_next_led(
+    user_leds: &drv_user_leds_api::UserLeds,
+    current: &mut usize,
+) {
+    // Toggle the current LED -- and if we've run out, start over
+    loop {
+        match user_leds.led_toggle(*current >> 1) {
+            Ok(_) => {
+                *current = *current + 1;
+                break;
+            }
+            Err(drv_user_leds_api::LedError::NotPresent) => {
+                *current = 0;
+            }
+        };
+    }
+}
@@ -0,0 +1,70 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Client API for the Power task.
+
+#![no_std]
+
+use derive_idol_err::IdolError;
+use serde::{Deserialize, Serialize};
+use userlib::*;
+use zerocopy::AsBytes;
+
+#[derive(Copy, Clone, Debug, FromPrimitive, PartialEq, IdolError)]
+pub enum PowerError {
+    InvalidRail = 1,
+    NoFault = 2,
+    NoStatus = 3,
+    DeviceError = 4,
+    UnknownRail = 5,
+    ConfigLocked = 6,
+    BadConfig = 7,
+    ConfigMismatch = 8,
+}
+
+#[derive(Copy, Clone, Debug, FromPrimitive, PartialEq, AsBytes)]
+#[repr(u8)]
+pub enum RailState {
+    Off = 0,
+    On = 1,
+}
+
+/// The most recent readings from a rail; readings are `None` if the rail is
+/// off or couldn't be read.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RailReading {
+    /// Output voltage, in volts
+    pub voltage: Option<f32>,
+    /// Output current, in amperes
+    pub current: Option<f32>,
+    /// Temperature, in degrees Celsius, if the rail's controller has one
+    pub temperature: Option<f32>,
+    /// Output power, in watts
+    pub power: Option<f32>,
+}
+
+/// The PMBus status registers of a rail.  `vout`, `iout`, and `temperature`
+/// are only read if the corresponding bit in `word` is set, and are zero
+/// otherwise.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RailStatus {
+    pub word: u16,
+    pub vout: u8,
+    pub iout: u8,
+    pub temperature: u8,
+}
+
+/// A fault latched on a rail.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RailFault {
+    /// Status as of when the fault was latched
+    pub status: RailStatus,
+    /// Time (in milliseconds since boot) at which the fault was latched
+    pub timestamp: u64,
+    /// Number of polls that have found the rail faulted since the fault was
+    /// latched
+    pub count: u32,
+}
+
+include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
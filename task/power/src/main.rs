@@ -4,7 +4,23 @@
 
 //! Power monitoring
 //!
-//! This is a primordial power monitoring task.
+//! This is a primordial power monitoring task.  Every second, it posts the
+//! voltage, current, and temperature of each rail to the sensor task, and
+//! polls each rail's PMBus status.  Rails with hot swap controllers also
+//! report their input power -- for the sled's hot swap controller, that of
+//! the entire board -- and the energy consumed through them (in watt-hours,
+//! accumulated since the task started).
+//!
+//! The most recent readings and status of each rail (named as in the
+//! application's PMBus configuration) can be retrieved via the task's Idol
+//! interface.  The first fault seen on a rail is latched (along with when it
+//! was seen) until it is explicitly cleared; if built with the
+//! `notify-sequencer` feature, the sequencer is notified of each newly
+//! latched fault.
+//!
+//! The configuration of each rail's device can also be verified against (and,
+//! once explicitly unlocked, programmed from and stored to NVM) a PMBus
+//! configuration blob; see `drv_i2c_devices::pmbus_config`.
 //!
 
 #![no_std]
@@ -12,16 +28,22 @@
 
 use drv_gimlet_seq_api as seq_api;
 use drv_i2c_devices::adm1272::*;
-use drv_i2c_devices::bmr491::*;
-use drv_i2c_devices::isl68224::*;
-use drv_i2c_devices::raa229618::*;
-use drv_i2c_devices::tps546b24a::*;
+use drv_i2c_devices::pmbus_config::{self, Blob, PmbusConfig};
+use drv_i2c_devices::pmbus_device::PmbusDevice;
+use idol_runtime::{Leased, LenLimit, NotificationHandler, RequestError, R, W};
+use ringbuf::*;
+use task_power_api::{
+    PowerError, RailFault, RailReading, RailState, RailStatus,
+};
 use task_sensor_api as sensor_api;
 use userlib::units::*;
 use userlib::*;
 
 use drv_i2c_api::ResponseCode;
-use drv_i2c_devices::{CurrentSensor, TempSensor, VoltageSensor};
+use drv_i2c_devices::{
+    CurrentSensor, PmbusStatus, PowerSensor, StatusSensor, TempSensor,
+    VoltageSensor,
+};
 
 use sensor_api::{NoData, SensorId};
 use seq_api::PowerState;
@@ -32,24 +54,65 @@ task_slot!(SEQUENCER, gimlet_seq);
 
 include!(concat!(env!("OUT_DIR"), "/i2c_config.rs"));
 
-use i2c_config::sensors;
+use i2c_config::power::{self, NUM_RAILS};
 
 enum Device {
-    IBC(Bmr491),
-    Core(Raa229618),
-    Mem(Raa229618),
-    MemVpp(Isl68224),
-    Sys(Tps546b24a),
-    HotSwap(Adm1272),
-    Fan(Adm1272),
+    Pmbus(PmbusDevice),
+    Adm1272(Adm1272),
 }
 
 struct PowerController {
+    /// Name of the rail, as it appears in the PMBus configuration
+    name: &'static str,
+
+    /// Power state in (and above) which the rail is on
     state: seq_api::PowerState,
     device: Device,
+
+    /// Configuration of the rail's device, which is locked until unlocked
+    /// by a client
+    config: PmbusConfig,
     voltage: SensorId,
     current: SensorId,
     temperature: Option<SensorId>,
+    power: Option<SensorId>,
+    energy: Option<Energy>,
+}
+
+/// Energy accounting for a rail whose controller accumulates energy.
+struct Energy {
+    id: SensorId,
+
+    /// Time of the last successful read of the energy accumulator
+    last: Option<u64>,
+
+    /// Energy consumed, in watt-hours
+    wh: f64,
+}
+
+impl Energy {
+    fn new(id: SensorId) -> Self {
+        Energy {
+            id,
+            last: None,
+            wh: 0.0,
+        }
+    }
+
+    ///
+    /// Accounts for the average power since the last read of the energy
+    /// accumulator (if any) as of `now`.  If the accumulator can't be read,
+    /// we leave `last` as it is: the next successful read will return the
+    /// average power over the entire interval.
+    ///
+    fn update(&mut self, average: Option<Watts>, now: u64) {
+        if let (Some(last), Some(watts)) = (self.last, average) {
+            let hours = (now - last) as f64 / (60.0 * 60.0 * 1000.0);
+            self.wh += watts.0 as f64 * hours;
+        }
+
+        self.last = Some(now);
+    }
 }
 
 fn read_temperature<E, T: TempSensor<E>>(
@@ -97,151 +160,216 @@ where
     }
 }
 
+fn read_power<E, T: PowerSensor<E>>(
+    device: &mut T,
+) -> Result<Watts, ResponseCode>
+where
+    ResponseCode: From<E>,
+{
+    match device.read_power() {
+        Ok(reading) => Ok(reading),
+        Err(err) => {
+            let err: ResponseCode = err.into();
+            Err(err)
+        }
+    }
+}
+
+fn read_status<E, T: StatusSensor<E>>(
+    device: &mut T,
+) -> Result<PmbusStatus, ResponseCode>
+where
+    ResponseCode: From<E>,
+{
+    match device.read_status() {
+        Ok(status) => Ok(status),
+        Err(err) => {
+            let err: ResponseCode = err.into();
+            Err(err)
+        }
+    }
+}
+
+fn clear_faults<E, T: StatusSensor<E>>(
+    device: &mut T,
+) -> Result<(), ResponseCode>
+where
+    ResponseCode: From<E>,
+{
+    match device.clear_faults() {
+        Ok(_) => Ok(()),
+        Err(err) => {
+            let err: ResponseCode = err.into();
+            Err(err)
+        }
+    }
+}
+
 impl PowerController {
+    fn new(rail: power::Rail) -> Self {
+        PowerController {
+            name: rail.name,
+            state: rail.state,
+            config: PmbusConfig::new(&rail.device),
+            device: match rail.part {
+                power::Part::Pmbus(quirks) => Device::Pmbus(PmbusDevice::new(
+                    &rail.device,
+                    rail.rail,
+                    quirks,
+                )),
+                power::Part::Adm1272(rsense) => {
+                    Device::Adm1272(Adm1272::new(&rail.device, rsense))
+                }
+            },
+            voltage: rail.voltage,
+            current: rail.current,
+            temperature: rail.temperature,
+            power: rail.power,
+            energy: rail.energy.map(Energy::new),
+        }
+    }
+
+    fn is_on(&self, state: PowerState) -> bool {
+        state as u8 >= self.state as u8
+    }
+
     fn read_temperature(&mut self) -> Result<Celsius, ResponseCode> {
         match &mut self.device {
-            Device::IBC(dev) => read_temperature(dev),
-            Device::Core(dev) | Device::Mem(dev) => read_temperature(dev),
-            Device::MemVpp(_) => panic!(),
-            Device::Sys(dev) => read_temperature(dev),
-            Device::HotSwap(dev) | Device::Fan(dev) => read_temperature(dev),
+            Device::Pmbus(dev) => read_temperature(dev),
+            Device::Adm1272(dev) => read_temperature(dev),
         }
     }
 
     fn read_iout(&mut self) -> Result<Amperes, ResponseCode> {
         match &mut self.device {
-            Device::IBC(dev) => read_current(dev),
-            Device::Core(dev) | Device::Mem(dev) => read_current(dev),
-            Device::MemVpp(dev) => read_current(dev),
-            Device::Sys(dev) => read_current(dev),
-            Device::HotSwap(dev) | Device::Fan(dev) => read_current(dev),
+            Device::Pmbus(dev) => read_current(dev),
+            Device::Adm1272(dev) => read_current(dev),
         }
     }
 
     fn read_vout(&mut self) -> Result<Volts, ResponseCode> {
         match &mut self.device {
-            Device::IBC(dev) => read_voltage(dev),
-            Device::Core(dev) | Device::Mem(dev) => read_voltage(dev),
-            Device::MemVpp(dev) => read_voltage(dev),
-            Device::Sys(dev) => read_voltage(dev),
-            Device::HotSwap(dev) | Device::Fan(dev) => read_voltage(dev),
+            Device::Pmbus(dev) => read_voltage(dev),
+            Device::Adm1272(dev) => read_voltage(dev),
         }
     }
-}
 
-macro_rules! rail_controller {
-    ($task:expr, $which:ident, $dev:ident, $rail:ident, $state:ident) => {
-        paste::paste! {
-            PowerController {
-                state: seq_api::PowerState::$state,
-                device: Device::$which({
-                    let (device, rail) = i2c_config::pmbus::$rail($task);
-                    [<$dev:camel>]::new(&device, rail)
-                }),
-                voltage: sensors::[<$dev:upper _ $rail:upper _VOLTAGE_SENSOR>],
-                current: sensors::[<$dev:upper _ $rail:upper _CURRENT_SENSOR>],
-                temperature: Some(
-                    sensors::[<$dev:upper _ $rail:upper _TEMPERATURE_SENSOR>]
-                ),
-            }
+    fn read_power(&mut self) -> Result<Watts, ResponseCode> {
+        match &mut self.device {
+            Device::Pmbus(dev) => read_power(dev),
+            Device::Adm1272(dev) => read_power(dev),
         }
-    };
-}
+    }
 
-macro_rules! rail_controller_notemp {
-    ($task:expr, $which:ident, $dev:ident, $rail:ident, $state:ident) => {
-        paste::paste! {
-            PowerController {
-                state: seq_api::PowerState::$state,
-                device: Device::$which({
-                    let (device, rail) = i2c_config::pmbus::$rail($task);
-                    [<$dev:camel>]::new(&device, rail)
-                }),
-                voltage: sensors::[<$dev:upper _ $rail:upper _VOLTAGE_SENSOR>],
-                current: sensors::[<$dev:upper _ $rail:upper _CURRENT_SENSOR>],
-                temperature: None,
+    ///
+    /// Returns the average power since the last call, or `None` if this is
+    /// the first call -- or if the device has no energy accumulator.
+    ///
+    fn average_power(&mut self) -> Result<Option<Watts>, ResponseCode> {
+        match &mut self.device {
+            Device::Pmbus(_) => Ok(None),
+            Device::Adm1272(dev) => {
+                dev.average_power().map_err(ResponseCode::from)
             }
         }
-    };
-}
+    }
 
-macro_rules! adm1272_controller {
-    ($task:expr, $which:ident, $rail:ident, $state:ident, $rsense:expr) => {
-        paste::paste! {
-            PowerController {
-                state: seq_api::PowerState::$state,
-                device: Device::$which({
-                    let (device, _) = i2c_config::pmbus::$rail($task);
-                    Adm1272::new(&device, $rsense)
-                }),
-                voltage: sensors::[<ADM1272_ $rail:upper _VOLTAGE_SENSOR>],
-                current: sensors::[<ADM1272_ $rail:upper _CURRENT_SENSOR>],
-                temperature: Some(
-                    sensors::[<ADM1272_ $rail:upper _TEMPERATURE_SENSOR>]
-                ),
-            }
+    fn read_status(&mut self) -> Result<PmbusStatus, ResponseCode> {
+        match &mut self.device {
+            Device::Pmbus(dev) => read_status(dev),
+            Device::Adm1272(dev) => read_status(dev),
         }
-    };
+    }
+
+    fn clear_faults(&mut self) -> Result<(), ResponseCode> {
+        match &mut self.device {
+            Device::Pmbus(dev) => clear_faults(dev),
+            Device::Adm1272(dev) => clear_faults(dev),
+        }
+    }
 }
 
-#[cfg(target_board = "gimlet-a")]
-fn controllers() -> [PowerController; 13] {
-    let task = I2C.get_task_id();
-
-    [
-        rail_controller!(task, IBC, bmr491, v12_sys_a2, A2),
-        rail_controller!(task, Core, raa229618, vdd_vcore, A0),
-        rail_controller!(task, Core, raa229618, vddcr_soc, A0),
-        rail_controller!(task, Mem, raa229618, vdd_mem_abcd, A0),
-        rail_controller!(task, Mem, raa229618, vdd_mem_efgh, A0),
-        rail_controller_notemp!(task, MemVpp, isl68224, vpp_abcd, A0),
-        rail_controller_notemp!(task, MemVpp, isl68224, vpp_efgh, A0),
-        rail_controller_notemp!(task, MemVpp, isl68224, v3p3_sys, A0),
-        rail_controller!(task, Sys, tps546b24a, v3p3_sp_a2, A2),
-        rail_controller!(task, Sys, tps546b24a, v1p8_sp3, A0),
-        rail_controller!(task, Sys, tps546b24a, v5_sys_a2, A2),
-        adm1272_controller!(task, HotSwap, v54_hs_output, A2, Ohms(0.001)),
-        adm1272_controller!(task, Fan, v54_fan, A2, Ohms(0.002)),
-    ]
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Trace {
+    None,
+    Fault(u8, u16),
+    StatusFailed(u8, ResponseCode),
+    Cleared(u8),
+    ClearFailed(u8, ResponseCode),
+    ConfigMismatch(u8, u8),
+    ConfigUnlocked(u8),
+    ConfigStored(u8),
 }
 
-#[cfg(target_board = "gimlet-b")]
-fn controllers() -> [PowerController; 15] {
-    let task = I2C.get_task_id();
-
-    [
-        rail_controller!(task, IBC, bmr491, v12_sys_a2, A2),
-        rail_controller!(task, Core, raa229618, vdd_vcore, A0),
-        rail_controller!(task, Core, raa229618, vddcr_soc, A0),
-        rail_controller!(task, Mem, raa229618, vdd_mem_abcd, A0),
-        rail_controller!(task, Mem, raa229618, vdd_mem_efgh, A0),
-        rail_controller_notemp!(task, MemVpp, isl68224, vpp_abcd, A0),
-        rail_controller_notemp!(task, MemVpp, isl68224, vpp_efgh, A0),
-        rail_controller_notemp!(task, MemVpp, isl68224, v1p8_sp3, A0),
-        rail_controller!(task, Sys, tps546b24a, v3p3_sp_a2, A2),
-        rail_controller!(task, Sys, tps546b24a, v3p3_sys_a0, A0),
-        rail_controller!(task, Sys, tps546b24a, v5_sys_a2, A2),
-        rail_controller!(task, Sys, tps546b24a, v1p8_sys_a2, A2),
-        rail_controller!(task, Sys, tps546b24a, v0p96_nic_vdd_a0hp, A0),
-        adm1272_controller!(task, HotSwap, v54_hs_output, A2, Ohms(0.001)),
-        adm1272_controller!(task, Fan, v54_fan, A2, Ohms(0.002)),
-    ]
+ringbuf!(Trace, 32, Trace::None);
+
+impl From<PmbusStatus> for RailStatus {
+    fn from(status: PmbusStatus) -> Self {
+        RailStatus {
+            word: status.word,
+            vout: status.vout,
+            iout: status.iout,
+            temperature: status.temperature,
+        }
+    }
 }
 
-#[export_name = "main"]
-fn main() -> ! {
-    let sensor = sensor_api::Sensor::from(SENSOR.get_task_id());
-    let sequencer = seq_api::Sequencer::from(SEQUENCER.get_task_id());
+struct ServerImpl {
+    sensor: sensor_api::Sensor,
+    sequencer: seq_api::Sequencer,
+    controllers: [PowerController; NUM_RAILS],
 
-    let mut controllers = controllers();
+    /// Status of each rail as of the most recent poll, if it could be read
+    status: [Option<RailStatus>; NUM_RAILS],
 
-    loop {
-        hl::sleep_for(1000);
+    /// Fault latched on each rail, if any
+    faults: [Option<RailFault>; NUM_RAILS],
+
+    /// Readings from each rail as of the most recent poll
+    readings: [RailReading; NUM_RAILS],
+    deadline: u64,
+}
+
+/// Longest rail name accepted by `lookup_rail` (as enforced by the IDL).
+const MAX_RAIL_NAME: usize = 32;
+
+/// Longest configuration blob accepted (as enforced by the IDL).
+const MAX_CONFIG_BLOB: usize = 256;
+
+fn config_error(err: pmbus_config::Error) -> PowerError {
+    match err {
+        pmbus_config::Error::BadRead { .. }
+        | pmbus_config::Error::BadWrite { .. } => PowerError::DeviceError,
+        pmbus_config::Error::BadBlob { .. } => PowerError::BadConfig,
+        pmbus_config::Error::Locked => PowerError::ConfigLocked,
+        pmbus_config::Error::Mismatch { .. } => PowerError::ConfigMismatch,
+    }
+}
+
+const TIMER_MASK: u32 = 1 << 0;
+const TIMER_INTERVAL: u64 = 1000;
+
+impl ServerImpl {
+    fn config(
+        &mut self,
+        rail: u8,
+    ) -> Result<&mut PmbusConfig, RequestError<PowerError>> {
+        match self.controllers.get_mut(rail as usize) {
+            Some(c) => Ok(&mut c.config),
+            None => Err(PowerError::InvalidRail.into()),
+        }
+    }
+
+    /// Polls every rail, posting its readings and latching any faults.
+    fn poll(&mut self) {
+        let sensor = &self.sensor;
+        let state = self.sequencer.get_state().unwrap();
 
-        let state = sequencer.get_state().unwrap();
+        for (rail, c) in self.controllers.iter_mut().enumerate() {
+            let reading = &mut self.readings[rail];
+            *reading = RailReading::default();
 
-        for c in &mut controllers {
-            if c.state == PowerState::A0 && state != PowerState::A0 {
+            if !c.is_on(state) {
                 sensor.nodata(c.voltage, NoData::DeviceOff).unwrap();
                 sensor.nodata(c.current, NoData::DeviceOff).unwrap();
 
@@ -249,13 +377,23 @@ fn main() -> ! {
                     sensor.nodata(id, NoData::DeviceOff).unwrap();
                 }
 
+                if let Some(id) = c.power {
+                    sensor.nodata(id, NoData::DeviceOff).unwrap();
+                }
+
+                if let Some(energy) = &c.energy {
+                    sensor.nodata(energy.id, NoData::DeviceOff).unwrap();
+                }
+
+                self.status[rail] = None;
                 continue;
             }
 
             if let Some(id) = c.temperature {
                 match c.read_temperature() {
-                    Ok(reading) => {
-                        sensor.post(id, reading.0).unwrap();
+                    Ok(temp) => {
+                        sensor.post(id, temp.0).unwrap();
+                        reading.temperature = Some(temp.0);
                     }
                     Err(_) => {
                         sensor.nodata(id, NoData::DeviceError).unwrap();
@@ -264,8 +402,9 @@ fn main() -> ! {
             }
 
             match c.read_iout() {
-                Ok(reading) => {
-                    sensor.post(c.current, reading.0).unwrap();
+                Ok(iout) => {
+                    sensor.post(c.current, iout.0).unwrap();
+                    reading.current = Some(iout.0);
                 }
                 Err(_) => {
                     sensor.nodata(c.current, NoData::DeviceError).unwrap();
@@ -273,13 +412,336 @@ fn main() -> ! {
             }
 
             match c.read_vout() {
-                Ok(reading) => {
-                    sensor.post(c.voltage, reading.0).unwrap();
+                Ok(vout) => {
+                    sensor.post(c.voltage, vout.0).unwrap();
+                    reading.voltage = Some(vout.0);
                 }
                 Err(_) => {
                     sensor.nodata(c.voltage, NoData::DeviceError).unwrap();
                 }
             }
+
+            if let Some(id) = c.power {
+                match c.read_power() {
+                    Ok(power) => {
+                        sensor.post(id, power.0).unwrap();
+                        reading.power = Some(power.0);
+                    }
+                    Err(_) => {
+                        sensor.nodata(id, NoData::DeviceError).unwrap();
+                    }
+                }
+            } else if let (Some(v), Some(i)) =
+                (reading.voltage, reading.current)
+            {
+                reading.power = Some(v * i);
+            }
+
+            if c.energy.is_some() {
+                let now = sys_get_timer().now;
+                let average = c.average_power();
+                let energy = c.energy.as_mut().unwrap();
+
+                match average {
+                    Ok(average) => {
+                        energy.update(average, now);
+                        sensor.post(energy.id, energy.wh as f32).unwrap();
+                    }
+                    Err(_) => {
+                        sensor.nodata(energy.id, NoData::DeviceError).unwrap();
+                    }
+                }
+            }
+
+            let status = match c.read_status() {
+                Ok(status) => status,
+                Err(code) => {
+                    ringbuf_entry!(Trace::StatusFailed(rail as u8, code));
+                    self.status[rail] = None;
+                    continue;
+                }
+            };
+
+            self.status[rail] = Some(status.into());
+
+            if !status.is_faulted() {
+                continue;
+            }
+
+            match &mut self.faults[rail] {
+                Some(fault) => {
+                    fault.count = fault.count.saturating_add(1);
+                }
+                None => {
+                    ringbuf_entry!(Trace::Fault(rail as u8, status.word));
+
+                    self.faults[rail] = Some(RailFault {
+                        status: status.into(),
+                        timestamp: sys_get_timer().now,
+                        count: 1,
+                    });
+
+                    #[cfg(feature = "notify-sequencer")]
+                    sys_post(
+                        sys_refresh_task_id(SEQUENCER.get_task_id()),
+                        seq_api::POWER_FAULT_NOTIFICATION,
+                    );
+                }
+            }
+        }
+    }
+}
+
+impl idl::InOrderPowerImpl for ServerImpl {
+    fn num_rails(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<u32, RequestError<PowerError>> {
+        Ok(NUM_RAILS as u32)
+    }
+
+    fn rail_name(
+        &mut self,
+        _: &RecvMessage,
+        rail: u8,
+        dest: Leased<W, [u8]>,
+    ) -> Result<u32, RequestError<PowerError>> {
+        let c = self
+            .controllers
+            .get(rail as usize)
+            .ok_or(PowerError::InvalidRail)?;
+        let name = c.name.as_bytes();
+        let len = usize::min(dest.len(), name.len());
+
+        dest.write_range(0..len, &name[..len])
+            .map_err(|_| RequestError::went_away())?;
+
+        Ok(name.len() as u32)
+    }
+
+    fn lookup_rail(
+        &mut self,
+        _: &RecvMessage,
+        name: LenLimit<Leased<R, [u8]>, MAX_RAIL_NAME>,
+    ) -> Result<u8, RequestError<PowerError>> {
+        let mut buf = [0u8; MAX_RAIL_NAME];
+        let len = name.len();
+
+        name.read_range(0..len, &mut buf[..len])
+            .map_err(|_| RequestError::went_away())?;
+
+        match self
+            .controllers
+            .iter()
+            .position(|c| c.name.as_bytes() == &buf[..len])
+        {
+            Some(rail) => Ok(rail as u8),
+            None => Err(PowerError::UnknownRail.into()),
+        }
+    }
+
+    fn get_rail_state(
+        &mut self,
+        _: &RecvMessage,
+        rail: u8,
+    ) -> Result<RailState, RequestError<PowerError>> {
+        let c = self
+            .controllers
+            .get(rail as usize)
+            .ok_or(PowerError::InvalidRail)?;
+        let state = self.sequencer.get_state().unwrap();
+
+        Ok(if c.is_on(state) {
+            RailState::On
+        } else {
+            RailState::Off
+        })
+    }
+
+    fn get_reading(
+        &mut self,
+        _: &RecvMessage,
+        rail: u8,
+    ) -> Result<RailReading, RequestError<PowerError>> {
+        match self.readings.get(rail as usize) {
+            Some(reading) => Ok(*reading),
+            None => Err(PowerError::InvalidRail.into()),
+        }
+    }
+
+    fn faulted_rails(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<u32, RequestError<PowerError>> {
+        Ok(self
+            .faults
+            .iter()
+            .enumerate()
+            .filter(|(_, fault)| fault.is_some())
+            .fold(0, |mask, (rail, _)| mask | (1 << rail)))
+    }
+
+    fn get_fault(
+        &mut self,
+        _: &RecvMessage,
+        rail: u8,
+    ) -> Result<RailFault, RequestError<PowerError>> {
+        match self.faults.get(rail as usize) {
+            Some(Some(fault)) => Ok(*fault),
+            Some(None) => Err(PowerError::NoFault.into()),
+            None => Err(PowerError::InvalidRail.into()),
+        }
+    }
+
+    fn get_status(
+        &mut self,
+        _: &RecvMessage,
+        rail: u8,
+    ) -> Result<RailStatus, RequestError<PowerError>> {
+        match self.status.get(rail as usize) {
+            Some(Some(status)) => Ok(*status),
+            Some(None) => Err(PowerError::NoStatus.into()),
+            None => Err(PowerError::InvalidRail.into()),
+        }
+    }
+
+    fn clear_fault(
+        &mut self,
+        _: &RecvMessage,
+        rail: u8,
+    ) -> Result<(), RequestError<PowerError>> {
+        let c = self
+            .controllers
+            .get_mut(rail as usize)
+            .ok_or(PowerError::InvalidRail)?;
+
+        //
+        // Only forget the fault once the device has, lest we latch it again
+        // on the next poll with a misleading timestamp.
+        //
+        match c.clear_faults() {
+            Ok(_) => {
+                ringbuf_entry!(Trace::Cleared(rail));
+                self.faults[rail as usize] = None;
+                Ok(())
+            }
+            Err(code) => {
+                ringbuf_entry!(Trace::ClearFailed(rail, code));
+                Err(PowerError::DeviceError.into())
+            }
         }
     }
+
+    fn verify_config(
+        &mut self,
+        _: &RecvMessage,
+        rail: u8,
+        blob: LenLimit<Leased<R, [u8]>, MAX_CONFIG_BLOB>,
+    ) -> Result<u32, RequestError<PowerError>> {
+        let config = self.config(rail)?;
+        let mut buf = [0u8; MAX_CONFIG_BLOB];
+        let len = blob.len();
+
+        blob.read_range(0..len, &mut buf[..len])
+            .map_err(|_| RequestError::went_away())?;
+
+        let blob = Blob::new(&buf[..len]).map_err(|_| PowerError::BadConfig)?;
+
+        let count = config
+            .verify(&blob, |m| {
+                ringbuf_entry!(Trace::ConfigMismatch(rail, m.cmd));
+            })
+            .map_err(config_error)?;
+
+        Ok(count as u32)
+    }
+
+    fn unlock_config(
+        &mut self,
+        _: &RecvMessage,
+        rail: u8,
+    ) -> Result<(), RequestError<PowerError>> {
+        self.config(rail)?.unlock();
+        ringbuf_entry!(Trace::ConfigUnlocked(rail));
+        Ok(())
+    }
+
+    fn lock_config(
+        &mut self,
+        _: &RecvMessage,
+        rail: u8,
+    ) -> Result<(), RequestError<PowerError>> {
+        self.config(rail)?.lock();
+        Ok(())
+    }
+
+    fn program_config(
+        &mut self,
+        _: &RecvMessage,
+        rail: u8,
+        blob: LenLimit<Leased<R, [u8]>, MAX_CONFIG_BLOB>,
+    ) -> Result<(), RequestError<PowerError>> {
+        let config = self.config(rail)?;
+        let mut buf = [0u8; MAX_CONFIG_BLOB];
+        let len = blob.len();
+
+        blob.read_range(0..len, &mut buf[..len])
+            .map_err(|_| RequestError::went_away())?;
+
+        let blob = Blob::new(&buf[..len]).map_err(|_| PowerError::BadConfig)?;
+
+        config.program(&blob).map_err(config_error)?;
+        Ok(())
+    }
+
+    fn store_config(
+        &mut self,
+        _: &RecvMessage,
+        rail: u8,
+    ) -> Result<(), RequestError<PowerError>> {
+        self.config(rail)?.store().map_err(config_error)?;
+        ringbuf_entry!(Trace::ConfigStored(rail));
+        Ok(())
+    }
+}
+
+impl NotificationHandler for ServerImpl {
+    fn current_notification_mask(&self) -> u32 {
+        TIMER_MASK
+    }
+
+    fn handle_notification(&mut self, _bits: u32) {
+        self.deadline += TIMER_INTERVAL;
+        sys_set_timer(Some(self.deadline), TIMER_MASK);
+
+        self.poll();
+    }
+}
+
+#[export_name = "main"]
+fn main() -> ! {
+    let deadline = sys_get_timer().now + TIMER_INTERVAL;
+    sys_set_timer(Some(deadline), TIMER_MASK);
+
+    let mut server = ServerImpl {
+        sensor: sensor_api::Sensor::from(SENSOR.get_task_id()),
+        sequencer: seq_api::Sequencer::from(SEQUENCER.get_task_id()),
+        controllers: power::rails(I2C.get_task_id()).map(PowerController::new),
+        status: [None; NUM_RAILS],
+        faults: [None; NUM_RAILS],
+        readings: [RailReading::default(); NUM_RAILS],
+        deadline,
+    };
+
+    let mut buffer = [0; idl::INCOMING_SIZE];
+
+    loop {
+        idol_runtime::dispatch_n(&mut buffer, &mut server);
+    }
+}
+
+mod idl {
+    use super::{PowerError, RailFault, RailReading, RailState, RailStatus};
+
+    include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
 }
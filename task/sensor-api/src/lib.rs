@@ -8,6 +8,7 @@
 
 use derive_idol_err::IdolError;
 use drv_i2c_api::ResponseCode;
+use serde::{Deserialize, Serialize};
 use userlib::*;
 
 #[derive(zerocopy::AsBytes, Copy, Clone, Debug, PartialEq)]
@@ -33,6 +34,248 @@ pub enum Reading {
     NoData(NoData),
 }
 
+/// What a sensor measures.
+#[derive(
+    Copy, Clone, Debug, FromPrimitive, PartialEq, Serialize, Deserialize,
+)]
+#[repr(u8)]
+pub enum SensorKind {
+    Temperature = 0,
+    Power = 1,
+    Current = 2,
+    Voltage = 3,
+    Speed = 4,
+    Energy = 5,
+}
+
+impl SensorKind {
+    /// Returns the units in which values for this kind of sensor are posted.
+    pub fn units(&self) -> &'static str {
+        match self {
+            SensorKind::Temperature => "°C",
+            SensorKind::Power => "W",
+            SensorKind::Current => "A",
+            SensorKind::Voltage => "V",
+            SensorKind::Speed => "RPM",
+            SensorKind::Energy => "Wh",
+        }
+    }
+}
+
+/// Static description of a sensor, as generated from the application's I2C
+/// configuration.
+#[derive(Copy, Clone, Debug)]
+pub struct SensorInfo {
+    pub kind: SensorKind,
+    /// Part name of the device, e.g. `tmp117`
+    pub device: &'static str,
+    /// Name of the device (or, for PMBus devices, of the rail), if any
+    pub name: Option<&'static str>,
+    pub refdes: Option<&'static str>,
+    pub description: &'static str,
+    pub controller: u8,
+    pub port: u8,
+    pub mux: Option<u8>,
+    pub segment: Option<u8>,
+    pub address: u8,
+}
+
+/// The fixed-size parts of a [`SensorInfo`], as returned by the
+/// `get_metadata` operation; its strings are retrieved with `get_label`.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SensorMetadata {
+    pub kind: SensorKind,
+    pub controller: u8,
+    pub port: u8,
+    pub mux: Option<u8>,
+    pub segment: Option<u8>,
+    pub address: u8,
+}
+
+impl From<&SensorInfo> for SensorMetadata {
+    fn from(info: &SensorInfo) -> Self {
+        SensorMetadata {
+            kind: info.kind,
+            controller: info.controller,
+            port: info.port,
+            mux: info.mux,
+            segment: info.segment,
+            address: info.address,
+        }
+    }
+}
+
+/// The strings that describe a sensor.
+#[derive(Copy, Clone, Debug, FromPrimitive, PartialEq)]
+#[repr(u8)]
+pub enum Label {
+    Device = 0,
+    Name = 1,
+    Refdes = 2,
+    Description = 3,
+}
+
+impl SensorInfo {
+    /// Returns the given label, or the empty string if the sensor has none.
+    pub fn label(&self, label: Label) -> &'static str {
+        match label {
+            Label::Device => self.device,
+            Label::Name => self.name.unwrap_or(""),
+            Label::Refdes => self.refdes.unwrap_or(""),
+            Label::Description => self.description,
+        }
+    }
+}
+
+/// A reading from a sensor, along with when it was posted.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SensorReading {
+    pub value: f32,
+    /// Time (per `sys_get_timer`) at which the reading was posted
+    pub timestamp: u64,
+    /// Milliseconds elapsed between posting and the request for the reading
+    pub age: u64,
+}
+
+/// Statistics over the values posted for a sensor.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SensorStats {
+    pub min: f32,
+    pub max: f32,
+    pub mean: f32,
+
+    /// Number of values over which the statistics were taken
+    pub count: u32,
+
+    /// Time (per `sys_get_timer`) at which the statistics were last reset
+    pub since: u64,
+}
+
+impl SensorStats {
+    pub const fn new(since: u64) -> Self {
+        SensorStats {
+            min: f32::INFINITY,
+            max: f32::NEG_INFINITY,
+            mean: 0.0,
+            count: 0,
+            since,
+        }
+    }
+
+    /// Accounts for a new value.  The mean is maintained incrementally, so
+    /// as to not lose precision as the count grows.
+    pub fn update(&mut self, value: f32) {
+        self.min = f32::min(self.min, value);
+        self.max = f32::max(self.max, value);
+        self.count = self.count.saturating_add(1);
+        self.mean += (value - self.mean) / self.count as f32;
+    }
+}
+
+/// The alarm state of a sensor, relative to its [`Thresholds`].
+#[derive(
+    Copy, Clone, Debug, FromPrimitive, PartialEq, Serialize, Deserialize,
+)]
+#[repr(u8)]
+pub enum Alarm {
+    Normal = 0,
+    WarningLow = 1,
+    WarningHigh = 2,
+    CriticalLow = 3,
+    CriticalHigh = 4,
+}
+
+impl Alarm {
+    fn severity(self) -> u8 {
+        match self {
+            Alarm::Normal => 0,
+            Alarm::WarningLow | Alarm::WarningHigh => 1,
+            Alarm::CriticalLow | Alarm::CriticalHigh => 2,
+        }
+    }
+}
+
+/// Alarm thresholds for a sensor.  A limit of `None` is never crossed.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Thresholds {
+    pub warning_low: Option<f32>,
+    pub warning_high: Option<f32>,
+    pub critical_low: Option<f32>,
+    pub critical_high: Option<f32>,
+
+    /// How far a value must move back past a threshold before the alarm
+    /// that it raised clears
+    pub hysteresis: f32,
+}
+
+impl Thresholds {
+    pub const NONE: Thresholds = Thresholds {
+        warning_low: None,
+        warning_high: None,
+        critical_low: None,
+        critical_high: None,
+        hysteresis: 0.0,
+    };
+
+    /// Returns the alarm state for `value`, with the high thresholds moved
+    /// down by `high_margin` and the low thresholds moved up by `low_margin`.
+    fn level(&self, value: f32, high_margin: f32, low_margin: f32) -> Alarm {
+        let above = |limit: Option<f32>| match limit {
+            Some(limit) => value >= limit - high_margin,
+            None => false,
+        };
+
+        let below = |limit: Option<f32>| match limit {
+            Some(limit) => value <= limit + low_margin,
+            None => false,
+        };
+
+        if above(self.critical_high) {
+            Alarm::CriticalHigh
+        } else if below(self.critical_low) {
+            Alarm::CriticalLow
+        } else if above(self.warning_high) {
+            Alarm::WarningHigh
+        } else if below(self.warning_low) {
+            Alarm::WarningLow
+        } else {
+            Alarm::Normal
+        }
+    }
+
+    /// Determines the alarm state for a new `value`, given the `current`
+    /// state.  Alarms are raised as soon as a threshold is reached, but are
+    /// only lowered once the value has moved back past it by `hysteresis`.
+    pub fn evaluate(&self, current: Alarm, value: f32) -> Alarm {
+        let raised = self.level(value, 0.0, 0.0);
+
+        if raised.severity() >= current.severity() {
+            return raised;
+        }
+
+        //
+        // Hysteresis only applies to the thresholds on the side that raised
+        // the current alarm; a value heading towards the other side is
+        // judged against those thresholds as they are.
+        //
+        let held = match current {
+            Alarm::WarningHigh | Alarm::CriticalHigh => {
+                self.level(value, self.hysteresis, 0.0)
+            }
+            Alarm::WarningLow | Alarm::CriticalLow => {
+                self.level(value, 0.0, self.hysteresis)
+            }
+            Alarm::Normal => raised,
+        };
+
+        if held.severity() >= current.severity() {
+            current
+        } else {
+            held
+        }
+    }
+}
+
 #[derive(zerocopy::AsBytes, Copy, Clone, Debug, FromPrimitive, PartialEq)]
 #[repr(u8)]
 pub enum NoData {
@@ -65,6 +308,8 @@ pub enum SensorError {
     DeviceUnavailable = 5,
     DeviceTimeout = 6,
     DeviceOff = 7,
+    Stale = 8,
+    TooManySubscribers = 9,
 }
 
 impl From<NoData> for SensorError {
@@ -7,76 +7,357 @@
 #![no_std]
 #![no_main]
 
-use idol_runtime::{NotificationHandler, RequestError};
-use task_sensor_api::{NoData, Reading, SensorError, SensorId};
+use core::sync::atomic::{AtomicBool, Ordering};
+use idol_runtime::{Leased, NotificationHandler, RequestError, W};
+use task_sensor_api::{
+    Alarm, Label, NoData, Reading, SensorError, SensorId, SensorMetadata,
+    SensorReading, SensorStats,
+};
 use userlib::*;
 
-// This is only included to determine the number of sensors
+// This is only included to determine the number of sensors and their
+// thresholds and metadata
 include!(concat!(env!("OUT_DIR"), "/i2c_config.rs"));
 
 use i2c_config::sensors;
 use sensors::NUM_SENSORS;
 
+/// Number of readings that we retain for each sensor (including the most
+/// recent one).
+const HISTORY_DEPTH: usize = 4;
+
+#[derive(Copy, Clone)]
+struct Entry {
+    reading: Reading,
+    timestamp: u64,
+}
+
+/// The most recent readings for a single sensor, as a ring.
+#[derive(Copy, Clone)]
+struct History {
+    entries: [Entry; HISTORY_DEPTH],
+    newest: usize,
+
+    /// Age (in milliseconds) beyond which `get` considers the most recent
+    /// reading to be stale, or 0 if readings never go stale
+    staleness: u32,
+}
+
+impl History {
+    const EMPTY: History = History {
+        entries: [Entry {
+            reading: Reading::Absent,
+            timestamp: 0,
+        }; HISTORY_DEPTH],
+        newest: 0,
+        staleness: 0,
+    };
+
+    fn push(&mut self, reading: Reading, timestamp: u64) {
+        self.newest = (self.newest + 1) % HISTORY_DEPTH;
+        self.entries[self.newest] = Entry { reading, timestamp };
+    }
+
+    /// Returns the `index`th most recent entry, where 0 is the newest.
+    fn entry(&self, index: usize) -> Option<&Entry> {
+        if index < HISTORY_DEPTH {
+            Some(
+                &self.entries
+                    [(self.newest + HISTORY_DEPTH - index) % HISTORY_DEPTH],
+            )
+        } else {
+            None
+        }
+    }
+}
+
+impl Entry {
+    fn reading(&self, now: u64) -> Result<SensorReading, SensorError> {
+        match self.reading {
+            Reading::Absent => Err(SensorError::NoReading),
+            Reading::NoData(nodata) => Err(nodata.into()),
+            Reading::Value(value) => Ok(SensorReading {
+                value,
+                timestamp: self.timestamp,
+                age: now.saturating_sub(self.timestamp),
+            }),
+        }
+    }
+}
+
+/// Maximum number of tasks that can subscribe to alarm state changes.
+const MAX_SUBSCRIBERS: usize = 4;
+
+#[derive(Copy, Clone)]
+struct Subscriber {
+    task: TaskId,
+    notification: u32,
+}
+
 struct ServerImpl {
-    data: [Reading; NUM_SENSORS],
+    data: &'static mut [History; NUM_SENSORS],
+    stats: &'static mut [SensorStats; NUM_SENSORS],
+    alarms: [Alarm; NUM_SENSORS],
+    subscribers: [Option<Subscriber>; MAX_SUBSCRIBERS],
     deadline: u64,
 }
 
 const TIMER_MASK: u32 = 1 << 0;
 const TIMER_INTERVAL: u64 = 1000;
 
+impl ServerImpl {
+    fn history(&self, id: SensorId) -> Result<&History, SensorError> {
+        self.data.get(id.0).ok_or(SensorError::InvalidSensor)
+    }
+
+    fn update(
+        &mut self,
+        id: SensorId,
+        reading: Reading,
+    ) -> Result<(), SensorError> {
+        let now = sys_get_timer().now;
+        let history =
+            self.data.get_mut(id.0).ok_or(SensorError::InvalidSensor)?;
+
+        history.push(reading, now);
+
+        //
+        // Only values move a sensor's alarm state; if we can't read a
+        // sensor, we leave its alarm state as it was.
+        //
+        if let Reading::Value(value) = reading {
+            self.stats[id.0].update(value);
+
+            let alarm = &mut self.alarms[id.0];
+            let next = sensors::THRESHOLDS[id.0].evaluate(*alarm, value);
+
+            if next != *alarm {
+                *alarm = next;
+                self.notify();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Tells each subscriber that an alarm state has changed.
+    fn notify(&self) {
+        for s in self.subscribers.iter().flatten() {
+            sys_post(sys_refresh_task_id(s.task), s.notification);
+        }
+    }
+}
+
 impl idl::InOrderSensorImpl for ServerImpl {
+    fn num_sensors(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<u32, RequestError<SensorError>> {
+        Ok(NUM_SENSORS as u32)
+    }
+
+    fn get_metadata(
+        &mut self,
+        _: &RecvMessage,
+        id: SensorId,
+    ) -> Result<SensorMetadata, RequestError<SensorError>> {
+        let info = sensors::METADATA
+            .get(id.0)
+            .ok_or(SensorError::InvalidSensor)?;
+
+        Ok(info.into())
+    }
+
+    fn get_label(
+        &mut self,
+        _: &RecvMessage,
+        id: SensorId,
+        label: Label,
+        dest: Leased<W, [u8]>,
+    ) -> Result<u32, RequestError<SensorError>> {
+        let info = sensors::METADATA
+            .get(id.0)
+            .ok_or(SensorError::InvalidSensor)?;
+        let label = info.label(label).as_bytes();
+        let len = usize::min(dest.len(), label.len());
+
+        dest.write_range(0..len, &label[..len])
+            .map_err(|_| RequestError::went_away())?;
+
+        Ok(label.len() as u32)
+    }
+
     fn get(
         &mut self,
         _: &RecvMessage,
         id: SensorId,
     ) -> Result<f32, RequestError<SensorError>> {
-        let index = id.0;
-
-        if index < NUM_SENSORS {
-            match self.data[index] {
-                Reading::Absent => Err(SensorError::NoReading.into()),
-                Reading::NoData(nodata) => {
-                    let err: SensorError = nodata.into();
-                    Err(err.into())
-                }
-                Reading::Value(reading) => Ok(reading),
-            }
+        let history = self.history(id)?;
+        let reading =
+            history.entries[history.newest].reading(sys_get_timer().now)?;
+
+        if history.staleness != 0 && reading.age > history.staleness as u64 {
+            Err(SensorError::Stale.into())
         } else {
-            Err(SensorError::InvalidSensor.into())
+            Ok(reading.value)
         }
     }
 
-    fn post(
+    fn get_reading(
         &mut self,
         _: &RecvMessage,
         id: SensorId,
-        value: f32,
+    ) -> Result<SensorReading, RequestError<SensorError>> {
+        let history = self.history(id)?;
+        Ok(history.entries[history.newest].reading(sys_get_timer().now)?)
+    }
+
+    fn get_history(
+        &mut self,
+        _: &RecvMessage,
+        id: SensorId,
+        index: u32,
+    ) -> Result<SensorReading, RequestError<SensorError>> {
+        let entry = self
+            .history(id)?
+            .entry(index as usize)
+            .ok_or(SensorError::NoReading)?;
+
+        Ok(entry.reading(sys_get_timer().now)?)
+    }
+
+    fn set_staleness(
+        &mut self,
+        _: &RecvMessage,
+        id: SensorId,
+        threshold: u32,
     ) -> Result<(), RequestError<SensorError>> {
-        let index = id.0;
+        let history =
+            self.data.get_mut(id.0).ok_or(SensorError::InvalidSensor)?;
 
-        if index < NUM_SENSORS {
-            self.data[index] = Reading::Value(value);
-            Ok(())
+        history.staleness = threshold;
+        Ok(())
+    }
+
+    fn get_stats(
+        &mut self,
+        _: &RecvMessage,
+        id: SensorId,
+    ) -> Result<SensorStats, RequestError<SensorError>> {
+        let stats = self.stats.get(id.0).ok_or(SensorError::InvalidSensor)?;
+
+        if stats.count == 0 {
+            Err(SensorError::NoReading.into())
         } else {
-            Err(SensorError::InvalidSensor.into())
+            Ok(*stats)
         }
     }
 
-    fn nodata(
+    fn reset_stats(
         &mut self,
         _: &RecvMessage,
         id: SensorId,
-        nodata: NoData,
     ) -> Result<(), RequestError<SensorError>> {
-        let index = id.0;
+        let now = sys_get_timer().now;
+        let stats =
+            self.stats.get_mut(id.0).ok_or(SensorError::InvalidSensor)?;
 
-        if index < NUM_SENSORS {
-            self.data[index] = Reading::NoData(nodata);
-            Ok(())
-        } else {
-            Err(SensorError::InvalidSensor.into())
+        *stats = SensorStats::new(now);
+        Ok(())
+    }
+
+    fn get_alarm(
+        &mut self,
+        _: &RecvMessage,
+        id: SensorId,
+    ) -> Result<Alarm, RequestError<SensorError>> {
+        let alarm = self.alarms.get(id.0).ok_or(SensorError::InvalidSensor)?;
+        Ok(*alarm)
+    }
+
+    fn get_alarms(
+        &mut self,
+        _: &RecvMessage,
+        states: Leased<W, [u8]>,
+    ) -> Result<u32, RequestError<SensorError>> {
+        let mut buf = [0u8; NUM_SENSORS];
+
+        for (byte, alarm) in buf.iter_mut().zip(self.alarms.iter()) {
+            *byte = *alarm as u8;
+        }
+
+        let len = usize::min(states.len(), NUM_SENSORS);
+
+        states
+            .write_range(0..len, &buf[..len])
+            .map_err(|_| RequestError::went_away())?;
+
+        Ok(NUM_SENSORS as u32)
+    }
+
+    fn subscribe(
+        &mut self,
+        msg: &RecvMessage,
+        notification: u32,
+    ) -> Result<(), RequestError<SensorError>> {
+        let index = msg.sender.index();
+
+        //
+        // A task that subscribes again (e.g., after restarting) replaces its
+        // existing subscription.
+        //
+        let slot = match self
+            .subscribers
+            .iter()
+            .position(|s| matches!(s, Some(s) if s.task.index() == index))
+        {
+            Some(slot) => slot,
+            None => self
+                .subscribers
+                .iter()
+                .position(|s| s.is_none())
+                .ok_or(SensorError::TooManySubscribers)?,
+        };
+
+        self.subscribers[slot] = Some(Subscriber {
+            task: msg.sender,
+            notification,
+        });
+
+        Ok(())
+    }
+
+    fn unsubscribe(
+        &mut self,
+        msg: &RecvMessage,
+    ) -> Result<(), RequestError<SensorError>> {
+        let index = msg.sender.index();
+
+        for s in self.subscribers.iter_mut() {
+            if matches!(s, Some(sub) if sub.task.index() == index) {
+                *s = None;
+            }
         }
+
+        Ok(())
+    }
+
+    fn post(
+        &mut self,
+        _: &RecvMessage,
+        id: SensorId,
+        value: f32,
+    ) -> Result<(), RequestError<SensorError>> {
+        Ok(self.update(id, Reading::Value(value))?)
+    }
+
+    fn nodata(
+        &mut self,
+        _: &RecvMessage,
+        id: SensorId,
+        nodata: NoData,
+    ) -> Result<(), RequestError<SensorError>> {
+        Ok(self.update(id, Reading::NoData(nodata))?)
     }
 }
 
@@ -91,6 +372,30 @@ impl NotificationHandler for ServerImpl {
     }
 }
 
+///
+/// Grabs references to the per-sensor history and statistics, which are too
+/// large to keep on our stack.  Can only be called once.
+///
+fn claim_statics() -> (
+    &'static mut [History; NUM_SENSORS],
+    &'static mut [SensorStats; NUM_SENSORS],
+) {
+    static TAKEN: AtomicBool = AtomicBool::new(false);
+    if TAKEN.swap(true, Ordering::Relaxed) {
+        panic!()
+    }
+
+    static mut DATA: [History; NUM_SENSORS] = [History::EMPTY; NUM_SENSORS];
+    static mut STATS: [SensorStats; NUM_SENSORS] =
+        [SensorStats::new(0); NUM_SENSORS];
+
+    // Safety: unsafe because of references to mutable statics; safe because
+    // the AtomicBool swap above, combined with the lexical scoping of the
+    // statics, means that these references can't be aliased by any other
+    // reference in the program.
+    unsafe { (&mut DATA, &mut STATS) }
+}
+
 #[export_name = "main"]
 fn main() -> ! {
     let deadline = sys_get_timer().now;
@@ -100,8 +405,17 @@ fn main() -> ! {
     //
     sys_set_timer(Some(deadline), TIMER_MASK);
 
+    let (data, stats) = claim_statics();
+
+    for s in stats.iter_mut() {
+        *s = SensorStats::new(deadline);
+    }
+
     let mut server = ServerImpl {
-        data: [Reading::Absent; NUM_SENSORS],
+        data,
+        stats,
+        alarms: [Alarm::Normal; NUM_SENSORS],
+        subscribers: [None; MAX_SUBSCRIBERS],
         deadline,
     };
 
@@ -113,7 +427,10 @@ fn main() -> ! {
 }
 
 mod idl {
-    use super::{NoData, SensorError, SensorId};
+    use super::{
+        Alarm, Label, NoData, SensorError, SensorId, SensorMetadata,
+        SensorReading, SensorStats,
+    };
 
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
 }
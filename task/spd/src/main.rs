@@ -24,6 +24,7 @@
 use core::cell::Cell;
 use core::cell::RefCell;
 use drv_i2c_api::*;
+use drv_i2c_mux::ltc4306::target as ltc4306;
 use drv_stm32h7_i2c::*;
 use drv_stm32xx_sys_api::*;
 use ringbuf::*;
@@ -32,8 +33,6 @@ use userlib::*;
 task_slot!(SYS, sys);
 task_slot!(I2C, i2c_driver);
 
-mod ltc4306;
-
 fn configure_pins(pins: &[I2cPin]) {
     let sys = SYS.get_task_id();
     let sys = Sys::from(sys);
@@ -7,6 +7,7 @@
 #![no_std]
 
 use derive_idol_err::IdolError;
+use serde::{Deserialize, Serialize};
 use userlib::*;
 
 #[derive(Copy, Clone, Debug, FromPrimitive, PartialEq, IdolError)]
@@ -14,6 +15,39 @@ pub enum ThermalError {
     InvalidFan = 1,
     InvalidPWM = 2,
     DeviceError = 3,
+    NotInManualMode = 4,
+}
+
+/// The health of a fan, as judged from its tach and its controller.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum FanStatus {
+    /// The fan hasn't been read yet
+    Unknown,
+    Ok,
+    /// The fan is turning more slowly than its duty cycle would suggest
+    Slow,
+    /// The fan isn't turning, despite being driven
+    Stalled,
+    /// The fan controller has flagged a fault on the fan
+    Faulted,
+    /// The fan's tach can't be read
+    Unreadable,
+}
+
+impl FanStatus {
+    /// Returns true if the fan can't be relied upon to cool its zone.
+    pub fn is_failed(&self) -> bool {
+        !matches!(self, FanStatus::Unknown | FanStatus::Ok)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FanState {
+    pub status: FanStatus,
+    /// Most recent speed, if the fan could be read
+    pub rpm: Option<u16>,
+    /// Duty cycle (in percent) that the fan was last set to
+    pub pwm: u8,
 }
 
 include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
@@ -2,10 +2,25 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io::Write;
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     build_util::expose_target_board();
     build_i2c::codegen(build_i2c::Disposition::Sensors)?;
 
+    //
+    // A board without a thermal configuration has no zones, and therefore
+    // no fans under automatic control.
+    //
+    let thermal = build_util::task_maybe_config::<TaskConfig>()?
+        .and_then(|c| c.thermal)
+        .unwrap_or_default();
+    let global_config = build_util::config::<GlobalConfig>()?;
+    check_thermal_config(&thermal, &global_config.i2c)?;
+    generate_thermal_config(&thermal)?;
+
     idol::server::build_server_support(
         "../../idl/thermal.idol",
         "server_stub.rs",
@@ -14,3 +29,237 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+//
+// Thermal configuration, from `[tasks.thermal.config.thermal]`.  Zones are
+// named by the thermal inputs in the I2C configuration; each zone names the
+// fans (numbered across fan controllers, in order) that cool it, and how
+// their duty cycle is derived from the zone's temperature (and, optionally,
+// that of the CPU).  As with the I2C configuration, toml-rs doesn't deal well
+// with enums, so controls are flattened: a control is either a PID loop
+// (`target`, `kp`, `ki`, `kd`) or a piecewise-linear `curve` of
+// `[temperature, pwm]` points.
+//
+#[derive(Deserialize)]
+struct TaskConfig {
+    thermal: Option<ThermalConfig>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ThermalConfig {
+    /// maximum change in duty cycle (in percent) per control interval
+    slew: u8,
+
+    /// speed of the fans at full duty cycle, if known; fans that turn much
+    /// more slowly than their duty cycle calls for are reported as slow
+    max_rpm: Option<u16>,
+
+    zones: BTreeMap<String, ZoneConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ZoneConfig {
+    fans: Vec<u8>,
+    control: ControlConfig,
+    cpu: Option<ControlConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ControlConfig {
+    target: Option<f32>,
+    kp: Option<f32>,
+    ki: Option<f32>,
+    kd: Option<f32>,
+    curve: Option<Vec<(f32, u8)>>,
+}
+
+//
+// The parts of the I2C configuration that describe thermal inputs and fan
+// controllers, against which we check our own configuration; see
+// `build/i2c` for the full definition.
+//
+#[derive(Deserialize)]
+struct GlobalConfig {
+    i2c: I2cConfig,
+}
+
+#[derive(Deserialize)]
+struct I2cConfig {
+    #[serde(default)]
+    devices: Vec<I2cDevice>,
+}
+
+#[derive(Deserialize)]
+struct I2cDevice {
+    device: String,
+    sensors: Option<I2cSensors>,
+    thermal: Option<I2cThermal>,
+}
+
+#[derive(Deserialize)]
+struct I2cSensors {
+    #[serde(default)]
+    speed: usize,
+}
+
+#[derive(Deserialize)]
+struct I2cThermal {
+    zone: String,
+    #[serde(default)]
+    cpu: bool,
+}
+
+fn check_thermal_config(
+    config: &ThermalConfig,
+    i2c: &I2cConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let nfans: usize = i2c
+        .devices
+        .iter()
+        .filter(|d| d.device == "max31790")
+        .filter_map(|d| d.sensors.as_ref())
+        .map(|s| s.speed)
+        .sum();
+
+    for d in &i2c.devices {
+        if let Some(thermal) = &d.thermal {
+            let zone = config.zones.get(&thermal.zone).ok_or_else(|| {
+                format!("{} is in unknown zone {}", d.device, thermal.zone)
+            })?;
+
+            if thermal.cpu && zone.cpu.is_none() {
+                return Err(format!(
+                    "zone {} has a CPU input but no CPU control",
+                    thermal.zone
+                )
+                .into());
+            }
+        }
+    }
+
+    for (name, zone) in &config.zones {
+        let inputs = i2c
+            .devices
+            .iter()
+            .filter_map(|d| d.thermal.as_ref())
+            .filter(|t| &t.zone == name);
+
+        //
+        // A zone without inputs (or with a CPU control, but no CPU) would
+        // spend its life running its fans at full speed.
+        //
+        if !inputs.clone().any(|t| !t.cpu) {
+            return Err(
+                format!("zone {} has no temperature inputs", name).into()
+            );
+        }
+
+        if zone.cpu.is_some() && !inputs.clone().any(|t| t.cpu) {
+            return Err(
+                format!("zone {} has a CPU control but no CPU", name).into()
+            );
+        }
+
+        if let Some(fan) = zone.fans.iter().find(|&&f| f as usize >= nfans) {
+            return Err(format!(
+                "zone {} has fan {}, but there are {} fans",
+                name, fan, nfans
+            )
+            .into());
+        }
+    }
+
+    Ok(())
+}
+
+impl ControlConfig {
+    fn generate(&self, zone: &str) -> Result<String, String> {
+        match (self.target, &self.curve) {
+            (Some(target), None) => Ok(format!(
+                "Control::Pid(Pid {{ target: {:?}, kp: {:?}, ki: {:?}, kd: {:?} }})",
+                target,
+                self.kp.unwrap_or(0.0),
+                self.ki.unwrap_or(0.0),
+                self.kd.unwrap_or(0.0),
+            )),
+            (None, Some(curve)) => {
+                if self.kp.is_some() || self.ki.is_some() || self.kd.is_some()
+                {
+                    return Err(format!("zone {}: curve has PID gains", zone));
+                }
+
+                if curve.is_empty() {
+                    return Err(format!("zone {}: curve is empty", zone));
+                }
+
+                if curve.windows(2).any(|w| w[0].0 >= w[1].0) {
+                    return Err(format!(
+                        "zone {}: curve temperatures must increase",
+                        zone
+                    ));
+                }
+
+                if curve.iter().any(|&(_, pwm)| pwm > 100) {
+                    return Err(format!("zone {}: curve exceeds 100%", zone));
+                }
+
+                let points = curve
+                    .iter()
+                    .map(|(temp, pwm)| format!("({:?}, {})", temp, pwm))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+
+                Ok(format!("Control::Curve(&[{}])", points))
+            }
+            _ => Err(format!(
+                "zone {}: control must have exactly one of target or curve",
+                zone
+            )),
+        }
+    }
+}
+
+fn generate_thermal_config(
+    config: &ThermalConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let out_dir = std::env::var("OUT_DIR")?;
+    let dest_path = std::path::Path::new(&out_dir).join("thermal_config.rs");
+    let mut out = std::fs::File::create(&dest_path)?;
+
+    writeln!(out, "pub const SLEW: u8 = {};", config.slew)?;
+    writeln!(
+        out,
+        "pub const MAX_RPM: Option<u16> = {:?};",
+        config.max_rpm
+    )?;
+    writeln!(out, "pub const NUM_ZONES: usize = {};", config.zones.len())?;
+    writeln!(out, "pub const ZONES: [ZoneConfig; NUM_ZONES] = [")?;
+
+    for (name, zone) in &config.zones {
+        if zone.fans.is_empty() {
+            return Err(format!("zone {} has no fans", name).into());
+        }
+
+        let cpu = match &zone.cpu {
+            Some(cpu) => format!("Some({})", cpu.generate(name)?),
+            None => "None".to_string(),
+        };
+
+        writeln!(
+            out,
+            "    ZoneConfig {{ name: {:?}, fans: &{:?}, control: {}, \
+            cpu: {} }},",
+            name,
+            zone.fans,
+            zone.control.generate(name)?,
+            cpu,
+        )?;
+    }
+
+    writeln!(out, "];")?;
+
+    Ok(())
+}
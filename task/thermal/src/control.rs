@@ -0,0 +1,130 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Fan control
+//!
+//! Each zone's fans are driven by a control -- either a PID loop or a
+//! piecewise-linear fan curve -- that maps the zone's temperature to a duty
+//! cycle.  The configuration of zones is generated from the task's
+//! configuration in the application TOML.
+
+/// Duty cycle (in percent) that we run fans at when we can't trust our
+/// temperature readings.
+pub const FAILSAFE_PWM: u8 = 100;
+
+/// Interval between control iterations, in seconds.
+const DT: f32 = super::TIMER_INTERVAL as f32 / 1000.0;
+
+pub struct Pid {
+    /// Temperature (in degrees C) that the loop tries to maintain
+    pub target: f32,
+    pub kp: f32,
+    pub ki: f32,
+    pub kd: f32,
+}
+
+pub enum Control {
+    Pid(Pid),
+
+    /// Points of (temperature, duty cycle), in increasing temperature
+    Curve(&'static [(f32, u8)]),
+}
+
+pub struct ZoneConfig {
+    /// Name of the zone, as used by thermal inputs in the I2C configuration
+    pub name: &'static str,
+    pub fans: &'static [u8],
+    pub control: Control,
+
+    /// Control for the CPU temperature, if this zone cools the CPU
+    pub cpu: Option<Control>,
+}
+
+/// State of a single control across iterations.
+#[derive(Copy, Clone)]
+pub struct ControlState {
+    integral: f32,
+    last_error: Option<f32>,
+}
+
+impl ControlState {
+    pub const INITIAL: ControlState = ControlState {
+        integral: 0.0,
+        last_error: None,
+    };
+}
+
+impl Control {
+    /// Returns the duty cycle that this control demands for `temp`.
+    pub fn demand(&self, state: &mut ControlState, temp: f32) -> u8 {
+        let pwm = match self {
+            Control::Pid(pid) => {
+                let error = temp - pid.target;
+                let derivative = match state.last_error {
+                    Some(last) => (error - last) / DT,
+                    None => 0.0,
+                };
+
+                state.last_error = Some(error);
+
+                //
+                // To keep the integral term from winding up while the output
+                // is saturated, clamp it to what could possibly matter.
+                //
+                if pid.ki != 0.0 {
+                    let limit = 100.0 / pid.ki;
+                    state.integral =
+                        clamp(state.integral + error * DT, -limit, limit);
+                }
+
+                pid.kp * error + pid.ki * state.integral + pid.kd * derivative
+            }
+
+            Control::Curve(points) => interpolate(points, temp),
+        };
+
+        clamp(pwm, 0.0, 100.0) as u8
+    }
+}
+
+fn clamp(v: f32, min: f32, max: f32) -> f32 {
+    if v < min {
+        min
+    } else if v > max {
+        max
+    } else {
+        v
+    }
+}
+
+fn interpolate(points: &[(f32, u8)], temp: f32) -> f32 {
+    let (first, last) = match (points.first(), points.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return FAILSAFE_PWM as f32,
+    };
+
+    if temp <= first.0 {
+        return first.1 as f32;
+    }
+
+    for w in points.windows(2) {
+        let ((t0, p0), (t1, p1)) = (w[0], w[1]);
+
+        if temp <= t1 {
+            let (p0, p1) = (p0 as f32, p1 as f32);
+            return p0 + (p1 - p0) * (temp - t0) / (t1 - t0);
+        }
+    }
+
+    last.1 as f32
+}
+
+/// Moves from `current` towards `demand`, by no more than `slew`.
+pub fn slew(current: u8, demand: u8, slew: u8) -> u8 {
+    if demand > current {
+        current.saturating_add(slew).min(demand)
+    } else {
+        current.saturating_sub(slew).max(demand)
+    }
+}
@@ -0,0 +1,105 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Fan health
+//!
+//! A fan is judged by its controller's fault status, by whether its tach can
+//! be read, and by how fast it's turning relative to the duty cycle that it's
+//! being driven at.  Because a fan takes time to spin up or down after its
+//! duty cycle changes, a fan's status only changes once it has been observed
+//! consistently over several readings.
+
+use task_thermal_api::{FanState, FanStatus};
+
+/// Duty cycle (in percent) at or above which a fan should be turning.
+const STALL_PWM: u8 = 20;
+
+/// Speed below which we consider a driven fan to have stalled.
+const STALL_RPM: u16 = 100;
+
+/// Number of consecutive readings that must agree before a fan's status
+/// changes.
+const DEBOUNCE: u8 = 5;
+
+#[derive(Copy, Clone)]
+pub struct FanHealth {
+    /// Duty cycle that the fan was last successfully set to
+    pwm: u8,
+    rpm: Option<u16>,
+    status: FanStatus,
+
+    /// Status from the most recent readings, and how many in a row agree
+    pending: FanStatus,
+    count: u8,
+}
+
+impl FanHealth {
+    pub const INITIAL: FanHealth = FanHealth {
+        pwm: 0,
+        rpm: None,
+        status: FanStatus::Unknown,
+        pending: FanStatus::Unknown,
+        count: 0,
+    };
+
+    pub fn set_pwm(&mut self, pwm: u8) {
+        self.pwm = pwm;
+    }
+
+    pub fn status(&self) -> FanStatus {
+        self.status
+    }
+
+    pub fn state(&self) -> FanState {
+        FanState {
+            status: self.status,
+            rpm: self.rpm,
+            pwm: self.pwm,
+        }
+    }
+
+    /// Updates the fan's health from a reading of its speed (`None` if it
+    /// couldn't be read) and its controller's fault status, returning true
+    /// if the fan's status has changed.  If `max_rpm` is known, a fan that
+    /// turns at less than half of the speed that its duty cycle calls for is
+    /// considered slow.
+    pub fn update(
+        &mut self,
+        rpm: Option<u16>,
+        faulted: bool,
+        max_rpm: Option<u16>,
+    ) -> bool {
+        self.rpm = rpm;
+
+        let observed = match rpm {
+            None => FanStatus::Unreadable,
+            Some(_) if faulted => FanStatus::Faulted,
+            Some(_) if self.pwm < STALL_PWM => FanStatus::Ok,
+            Some(rpm) if rpm < STALL_RPM => FanStatus::Stalled,
+            Some(rpm) => match max_rpm {
+                Some(max)
+                    if (rpm as u32) * 200
+                        < (max as u32) * (self.pwm as u32) =>
+                {
+                    FanStatus::Slow
+                }
+                _ => FanStatus::Ok,
+            },
+        };
+
+        if observed == self.pending {
+            self.count = self.count.saturating_add(1);
+        } else {
+            self.pending = observed;
+            self.count = 1;
+        }
+
+        if self.count >= DEBOUNCE && self.pending != self.status {
+            self.status = self.pending;
+            true
+        } else {
+            false
+        }
+    }
+}
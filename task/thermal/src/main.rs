@@ -4,9 +4,12 @@
 
 //! Thermal loop
 //!
-//! This is a primordial thermal loop, which will ultimately reading temperature
-//! sensors and control fan duty cycles to actively manage thermals.  Right now,
-//! though it is merely reading every fan and temp sensor that it can find...
+//! This task reads every fan and temperature sensor that it can find, posting
+//! the readings to the sensor task, and -- unless it has been put into manual
+//! mode -- drives the fans in each zone from the zone's temperature.  If any
+//! temperature that a zone depends on can't be read, that zone's fans are run
+//! at full speed until it can be.  Similarly, if any fan in a zone has failed,
+//! the remaining fans in the zone are run at full speed to compensate.
 //!
 
 #![no_std]
@@ -18,8 +21,9 @@ use drv_i2c_devices::sbtsi::*;
 use drv_i2c_devices::tmp116::*;
 use drv_i2c_devices::TempSensor;
 use idol_runtime::{NotificationHandler, RequestError};
+use ringbuf::*;
 use task_sensor_api as sensor_api;
-use task_thermal_api::ThermalError;
+use task_thermal_api::{FanState, FanStatus, ThermalError};
 use userlib::units::*;
 use userlib::*;
 
@@ -29,24 +33,35 @@ task_slot!(I2C, i2c_driver);
 task_slot!(SENSOR, sensor);
 
 include!(concat!(env!("OUT_DIR"), "/i2c_config.rs"));
-use i2c_config::devices;
-use i2c_config::sensors;
+use i2c_config::thermal;
 
-enum Zone {
-    East,
-    Central,
-    West,
+mod control;
+mod health;
+
+use control::{ControlState, FAILSAFE_PWM};
+use health::FanHealth;
+
+mod config {
+    use super::control::{Control, Pid, ZoneConfig};
+
+    include!(concat!(env!("OUT_DIR"), "/thermal_config.rs"));
 }
 
 enum Device {
-    North(Zone, Tmp116),
-    South(Zone, Tmp116),
-    CPU(SbTsi),
+    Tmp117(Tmp116),
+    Sbtsi(SbTsi),
 }
 
+/// A temperature input, as described by the I2C configuration.
 struct Sensor {
     device: Device,
     id: SensorId,
+
+    /// Index of the zone (in `config::ZONES`) that this sensor feeds
+    zone: usize,
+
+    /// Whether this sensor measures a CPU (rather than the zone itself)
+    cpu: bool,
 }
 
 fn temp_read<E, T: TempSensor<E>>(
@@ -67,74 +82,119 @@ where
 impl Sensor {
     fn read_temp(&mut self) -> Result<Celsius, ResponseCode> {
         match &mut self.device {
-            Device::North(_, dev) | Device::South(_, dev) => temp_read(dev),
-            Device::CPU(dev) => temp_read(dev),
+            Device::Tmp117(dev) => temp_read(dev),
+            Device::Sbtsi(dev) => temp_read(dev),
         }
     }
 }
 
-const NUM_TEMPERATURE_SENSORS: usize = sensors::NUM_TMP117_TEMPERATURE_SENSORS
-    + sensors::NUM_SBTSI_TEMPERATURE_SENSORS;
-
-fn temperature_sensors() -> [Sensor; NUM_TEMPERATURE_SENSORS] {
-    let task = I2C.get_task_id();
+impl From<&thermal::Input> for Sensor {
+    fn from(input: &thermal::Input) -> Self {
+        let device = match input.part {
+            thermal::Part::Tmp117 => Device::Tmp117(Tmp116::new(&input.device)),
+            thermal::Part::Sbtsi => Device::Sbtsi(SbTsi::new(&input.device)),
+        };
+
+        //
+        // Our build script checks that every zone named in the I2C
+        // configuration is configured for this task.
+        //
+        let zone = config::ZONES
+            .iter()
+            .position(|z| z.name == input.zone)
+            .unwrap();
 
-    [
-        // North and south zones are inverted with respect to one another;
-        // see Gimlet issue #1302 for details.
-        Sensor {
-            device: Device::North(
-                Zone::East,
-                Tmp116::new(&devices::tmp117_northeast(task)),
-            ),
-            id: sensors::TMP117_NORTHEAST_TEMPERATURE_SENSOR,
-        },
         Sensor {
-            device: Device::North(
-                Zone::Central,
-                Tmp116::new(&devices::tmp117_north(task)),
-            ),
-            id: sensors::TMP117_NORTH_TEMPERATURE_SENSOR,
-        },
-        Sensor {
-            device: Device::North(
-                Zone::West,
-                Tmp116::new(&devices::tmp117_northwest(task)),
-            ),
-            id: sensors::TMP117_NORTHWEST_TEMPERATURE_SENSOR,
-        },
-        Sensor {
-            device: Device::South(
-                Zone::East,
-                Tmp116::new(&devices::tmp117_southeast(task)),
-            ),
-            id: sensors::TMP117_SOUTHEAST_TEMPERATURE_SENSOR,
-        },
-        Sensor {
-            device: Device::South(
-                Zone::Central,
-                Tmp116::new(&devices::tmp117_south(task)),
-            ),
-            id: sensors::TMP117_SOUTH_TEMPERATURE_SENSOR,
-        },
-        Sensor {
-            device: Device::South(
-                Zone::West,
-                Tmp116::new(&devices::tmp117_southwest(task)),
-            ),
-            id: sensors::TMP117_SOUTHWEST_TEMPERATURE_SENSOR,
-        },
-        Sensor {
-            device: Device::CPU(SbTsi::new(&devices::sbtsi(task)[0])),
-            id: sensors::SBTSI_TEMPERATURE_SENSOR,
-        },
-    ]
+            device,
+            id: input.sensor,
+            zone,
+            cpu: input.cpu,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Mode {
+    /// Fans are driven by the control loop
+    Auto,
+    /// Fans are driven via `set_fan_pwm`
+    Manual,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Trace {
+    None,
+    Mode(Mode),
+    Failsafe(u8),
+    Compensate(u8),
+    Pwm(u8, u8),
+    SetPwmFailed(u8, ResponseCode),
+    FanStatus(u8, FanStatus),
+}
+
+ringbuf!(Trace, 32, Trace::None);
+
+/// Control state for a zone.
+#[derive(Copy, Clone)]
+struct ZoneState {
+    pwm: u8,
+    control: ControlState,
+    cpu: ControlState,
+}
+
+impl ZoneState {
+    /// Initial state: fans at full speed, from which the control loop will
+    /// slew them down.
+    const INITIAL: ZoneState = ZoneState {
+        pwm: FAILSAFE_PWM,
+        control: ControlState::INITIAL,
+        cpu: ControlState::INITIAL,
+    };
+}
+
+/// The temperature of a zone (or of the CPU) as of one control iteration.
+#[derive(Copy, Clone)]
+enum Temperature {
+    /// No sensors have been read
+    Unknown,
+    /// The hottest reading
+    Value(f32),
+    /// At least one sensor couldn't be read
+    Failed,
+}
+
+impl Temperature {
+    fn update(&mut self, reading: &Result<Celsius, ResponseCode>) {
+        *self = match (*self, reading) {
+            (Temperature::Failed, _) | (_, Err(_)) => Temperature::Failed,
+            (Temperature::Unknown, Ok(t)) => Temperature::Value(t.0),
+            (Temperature::Value(v), Ok(t)) => {
+                Temperature::Value(if t.0 > v { t.0 } else { v })
+            }
+        };
+    }
+
+    fn value(&self) -> Option<f32> {
+        match self {
+            Temperature::Value(v) => Some(*v),
+            _ => None,
+        }
+    }
+}
+
+/// A fan controller and the speed sensors for its fans.
+struct FanController {
+    device: Max31790,
+    fans: &'static [SensorId],
 }
 
 struct ServerImpl {
     sensor: sensor_api::Sensor,
-    sensors: [Sensor; NUM_TEMPERATURE_SENSORS],
-    fctrl: Max31790,
+    sensors: [Sensor; thermal::NUM_INPUTS],
+    fctrls: [FanController; thermal::NUM_FAN_CONTROLLERS],
+    fans: [FanHealth; thermal::NUM_FANS],
+    mode: Mode,
+    zones: [ZoneState; config::NUM_ZONES],
     deadline: u64,
 }
 
@@ -142,22 +202,165 @@ const TIMER_MASK: u32 = 1 << 0;
 const TIMER_INTERVAL: u64 = 1000;
 
 impl ServerImpl {
-    fn read_fans(&self) {
-        let ids = &sensors::MAX31790_SPEED_SENSORS;
+    /// Finds the controller (and the fan on that controller) for a fan
+    /// index, where fans are numbered across controllers in order.
+    fn fan(&self, index: u8) -> Option<(&Max31790, Fan)> {
+        let mut base = 0;
 
-        for ndx in 0..MAX_FANS {
-            let fan = Fan::from(ndx);
+        for fctrl in &self.fctrls {
+            let nfans = fctrl.fans.len();
+
+            if (index as usize) < base + nfans {
+                return Some((&fctrl.device, Fan::from(index - base as u8)));
+            }
+
+            base += nfans;
+        }
+
+        None
+    }
+
+    fn set_pwm(&mut self, index: u8, pwm: u8) -> Result<(), ResponseCode> {
+        let r = match self.fan(index) {
+            Some((device, fan)) => device.set_pwm(fan, PWMDuty(pwm)),
+            None => Err(ResponseCode::BadArg),
+        };
+
+        match r {
+            Ok(_) => self.fans[index as usize].set_pwm(pwm),
+            Err(code) => ringbuf_entry!(Trace::SetPwmFailed(index, code)),
+        }
 
-            match self.fctrl.fan_rpm(fan) {
+        r
+    }
+
+    /// Reads every temperature sensor, posting the results, and returns the
+    /// temperature of each zone and of the CPU(s) in each zone.
+    fn read_temps(
+        &mut self,
+    ) -> (
+        [Temperature; config::NUM_ZONES],
+        [Temperature; config::NUM_ZONES],
+    ) {
+        let mut zones = [Temperature::Unknown; config::NUM_ZONES];
+        let mut cpus = [Temperature::Unknown; config::NUM_ZONES];
+
+        for s in &mut self.sensors {
+            let reading = s.read_temp();
+
+            match reading {
                 Ok(reading) => {
-                    self.sensor
-                        .post(ids[ndx as usize], reading.0.into())
-                        .unwrap();
+                    self.sensor.post(s.id, reading.0).unwrap();
+                }
+                Err(e) => self.sensor.nodata(s.id, e.into()).unwrap(),
+            };
+
+            if s.cpu {
+                cpus[s.zone].update(&reading);
+            } else {
+                zones[s.zone].update(&reading);
+            }
+        }
+
+        (zones, cpus)
+    }
+
+    /// Runs one iteration of the control loop.
+    fn control(
+        &mut self,
+        zones: &[Temperature; config::NUM_ZONES],
+        cpus: &[Temperature; config::NUM_ZONES],
+    ) {
+        if self.mode != Mode::Auto {
+            return;
+        }
+
+        for (index, z) in config::ZONES.iter().enumerate() {
+            let failed = z
+                .fans
+                .iter()
+                .any(|&fan| self.fans[fan as usize].status().is_failed());
+
+            let state = &mut self.zones[index];
+
+            let demand = match (zones[index].value(), &z.cpu) {
+                (Some(temp), None) => {
+                    Some(z.control.demand(&mut state.control, temp))
                 }
-                Err(e) => {
-                    self.sensor.nodata(ids[ndx as usize], e.into()).unwrap()
+                (Some(temp), Some(control)) => cpus[index].value().map(|cpu| {
+                    let zone = z.control.demand(&mut state.control, temp);
+                    let cpu = control.demand(&mut state.cpu, cpu);
+                    zone.max(cpu)
+                }),
+                (None, _) => None,
+            };
+
+            let pwm = match demand {
+                Some(_) if failed => {
+                    ringbuf_entry!(Trace::Compensate(index as u8));
+                    FAILSAFE_PWM
+                }
+                Some(demand) => control::slew(state.pwm, demand, config::SLEW),
+                None => {
+                    ringbuf_entry!(Trace::Failsafe(index as u8));
+                    FAILSAFE_PWM
+                }
+            };
+
+            if pwm != state.pwm {
+                ringbuf_entry!(Trace::Pwm(index as u8, pwm));
+            }
+
+            state.pwm = pwm;
+
+            //
+            // We set every fan on every iteration (rather than only on a
+            // change) to correct any fan that didn't take a prior setting.
+            //
+            for &fan in z.fans {
+                let _ = self.set_pwm(fan, pwm);
+            }
+        }
+    }
+
+    /// Reads every fan, posting the results, and updates each fan's health.
+    fn read_fans(&mut self) {
+        let mut base = 0;
+
+        for fctrl in &self.fctrls {
+            //
+            // If we can't read the fault status, we'll almost certainly
+            // fail to read the fans themselves, and mark them unreadable.
+            //
+            let faults = fctrl.device.fan_faults().unwrap_or(0);
+
+            for (ndx, &id) in fctrl.fans.iter().enumerate() {
+                let fan = Fan::from(ndx as u8);
+
+                let rpm = match fctrl.device.fan_rpm(fan) {
+                    Ok(reading) => {
+                        self.sensor.post(id, reading.0.into()).unwrap();
+                        Some(reading.0)
+                    }
+                    Err(e) => {
+                        self.sensor.nodata(id, e.into()).unwrap();
+                        None
+                    }
+                };
+
+                let index = base + ndx;
+                let health = &mut self.fans[index];
+
+                if health.update(rpm, faults & (1 << ndx) != 0, config::MAX_RPM)
+                {
+                    ringbuf_entry!(Trace::FanStatus(
+                        index as u8,
+                        health.status()
+                    ));
                 }
             }
+
+            base += fctrl.fans.len();
         }
     }
 }
@@ -169,11 +372,13 @@ impl idl::InOrderThermalImpl for ServerImpl {
         index: u8,
         pwm: u8,
     ) -> Result<(), RequestError<ThermalError>> {
-        if index < MAX_FANS {
-            let fan = Fan::from(index);
+        if self.mode != Mode::Manual {
+            return Err(ThermalError::NotInManualMode.into());
+        }
 
+        if (index as usize) < thermal::NUM_FANS {
             if pwm <= 100 {
-                match self.fctrl.set_pwm(fan, PWMDuty(pwm)) {
+                match self.set_pwm(index, pwm) {
                     Ok(_) => Ok(()),
                     Err(_) => Err(ThermalError::DeviceError.into()),
                 }
@@ -184,6 +389,57 @@ impl idl::InOrderThermalImpl for ServerImpl {
             Err(ThermalError::InvalidFan.into())
         }
     }
+
+    fn get_fan_status(
+        &mut self,
+        _: &RecvMessage,
+        index: u8,
+    ) -> Result<FanState, RequestError<ThermalError>> {
+        match self.fans.get(index as usize) {
+            Some(health) => Ok(health.state()),
+            None => Err(ThermalError::InvalidFan.into()),
+        }
+    }
+
+    fn set_mode_manual(
+        &mut self,
+        _: &RecvMessage,
+        initial_pwm: u8,
+    ) -> Result<(), RequestError<ThermalError>> {
+        if initial_pwm > 100 {
+            return Err(ThermalError::InvalidPWM.into());
+        }
+
+        self.mode = Mode::Manual;
+        ringbuf_entry!(Trace::Mode(self.mode));
+
+        let mut result = Ok(());
+
+        for index in 0..thermal::NUM_FANS as u8 {
+            if self.set_pwm(index, initial_pwm).is_err() {
+                result = Err(ThermalError::DeviceError.into());
+            }
+        }
+
+        result
+    }
+
+    fn set_mode_auto(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<(), RequestError<ThermalError>> {
+        if self.mode != Mode::Auto {
+            //
+            // We don't know what the fans were left at, so we start over
+            // from full speed.
+            //
+            self.zones = [ZoneState::INITIAL; config::NUM_ZONES];
+            self.mode = Mode::Auto;
+            ringbuf_entry!(Trace::Mode(self.mode));
+        }
+
+        Ok(())
+    }
 }
 
 impl NotificationHandler for ServerImpl {
@@ -197,14 +453,8 @@ impl NotificationHandler for ServerImpl {
 
         self.read_fans();
 
-        for s in &mut self.sensors {
-            match s.read_temp() {
-                Ok(reading) => {
-                    self.sensor.post(s.id, reading.0).unwrap();
-                }
-                Err(e) => self.sensor.nodata(s.id, e.into()).unwrap(),
-            };
-        }
+        let (zones, cpus) = self.read_temps();
+        self.control(&zones, &cpus);
     }
 }
 
@@ -212,18 +462,14 @@ impl NotificationHandler for ServerImpl {
 fn main() -> ! {
     let task = I2C.get_task_id();
 
-    cfg_if::cfg_if! {
-        if #[cfg(any(
-            target_board = "gimlet-a",
-            target_board = "gimlet-b",
-        ))] {
-            let fctrl = Max31790::new(&devices::max31790(task)[0]);
-        } else {
-            compile_error!("unknown board");
-        }
-    }
+    let fctrls = thermal::fan_controllers(task).map(|fctrl| FanController {
+        device: Max31790::new(&fctrl.device),
+        fans: fctrl.fans,
+    });
 
-    fctrl.initialize().unwrap();
+    for fctrl in &fctrls {
+        fctrl.device.initialize().unwrap();
+    }
 
     let deadline = sys_get_timer().now;
 
@@ -234,8 +480,11 @@ fn main() -> ! {
 
     let mut server = ServerImpl {
         sensor: sensor_api::Sensor::from(SENSOR.get_task_id()),
-        sensors: temperature_sensors(),
-        fctrl: fctrl,
+        sensors: thermal::inputs(task).map(|input| Sensor::from(&input)),
+        fctrls,
+        fans: [FanHealth::INITIAL; thermal::NUM_FANS],
+        mode: Mode::Auto,
+        zones: [ZoneState::INITIAL; config::NUM_ZONES],
         deadline,
     };
 
@@ -247,7 +496,7 @@ fn main() -> ! {
 }
 
 mod idl {
-    use super::ThermalError;
+    use super::{FanState, ThermalError};
 
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
 }
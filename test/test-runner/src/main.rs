@@ -61,7 +61,8 @@
 //!
 //! # Output
 //!
-//! Output is produced on ITM stimulus port 8. Output is in a line-oriented
+//! Output is produced on ITM stimulus port 8 (or via semihosting, on ARMv6-M
+//! or with the `semihosting` feature). Output is in a line-oriented
 //! human-readable format modeled after report formats like TAP, but avoiding
 //! some issues.
 //!
@@ -78,8 +79,11 @@
 //!     containing newlines) is starting, and any hangs should be blamed on it.
 //!   - `finish STATUS NAME` - indicates that test suite NAME has completed with
 //!     STATUS (which is `ok` or `FAIL`).
-//! - `done STATUS` - signals the end of the test suite. STATUS is `ok` if all
+//! - `done STATUS` - signals the end of the test suite. STATUS is `pass` if all
 //!   tests passed, `FAIL` if any failed.
+//!
+//! With the `semihosting-exit` feature, the runner then exits via semihosting
+//! (with a nonzero status if any test failed), which ends an emulator session.
 
 #![no_std]
 #![no_main]
@@ -93,7 +97,7 @@ use zerocopy::AsBytes;
 use armv6m_atomic_hack::*;
 
 cfg_if::cfg_if! {
-    if #[cfg(armv6m)] {
+    if #[cfg(any(armv6m, feature = "semihosting"))] {
         /// Helper macro for producing output by semihosting :-(
         macro_rules! test_output {
             ($s:expr) => {
@@ -132,7 +136,8 @@ static TEST_RUNS: AtomicU32 = AtomicU32::new(0);
 /// We are sensitive to all notifications, to catch unexpected ones in test.
 const ALL_NOTIFICATIONS: u32 = !0;
 
-fn test_run() {
+/// Runs the test suite once, returning `true` if every case passed.
+fn test_run() -> bool {
     // Get things rolling by restarting the test task. This ensures that it's
     // running, so that we don't depend on the `start` key in `app.toml` for
     // correctness.
@@ -238,14 +243,26 @@ fn test_run() {
     } else {
         test_output!("done FAIL");
     }
+
+    failures == 0
 }
 
 #[export_name = "main"]
 fn main() -> ! {
     loop {
-        test_run();
+        let passed = test_run();
         TEST_RUNS.fetch_add(1, Ordering::SeqCst);
 
+        #[cfg(feature = "semihosting-exit")]
+        cortex_m_semihosting::debug::exit(if passed {
+            cortex_m_semihosting::debug::EXIT_SUCCESS
+        } else {
+            cortex_m_semihosting::debug::EXIT_FAILURE
+        });
+
+        #[cfg(not(feature = "semihosting-exit"))]
+        let _ = passed;
+
         while TEST_KICK.load(Ordering::SeqCst) == 0 {
             continue;
         }